@@ -0,0 +1,404 @@
+use serde::{Deserialize, Serialize};
+use zcash_address::unified::{self, Encoding, Receiver};
+use zcash_address::{ToAddress, ZcashAddress};
+use zcash_keys::keys::UnifiedFullViewingKey;
+use zcash_transparent::address::TransparentAddress;
+use zcash_transparent::keys::{IncomingViewingKey, NonHardenedChildIndex};
+use zip32::{DiversifierIndex, Scope};
+
+use crate::network::Network;
+
+/// Upper bound on addresses derived per call
+pub const MAX_ADDRESS_COUNT: u32 = 1000;
+
+/// Unified address derived at one diversifier index
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DerivedAddress {
+    pub diversifier_index: u32,
+    pub unified_address: Option<String>,
+    /// Single-receiver unified address (Orchard has no standalone encoding)
+    pub orchard: Option<String>,
+    /// Sapling payment address (`zs1...`)
+    pub sapling: Option<String>,
+    /// Transparent P2PKH address (`t1...`)
+    pub p2pkh: Option<String>,
+    /// Why no unified address exists at this index
+    pub error: Option<String>,
+}
+
+/// Which receivers to place in derived unified addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiverSelection {
+    pub orchard: bool,
+    pub sapling: bool,
+    pub p2pkh: bool,
+}
+
+impl ReceiverSelection {
+    /// Parse receiver names ("orchard", "sapling", "p2pkh"); an empty list
+    /// means "whatever the viewing key supports" and yields `None`
+    pub fn from_names<I, S>(names: I) -> Result<Option<Self>, String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut selection = ReceiverSelection {
+            orchard: false,
+            sapling: false,
+            p2pkh: false,
+        };
+        let mut any = false;
+
+        for name in names {
+            match name.as_ref().trim().to_ascii_lowercase().as_str() {
+                "" => continue,
+                "orchard" => selection.orchard = true,
+                "sapling" => selection.sapling = true,
+                "p2pkh" | "transparent" => selection.p2pkh = true,
+                other => return Err(format!("Unknown receiver type '{}'", other)),
+            }
+            any = true;
+        }
+
+        Ok(if any { Some(selection) } else { None })
+    }
+}
+
+/// Derive the unified addresses at diversifier indices `start..start + count`
+///
+/// With no explicit selection every receiver the viewing key supports is
+/// included; an explicitly requested receiver the key lacks is an error.
+pub fn derive_addresses(
+    ufvk: &UnifiedFullViewingKey,
+    network: Network,
+    start: u32,
+    count: u32,
+    receivers: Option<ReceiverSelection>,
+) -> Result<Vec<DerivedAddress>, String> {
+    if count == 0 || count > MAX_ADDRESS_COUNT {
+        return Err(format!(
+            "Address count must be between 1 and {}",
+            MAX_ADDRESS_COUNT
+        ));
+    }
+    let end = start
+        .checked_add(count - 1)
+        .ok_or_else(|| "Diversifier index range overflows".to_string())?;
+
+    let receivers = match receivers {
+        Some(selection) => {
+            if selection.orchard && ufvk.orchard().is_none() {
+                return Err("Viewing key has no Orchard component".to_string());
+            }
+            if selection.sapling && ufvk.sapling().is_none() {
+                return Err("Viewing key has no Sapling component".to_string());
+            }
+            if selection.p2pkh && ufvk.transparent().is_none() {
+                return Err("Viewing key has no transparent component".to_string());
+            }
+            selection
+        }
+        None => ReceiverSelection {
+            orchard: ufvk.orchard().is_some(),
+            sapling: ufvk.sapling().is_some(),
+            p2pkh: ufvk.transparent().is_some(),
+        },
+    };
+
+    let orchard = ufvk.orchard().filter(|_| receivers.orchard);
+    let sapling = ufvk.sapling().filter(|_| receivers.sapling);
+    let transparent = match ufvk.transparent().filter(|_| receivers.p2pkh) {
+        Some(account) => Some(
+            account
+                .derive_external_ivk()
+                .map_err(|e| format!("Failed to derive transparent key: {:?}", e))?,
+        ),
+        None => None,
+    };
+
+    if orchard.is_none() && sapling.is_none() {
+        return Err("Unified addresses need an Orchard or Sapling receiver".to_string());
+    }
+
+    let addresses = (start..=end)
+        .map(|index| {
+            let mut derived = DerivedAddress {
+                diversifier_index: index,
+                unified_address: None,
+                orchard: None,
+                sapling: None,
+                p2pkh: None,
+                error: None,
+            };
+            let mut orchard_receiver = None;
+            let mut sapling_receiver = None;
+            let mut p2pkh_receiver = None;
+
+            if let Some(fvk) = orchard {
+                let address = fvk.address_at(index, Scope::External);
+                derived.orchard = Some(encode_orchard_address(network, &address));
+                orchard_receiver = Some(Receiver::Orchard(address.to_raw_address_bytes()));
+            }
+
+            if let Some(dfvk) = sapling {
+                match dfvk.address(DiversifierIndex::from(index)) {
+                    Some(address) => {
+                        derived.sapling =
+                            Some(ZcashAddress::from_sapling(network, address.to_bytes()).encode());
+                        sapling_receiver = Some(Receiver::Sapling(address.to_bytes()));
+                    }
+                    None => {
+                        derived.error =
+                            Some("Index is not a valid Sapling diversifier".to_string());
+                    }
+                }
+            }
+
+            if let Some(ivk) = &transparent {
+                let receiver = NonHardenedChildIndex::from_index(index)
+                    .ok_or_else(|| "Index is beyond the transparent child range".to_string())
+                    .and_then(|child| p2pkh_hash(ivk.derive_address(child)));
+                match receiver {
+                    Ok(hash) => {
+                        derived.p2pkh =
+                            Some(ZcashAddress::from_transparent_p2pkh(network, hash).encode());
+                        p2pkh_receiver = Some(Receiver::P2pkh(hash));
+                    }
+                    Err(e) => derived.error = Some(e),
+                }
+            }
+
+            // ZIP 316: receivers are ordered by typecode and every receiver of
+            // a unified address shares one index
+            let items = [p2pkh_receiver, sapling_receiver, orchard_receiver]
+                .into_iter()
+                .flatten()
+                .collect();
+            if derived.error.is_none() {
+                match unified::Address::try_from_items(items) {
+                    Ok(ua) => {
+                        derived.unified_address =
+                            Some(ZcashAddress::from_unified(network, ua).encode())
+                    }
+                    Err(e) => derived.error = Some(e.to_string()),
+                }
+            }
+
+            derived
+        })
+        .collect();
+
+    Ok(addresses)
+}
+
+/// P2PKH addresses at child indices `0..count` of the viewing key's external
+/// chain, then of its internal (change) chain, or `None` if the key has no
+/// transparent item
+///
+/// Children whose key can't be derived have no address and are skipped.
+pub fn transparent_addresses(
+    ufvk: &UnifiedFullViewingKey,
+    network: Network,
+    count: u32,
+) -> Result<Option<Vec<String>>, String> {
+    let Some(account) = ufvk.transparent() else {
+        return Ok(None);
+    };
+    let external = account
+        .derive_external_ivk()
+        .map_err(|e| format!("Failed to derive transparent key: {:?}", e))?;
+    let internal = account
+        .derive_internal_ivk()
+        .map_err(|e| format!("Failed to derive transparent key: {:?}", e))?;
+
+    let children = (0..count).filter_map(NonHardenedChildIndex::from_index);
+    let external = children
+        .clone()
+        .filter_map(|child| p2pkh_hash(external.derive_address(child)).ok());
+    let internal = children.filter_map(|child| p2pkh_hash(internal.derive_address(child)).ok());
+
+    Ok(Some(
+        external
+            .chain(internal)
+            .map(|hash| ZcashAddress::from_transparent_p2pkh(network, hash).encode())
+            .collect(),
+    ))
+}
+
+/// The P2PKH receiver of a derived transparent address; a child key that
+/// can't be derived leaves the index without a unified address rather than
+/// silently dropping the receiver
+fn p2pkh_hash<E: std::fmt::Debug>(
+    derived: Result<TransparentAddress, E>,
+) -> Result<[u8; 20], String> {
+    match derived {
+        Ok(TransparentAddress::PublicKeyHash(hash)) => Ok(hash),
+        Ok(TransparentAddress::ScriptHash(_)) => {
+            Err("Transparent key derived a P2SH address".to_string())
+        }
+        Err(e) => Err(format!("Failed to derive transparent receiver: {:?}", e)),
+    }
+}
+
+/// Orchard receivers have no standalone encoding, so wrap them in a
+/// single-receiver unified address
+pub(crate) fn encode_orchard_address(network: Network, address: &orchard::Address) -> String {
+    let items = vec![Receiver::Orchard(address.to_raw_address_bytes())];
+    match unified::Address::try_from_items(items) {
+        Ok(ua) => ZcashAddress::from_unified(network, ua).encode(),
+        Err(_) => hex::encode(address.to_raw_address_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::parse_ufvk;
+    use crate::test_vectors::{
+        ORCHARD_ADDRESS_0, ORCHARD_ADDRESS_1, P2PKH_EXTERNAL, P2PKH_INTERNAL_5, SAPLING_ADDRESS_1,
+        SAPLING_ADDRESS_1_TESTNET, SAPLING_ADDRESS_2, UFVK, UFVK_TESTNET, UFVK_TRANSPARENT,
+        UNIFIED_ADDRESS_1, UNIFIED_ADDRESS_1_TESTNET, UNIFIED_ADDRESS_1_TRANSPARENT,
+    };
+
+    fn derive(
+        key: &str,
+        start: u32,
+        count: u32,
+        receivers: &str,
+    ) -> Result<Vec<DerivedAddress>, String> {
+        let (network, ufvk) = parse_ufvk(key).unwrap();
+        let selection = ReceiverSelection::from_names(receivers.split(','))?;
+        derive_addresses(&ufvk, network, start, count, selection)
+    }
+
+    #[test]
+    fn derives_known_addresses() {
+        let addresses = derive(UFVK, 0, 3, "").unwrap();
+        let indices: Vec<u32> = addresses.iter().map(|a| a.diversifier_index).collect();
+        assert_eq!(indices, [0, 1, 2]);
+
+        // Index 0 has an Orchard receiver but no Sapling one
+        let first = &addresses[0];
+        assert_eq!(first.orchard.as_deref(), Some(ORCHARD_ADDRESS_0));
+        assert_eq!((&first.sapling, &first.unified_address), (&None, &None));
+        assert_eq!(
+            first.error.as_deref(),
+            Some("Index is not a valid Sapling diversifier")
+        );
+
+        let second = &addresses[1];
+        assert_eq!(second.unified_address.as_deref(), Some(UNIFIED_ADDRESS_1));
+        assert_eq!(second.sapling.as_deref(), Some(SAPLING_ADDRESS_1));
+        assert_eq!(second.orchard.as_deref(), Some(ORCHARD_ADDRESS_1));
+        assert_eq!((&second.p2pkh, &second.error), (&None, &None));
+
+        assert_eq!(addresses[2].sapling.as_deref(), Some(SAPLING_ADDRESS_2));
+    }
+
+    #[test]
+    fn includes_transparent_receivers() {
+        let addresses = derive(UFVK_TRANSPARENT, 1, 1, "").unwrap();
+        assert_eq!(
+            addresses[0].unified_address.as_deref(),
+            Some(UNIFIED_ADDRESS_1_TRANSPARENT)
+        );
+        assert_eq!(addresses[0].p2pkh.as_deref(), Some(P2PKH_EXTERNAL[1]));
+
+        // Leaving the transparent receiver out gives the shielded-only address
+        let addresses = derive(UFVK_TRANSPARENT, 1, 1, "orchard,sapling").unwrap();
+        assert_eq!(
+            addresses[0].unified_address.as_deref(),
+            Some(UNIFIED_ADDRESS_1)
+        );
+        assert_eq!(addresses[0].p2pkh, None);
+    }
+
+    #[test]
+    fn selected_receivers_only() {
+        // An Orchard-only unified address exists at every index
+        let addresses = derive(UFVK, 0, 1, "orchard").unwrap();
+        assert_eq!(
+            addresses[0].unified_address.as_deref(),
+            Some(ORCHARD_ADDRESS_0)
+        );
+        assert_eq!((&addresses[0].sapling, &addresses[0].error), (&None, &None));
+
+        let error = derive(UFVK, 0, 1, "p2pkh").unwrap_err();
+        assert_eq!(error, "Viewing key has no transparent component");
+
+        let error = derive(UFVK_TRANSPARENT, 0, 1, "p2pkh").unwrap_err();
+        assert_eq!(
+            error,
+            "Unified addresses need an Orchard or Sapling receiver"
+        );
+
+        let error = derive(UFVK, 0, 1, "orchard,bogus").unwrap_err();
+        assert_eq!(error, "Unknown receiver type 'bogus'");
+    }
+
+    #[test]
+    fn encodes_for_the_key_network() {
+        let addresses = derive(UFVK_TESTNET, 1, 1, "").unwrap();
+        assert_eq!(
+            addresses[0].unified_address.as_deref(),
+            Some(UNIFIED_ADDRESS_1_TESTNET)
+        );
+        assert_eq!(
+            addresses[0].sapling.as_deref(),
+            Some(SAPLING_ADDRESS_1_TESTNET)
+        );
+    }
+
+    #[test]
+    fn transparent_derivation_failures_are_reported() {
+        let hash = [0x22; 20];
+        assert_eq!(
+            p2pkh_hash::<&str>(Ok(TransparentAddress::PublicKeyHash(hash))),
+            Ok(hash)
+        );
+
+        let error = p2pkh_hash(Err("invalid child key")).unwrap_err();
+        assert_eq!(
+            error,
+            "Failed to derive transparent receiver: \"invalid child key\""
+        );
+
+        let error = p2pkh_hash::<&str>(Ok(TransparentAddress::ScriptHash(hash))).unwrap_err();
+        assert_eq!(error, "Transparent key derived a P2SH address");
+    }
+
+    #[test]
+    fn indices_beyond_the_transparent_range_have_no_unified_address() {
+        // Shielded receivers exist there, but every receiver of a unified
+        // address must share its index
+        let index = 1 << 31;
+        let addresses = derive(UFVK_TRANSPARENT, index, 1, "").unwrap();
+        assert_eq!(addresses[0].p2pkh, None);
+        assert_eq!(addresses[0].unified_address, None);
+        assert_eq!(
+            addresses[0].error.as_deref(),
+            Some("Index is beyond the transparent child range")
+        );
+    }
+
+    #[test]
+    fn transparent_addresses_cover_both_chains() {
+        let (network, ufvk) = parse_ufvk(UFVK_TRANSPARENT).unwrap();
+        let addresses = transparent_addresses(&ufvk, network, 6).unwrap().unwrap();
+        assert_eq!(addresses.len(), 12);
+        assert_eq!(addresses[..3], P2PKH_EXTERNAL);
+        assert_eq!(addresses[6 + 5], P2PKH_INTERNAL_5);
+
+        let (network, ufvk) = parse_ufvk(UFVK).unwrap();
+        assert_eq!(transparent_addresses(&ufvk, network, 6), Ok(None));
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        assert!(derive(UFVK, 0, 0, "").is_err());
+        assert!(derive(UFVK, 0, MAX_ADDRESS_COUNT + 1, "").is_err());
+
+        let error = derive(UFVK, u32::MAX, 2, "").unwrap_err();
+        assert_eq!(error, "Diversifier index range overflows");
+    }
+}
@@ -0,0 +1,153 @@
+use sha2::{Digest, Sha256};
+
+use crate::network::Network;
+use crate::transaction::{decode_transaction_prefix, DecodedTransaction};
+use crate::txid::compute_txid;
+
+/// Header fields before the Equihash solution
+const HEADER_PREFIX_SIZE: usize = 140;
+
+/// A full block split into its transactions
+#[derive(Debug, Clone)]
+pub struct DecodedBlock {
+    /// Block hash in internal byte order
+    pub hash: [u8; 32],
+    /// Parent block hash in internal byte order
+    pub prev_hash: [u8; 32],
+    /// Unix time from the header
+    pub time: u32,
+    pub transactions: Vec<BlockTransaction>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockTransaction {
+    /// Transaction ID in display order
+    pub txid: String,
+    pub tx: DecodedTransaction,
+}
+
+/// Decode a serialized block as returned by `getblock <hash|height> 0`
+pub fn decode_block(bytes: &[u8], network: Network) -> Result<DecodedBlock, String> {
+    if bytes.len() < HEADER_PREFIX_SIZE {
+        return Err(format!(
+            "Block truncated: header needs {} bytes, got {}",
+            HEADER_PREFIX_SIZE,
+            bytes.len()
+        ));
+    }
+
+    let mut prev_hash = [0u8; 32];
+    prev_hash.copy_from_slice(&bytes[4..36]);
+    let time = u32::from_le_bytes(bytes[100..104].try_into().unwrap());
+
+    let (solution_size, offset) = compact_size(bytes, HEADER_PREFIX_SIZE)?;
+    let header_end = offset
+        .checked_add(solution_size)
+        .filter(|&end| end <= bytes.len())
+        .ok_or_else(|| "Block truncated in the Equihash solution".to_string())?;
+    let hash: [u8; 32] = Sha256::digest(Sha256::digest(&bytes[..header_end])).into();
+
+    let (tx_count, mut offset) = compact_size(bytes, header_end)?;
+    let mut transactions = Vec::new();
+    for index in 0..tx_count {
+        let tx = decode_transaction_prefix(&bytes[offset..], network)
+            .map_err(|e| format!("Transaction {} of block: {}", index, e))?;
+        let txid = compute_txid(&bytes[offset..offset + tx.size])?;
+        offset += tx.size;
+        transactions.push(BlockTransaction { txid, tx });
+    }
+
+    if offset != bytes.len() {
+        return Err(format!(
+            "Block has {} unexpected trailing bytes",
+            bytes.len() - offset
+        ));
+    }
+
+    Ok(DecodedBlock {
+        hash,
+        prev_hash,
+        time,
+        transactions,
+    })
+}
+
+/// Read a CompactSize at `offset`, returning it and the offset after it
+fn compact_size(bytes: &[u8], offset: usize) -> Result<(usize, usize), String> {
+    let truncated = || format!("Block truncated at offset {}", offset);
+    let width = match *bytes.get(offset).ok_or_else(truncated)? {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        n => return Ok((n as usize, offset + 1)),
+    };
+
+    let field = bytes
+        .get(offset + 1..offset + 1 + width)
+        .ok_or_else(truncated)?;
+    let mut value = [0u8; 8];
+    value[..width].copy_from_slice(field);
+    let value = usize::try_from(u64::from_le_bytes(value))
+        .map_err(|_| format!("CompactSize at offset {} is too large", offset))?;
+    Ok((value, offset + 1 + width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{
+        GENESIS_COINBASE, GENESIS_COINBASE_TXID, V5_TRANSPARENT, V5_TRANSPARENT_TXID,
+    };
+
+    /// Block with a made-up header, an empty Equihash solution, and the
+    /// genesis coinbase followed by a v5 transaction
+    fn block() -> Vec<u8> {
+        let mut bytes = hex::decode("04000000").unwrap();
+        bytes.extend([0xab; 32]);
+        // Merkle root and block commitments
+        bytes.extend([0u8; 64]);
+        bytes.extend(1_700_000_000u32.to_le_bytes());
+        bytes.extend(hex::decode("ffff071f").unwrap());
+        bytes.extend([0x5a; 32]);
+        bytes.push(0x00);
+
+        bytes.push(0x02);
+        bytes.extend(hex::decode(GENESIS_COINBASE).unwrap());
+        bytes.extend(hex::decode(V5_TRANSPARENT).unwrap());
+        bytes
+    }
+
+    #[test]
+    fn splits_block_into_transactions() {
+        let block = decode_block(&block(), Network::Main).unwrap();
+
+        let mut hash = block.hash;
+        hash.reverse();
+        assert_eq!(
+            hex::encode(hash),
+            "742a3cd73de99d0b186fdd1b009af3d4e2d5812eaa79232c71b0d558335c7368"
+        );
+        assert_eq!(block.prev_hash, [0xab; 32]);
+        assert_eq!(block.time, 1_700_000_000);
+
+        let txids: Vec<&str> = block.transactions.iter().map(|t| t.txid.as_str()).collect();
+        assert_eq!(txids, [GENESIS_COINBASE_TXID, V5_TRANSPARENT_TXID]);
+        assert_eq!(block.transactions[1].tx.header.version, 5);
+    }
+
+    #[test]
+    fn rejects_truncated_or_padded_blocks() {
+        let bytes = block();
+
+        let error = decode_block(&bytes[..100], Network::Main).unwrap_err();
+        assert!(error.starts_with("Block truncated"), "{}", error);
+
+        let error = decode_block(&bytes[..bytes.len() - 1], Network::Main).unwrap_err();
+        assert!(error.starts_with("Transaction 1 of block"), "{}", error);
+
+        let mut padded = bytes;
+        padded.push(0);
+        let error = decode_block(&padded, Network::Main).unwrap_err();
+        assert_eq!(error, "Block has 1 unexpected trailing bytes");
+    }
+}
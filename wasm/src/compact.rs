@@ -0,0 +1,376 @@
+use orchard::keys::{
+    FullViewingKey as OrchardFvk, PreparedIncomingViewingKey as OrchardPreparedIvk,
+};
+use orchard::note::{ExtractedNoteCommitment as OrchardCmx, Nullifier as OrchardNullifier};
+use orchard::note_encryption::{CompactAction, OrchardDomain};
+use sapling_crypto::keys::NullifierDerivingKey;
+use sapling_crypto::note::ExtractedNoteCommitment as SaplingCmu;
+use sapling_crypto::note_encryption::{
+    try_sapling_compact_note_decryption, CompactOutputDescription, PreparedIncomingViewingKey,
+};
+use serde::{Deserialize, Serialize};
+use zcash_keys::keys::UnifiedFullViewingKey;
+use zcash_note_encryption::{try_compact_note_decryption, EphemeralKeyBytes, COMPACT_NOTE_SIZE};
+use zcash_primitives::transaction::components::sapling::zip212_enforcement;
+use zcash_protocol::consensus::BlockHeight;
+use zip32::Scope;
+
+use crate::decrypt::scope_name;
+use crate::network::NetworkParams;
+
+/// Shielded parts of a transaction as carried in a lightwalletd compact block
+#[derive(Debug, Clone, Default)]
+pub struct CompactTransaction {
+    pub sapling_outputs: Vec<CompactSaplingOutput>,
+    pub orchard_actions: Vec<CompactOrchardAction>,
+    /// Position of the first Sapling output in the note commitment tree;
+    /// Sapling nullifiers can't be derived without it
+    pub sapling_tree_position: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompactSaplingOutput {
+    pub cmu: [u8; 32],
+    pub ephemeral_key: [u8; 32],
+    /// First 52 bytes of the note ciphertext
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompactOrchardAction {
+    pub nullifier: [u8; 32],
+    pub cmx: [u8; 32],
+    pub ephemeral_key: [u8; 32],
+    /// First 52 bytes of the note ciphertext
+    pub ciphertext: Vec<u8>,
+}
+
+/// A note detected by compact trial decryption; memos need the full
+/// transaction
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompactNote {
+    /// "sapling" or "orchard"
+    pub pool: String,
+    /// Output or action index within the transaction
+    pub index: usize,
+    /// "external" or "internal"
+    pub scope: String,
+    /// Note value in zatoshis
+    pub value: u64,
+    /// Hex nullifier a transaction spending the note will reveal, in
+    /// serialized byte order; unknown for Sapling notes without a tree
+    /// position
+    pub nullifier: Option<String>,
+}
+
+/// Incoming viewing and nullifier keys of a UFVK, prepared once for scanning
+/// many blocks
+pub struct ScanningKeys {
+    params: NetworkParams,
+    sapling: Vec<(Scope, PreparedIncomingViewingKey, NullifierDerivingKey)>,
+    orchard: Vec<(Scope, OrchardPreparedIvk)>,
+    /// Derives Orchard nullifiers, which are the same for both scopes
+    orchard_fvk: Option<OrchardFvk>,
+}
+
+impl ScanningKeys {
+    pub fn new(ufvk: &UnifiedFullViewingKey, params: NetworkParams) -> Result<Self, String> {
+        let scopes = [Scope::External, Scope::Internal];
+
+        let sapling = ufvk
+            .sapling()
+            .map(|dfvk| {
+                scopes
+                    .iter()
+                    .map(|&scope| {
+                        (
+                            scope,
+                            PreparedIncomingViewingKey::new(&dfvk.to_ivk(scope)),
+                            dfvk.to_nk(scope),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let orchard = ufvk
+            .orchard()
+            .map(|fvk| {
+                scopes
+                    .iter()
+                    .map(|&scope| (scope, OrchardPreparedIvk::new(&fvk.to_ivk(scope))))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let keys = ScanningKeys {
+            params,
+            sapling,
+            orchard,
+            orchard_fvk: ufvk.orchard().cloned(),
+        };
+        if keys.sapling.is_empty() && keys.orchard.is_empty() {
+            return Err("Viewing key has no Sapling or Orchard component".to_string());
+        }
+        Ok(keys)
+    }
+
+    /// Trial-decrypt every compact output and action of a transaction mined
+    /// at `height`
+    ///
+    /// Malformed outputs (bad lengths or non-canonical encodings) are
+    /// skipped, as no key can decrypt them. Each output still takes its
+    /// place in the Sapling tree, so positions count every output.
+    pub fn scan_transaction(&self, height: u32, tx: &CompactTransaction) -> Vec<CompactNote> {
+        let mut notes = Vec::new();
+        let zip212 = zip212_enforcement(&self.params, BlockHeight::from_u32(height));
+
+        for (index, output) in tx.sapling_outputs.iter().enumerate() {
+            let output = match sapling_output(output) {
+                Some(output) => output,
+                None => continue,
+            };
+            let position = tx.sapling_tree_position.map(|first| first + index as u64);
+            for (scope, ivk, nk) in &self.sapling {
+                if let Some((note, _)) = try_sapling_compact_note_decryption(ivk, &output, zip212) {
+                    notes.push(CompactNote {
+                        pool: "sapling".to_string(),
+                        index,
+                        scope: scope_name(*scope).to_string(),
+                        value: note.value().inner(),
+                        nullifier: position.map(|position| hex::encode(note.nf(nk, position).0)),
+                    });
+                    break;
+                }
+            }
+        }
+
+        for (index, action) in tx.orchard_actions.iter().enumerate() {
+            let action = match orchard_action(action) {
+                Some(action) => action,
+                None => continue,
+            };
+            let domain = OrchardDomain::for_compact_action(&action);
+            for (scope, ivk) in &self.orchard {
+                if let Some((note, _)) = try_compact_note_decryption(&domain, ivk, &action) {
+                    notes.push(CompactNote {
+                        pool: "orchard".to_string(),
+                        index,
+                        scope: scope_name(*scope).to_string(),
+                        value: note.value().inner(),
+                        nullifier: self
+                            .orchard_fvk
+                            .as_ref()
+                            .map(|fvk| hex::encode(note.nullifier(fvk).to_bytes())),
+                    });
+                    break;
+                }
+            }
+        }
+
+        notes
+    }
+}
+
+fn sapling_output(output: &CompactSaplingOutput) -> Option<CompactOutputDescription> {
+    Some(CompactOutputDescription {
+        ephemeral_key: EphemeralKeyBytes(output.ephemeral_key),
+        cmu: Option::from(SaplingCmu::from_bytes(&output.cmu))?,
+        enc_ciphertext: compact_ciphertext(&output.ciphertext)?,
+    })
+}
+
+fn orchard_action(action: &CompactOrchardAction) -> Option<CompactAction> {
+    Some(CompactAction::from_parts(
+        Option::from(OrchardNullifier::from_bytes(&action.nullifier))?,
+        Option::from(OrchardCmx::from_bytes(&action.cmx))?,
+        EphemeralKeyBytes(action.ephemeral_key),
+        compact_ciphertext(&action.ciphertext)?,
+    ))
+}
+
+fn compact_ciphertext(ciphertext: &[u8]) -> Option<[u8; COMPACT_NOTE_SIZE]> {
+    ciphertext.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::parse_ufvk;
+    use crate::network::Network;
+    use crate::test_vectors::UFVK;
+    use orchard::note_encryption::OrchardNoteEncryption;
+    use rand_core::OsRng;
+    use sapling_crypto::note_encryption::{sapling_note_encryption, SaplingDomain};
+    use zcash_note_encryption::Domain;
+    use zip32::DiversifierIndex;
+
+    /// Well after Canopy, so plaintexts follow ZIP 212
+    const HEIGHT: u32 = 2_700_000;
+
+    fn ufvk() -> UnifiedFullViewingKey {
+        parse_ufvk(UFVK).unwrap().1
+    }
+
+    fn keys() -> ScanningKeys {
+        ScanningKeys::new(&ufvk(), NetworkParams::new(Network::Main)).unwrap()
+    }
+
+    /// Compact Sapling output paying `value` to `recipient`, with the note
+    fn sapling_output(
+        recipient: sapling_crypto::PaymentAddress,
+        value: u64,
+    ) -> (CompactSaplingOutput, sapling_crypto::Note) {
+        let note = sapling_crypto::Note::from_parts(
+            recipient,
+            sapling_crypto::value::NoteValue::from_raw(value),
+            sapling_crypto::Rseed::AfterZip212([7; 32]),
+        );
+        let encryptor = sapling_note_encryption(None, note.clone(), [0; 512], &mut OsRng);
+        let output = CompactSaplingOutput {
+            cmu: note.cmu().to_bytes(),
+            ephemeral_key: SaplingDomain::epk_bytes(encryptor.epk()).0,
+            ciphertext: encryptor.encrypt_note_plaintext()[..COMPACT_NOTE_SIZE].to_vec(),
+        };
+        (output, note)
+    }
+
+    /// Compact Orchard action spending the note with nullifier `spends` and
+    /// creating one for `recipient`, with the new note
+    fn orchard_action(
+        spends: [u8; 32],
+        recipient: orchard::Address,
+        value: u64,
+    ) -> (CompactOrchardAction, orchard::Note) {
+        // The action's nullifier is the new note's rho
+        let rho = orchard::note::Rho::from_bytes(&spends).unwrap();
+        let rseed = orchard::note::RandomSeed::from_bytes([7; 32], &rho).unwrap();
+        let note = orchard::Note::from_parts(
+            recipient,
+            orchard::value::NoteValue::from_raw(value),
+            rho,
+            rseed,
+            orchard::NoteVersion::V2,
+        )
+        .unwrap();
+        let cmx = OrchardCmx::from(note.commitment());
+        let encryptor = OrchardNoteEncryption::new(None, note, [0; 512]);
+        let action = CompactOrchardAction {
+            nullifier: spends,
+            cmx: cmx.to_bytes(),
+            ephemeral_key: OrchardDomain::epk_bytes(encryptor.epk()).0,
+            ciphertext: encryptor.encrypt_note_plaintext()[..COMPACT_NOTE_SIZE].to_vec(),
+        };
+        (action, note)
+    }
+
+    fn summary(notes: &[CompactNote]) -> Vec<(&str, usize, &str, u64)> {
+        notes
+            .iter()
+            .map(|n| (n.pool.as_str(), n.index, n.scope.as_str(), n.value))
+            .collect()
+    }
+
+    #[test]
+    fn finds_incoming_notes_with_their_nullifiers() {
+        let ufvk = ufvk();
+        let dfvk = ufvk.sapling().unwrap();
+        let fvk = ufvk.orchard().unwrap();
+        let (sapling, sapling_note) =
+            sapling_output(dfvk.address(DiversifierIndex::from(1u32)).unwrap(), 150_000);
+        let (orchard, orchard_note) =
+            orchard_action([0; 32], fvk.address_at(1u32, Scope::External), 2_500_000);
+        let tx = CompactTransaction {
+            sapling_outputs: vec![sapling],
+            orchard_actions: vec![orchard],
+            sapling_tree_position: Some(1000),
+        };
+
+        let notes = keys().scan_transaction(HEIGHT, &tx);
+        assert_eq!(
+            summary(&notes),
+            [
+                ("sapling", 0, "external", 150_000),
+                ("orchard", 0, "external", 2_500_000)
+            ]
+        );
+        assert_eq!(
+            notes[0].nullifier.as_deref(),
+            Some("c98ab227b038c34dae82a9dae657748bab901c88f856abeb8f7953de561feaed")
+        );
+        assert_eq!(
+            notes[0].nullifier,
+            Some(hex::encode(
+                sapling_note.nf(&dfvk.to_nk(Scope::External), 1000).0
+            ))
+        );
+        assert_eq!(
+            notes[1].nullifier.as_deref(),
+            Some("d4943204321c0d096803510f08f2352b3dca08f2f069b18cb1697afca0b40f32")
+        );
+        assert_eq!(
+            notes[1].nullifier,
+            Some(hex::encode(orchard_note.nullifier(fvk).to_bytes()))
+        );
+    }
+
+    #[test]
+    fn change_is_found_with_the_internal_scope() {
+        let ufvk = ufvk();
+        let (stranger, _) = sapling_output(
+            sapling_crypto::zip32::ExtendedSpendingKey::master(&[1; 32])
+                .default_address()
+                .1,
+            10_000,
+        );
+        let (_, change) = ufvk.sapling().unwrap().change_address();
+        let (sapling, _) = sapling_output(change, 40_000);
+        let orchard_change = ufvk.orchard().unwrap().address_at(0u32, Scope::Internal);
+        let (orchard, _) = orchard_action([0; 32], orchard_change, 60_000);
+        let tx = CompactTransaction {
+            sapling_outputs: vec![stranger, sapling],
+            orchard_actions: vec![orchard],
+            sapling_tree_position: None,
+        };
+
+        let notes = keys().scan_transaction(HEIGHT, &tx);
+        assert_eq!(
+            summary(&notes),
+            [
+                ("sapling", 1, "internal", 40_000),
+                ("orchard", 0, "internal", 60_000)
+            ]
+        );
+        // Without a tree position the Sapling nullifier can't be derived
+        assert_eq!(notes[0].nullifier, None);
+        assert!(notes[1].nullifier.is_some());
+    }
+
+    #[test]
+    fn spends_reveal_the_reported_nullifier() {
+        let ufvk = ufvk();
+        let fvk = ufvk.orchard().unwrap();
+        let keys = keys();
+
+        let (received, _) = orchard_action([0; 32], fvk.address_at(1u32, Scope::External), 100_000);
+        let receive = CompactTransaction {
+            orchard_actions: vec![received],
+            ..Default::default()
+        };
+        let found = keys.scan_transaction(HEIGHT, &receive);
+        let nullifier = found[0].nullifier.clone().unwrap();
+
+        // Spending the note reveals its nullifier and sends the rest back
+        // as change
+        let spent: [u8; 32] = hex::decode(&nullifier).unwrap().try_into().unwrap();
+        let (spend, _) = orchard_action(spent, fvk.address_at(0u32, Scope::Internal), 90_000);
+        assert_eq!(hex::encode(spend.nullifier), nullifier);
+        let spend = CompactTransaction {
+            orchard_actions: vec![spend],
+            ..Default::default()
+        };
+        let change = keys.scan_transaction(HEIGHT, &spend);
+        assert_eq!(summary(&change), [("orchard", 0, "internal", 90_000)]);
+        assert_ne!(change[0].nullifier.as_deref(), Some(nullifier.as_str()));
+    }
+}
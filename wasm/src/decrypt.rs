@@ -0,0 +1,490 @@
+use crate::address::encode_orchard_address;
+use crate::memo::{parse_memo, TypedMemo};
+use crate::network::{Network, NetworkParams};
+use sapling_crypto::note_encryption::{
+    try_sapling_note_decryption, try_sapling_output_recovery, PreparedIncomingViewingKey,
+    Zip212Enforcement,
+};
+use serde::{Deserialize, Serialize};
+use zcash_address::{ToAddress, ZcashAddress};
+use zcash_keys::keys::UnifiedFullViewingKey;
+use zcash_primitives::transaction::components::sapling::zip212_enforcement as sapling_zip212_enforcement;
+use zcash_primitives::transaction::{Transaction, TxVersion};
+use zcash_protocol::consensus::{BlockHeight, BranchId};
+use zip32::Scope;
+
+/// A shielded note recovered from a transaction with a viewing key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DecryptedNote {
+    /// Shielded pool the note belongs to ("sapling" or "orchard")
+    pub pool: String,
+    /// Sapling output index or Orchard action index within its bundle
+    pub index: usize,
+    /// "incoming" when recovered with an IVK (the key received the note),
+    /// "outgoing" when recovered with an OVK (the key sent the note)
+    pub direction: String,
+    /// Key scope that decrypted the note ("external" or "internal")
+    pub scope: String,
+    /// Note value in zatoshis
+    pub value: u64,
+    /// Encoded address the note was sent to
+    pub recipient: String,
+    pub memo: TypedMemo,
+}
+
+/// Parse a raw transaction, mined at `height` if known
+///
+/// v5 transactions carry their own consensus branch ID; for v4 the branch
+/// only affects signature hashing, so without a height any post-Sapling
+/// branch parses them.
+pub fn read_transaction(
+    params: &NetworkParams,
+    tx_bytes: &[u8],
+    height: Option<u32>,
+) -> Result<Transaction, String> {
+    let branch_id = height.map_or(BranchId::Nu5, |height| params.branch_id(height));
+
+    Transaction::read(tx_bytes, branch_id)
+        .map_err(|e| format!("Failed to parse transaction: {}", e))
+}
+
+/// Trial-decrypt every Sapling output and Orchard action of a transaction
+/// with both scopes of the UFVK's incoming viewing keys
+///
+/// Outputs that no IVK opens are then tried with the outgoing viewing keys,
+/// recovering notes the key holder sent to someone else. `height` is the
+/// block the transaction was mined in, when known; it selects the consensus
+/// branch and ZIP-212 rules of `network` at that point.
+pub fn decrypt_transaction(
+    ufvk: &UnifiedFullViewingKey,
+    network: Network,
+    tx_bytes: &[u8],
+    height: Option<u32>,
+) -> Result<Vec<DecryptedNote>, String> {
+    let params = NetworkParams::new(network);
+    let tx = read_transaction(&params, tx_bytes, height)?;
+
+    if ufvk.sapling().is_none() && ufvk.orchard().is_none() {
+        return Err("Viewing key has no Sapling or Orchard component".to_string());
+    }
+
+    let mut notes = Vec::new();
+    decrypt_sapling(ufvk, &params, &tx, height, &mut notes);
+    decrypt_orchard(ufvk, network, &tx, &mut notes);
+
+    notes.sort_by(|a, b| (&a.pool, a.index).cmp(&(&b.pool, b.index)));
+    Ok(notes)
+}
+
+fn decrypt_sapling(
+    ufvk: &UnifiedFullViewingKey,
+    params: &NetworkParams,
+    tx: &Transaction,
+    height: Option<u32>,
+    notes: &mut Vec<DecryptedNote>,
+) {
+    let (dfvk, bundle) = match (ufvk.sapling(), tx.sapling_bundle()) {
+        (Some(dfvk), Some(bundle)) => (dfvk, bundle),
+        _ => return,
+    };
+
+    let network = params.network();
+    let zip212_enforcement = match height {
+        Some(height) => sapling_zip212_enforcement(params, BlockHeight::from_u32(height)),
+        None => zip212_enforcement(tx.version()),
+    };
+    let outputs = bundle.shielded_outputs();
+    let mut recovered = vec![false; outputs.len()];
+
+    for scope in [Scope::External, Scope::Internal] {
+        let ivk = PreparedIncomingViewingKey::new(&dfvk.to_ivk(scope));
+
+        for (index, output) in outputs.iter().enumerate() {
+            if recovered[index] {
+                continue;
+            }
+            if let Some((note, address, memo)) =
+                try_sapling_note_decryption(&ivk, output, zip212_enforcement)
+            {
+                recovered[index] = true;
+                notes.push(DecryptedNote {
+                    pool: "sapling".to_string(),
+                    index,
+                    direction: "incoming".to_string(),
+                    scope: scope_name(scope).to_string(),
+                    value: note.value().inner(),
+                    recipient: ZcashAddress::from_sapling(network, address.to_bytes()).encode(),
+                    memo: parse_memo(&memo),
+                });
+            }
+        }
+    }
+
+    for scope in [Scope::External, Scope::Internal] {
+        let ovk = dfvk.to_ovk(scope);
+
+        for (index, output) in outputs.iter().enumerate() {
+            if recovered[index] {
+                continue;
+            }
+            if let Some((note, address, memo)) =
+                try_sapling_output_recovery(&ovk, output, zip212_enforcement)
+            {
+                recovered[index] = true;
+                notes.push(DecryptedNote {
+                    pool: "sapling".to_string(),
+                    index,
+                    direction: "outgoing".to_string(),
+                    scope: scope_name(scope).to_string(),
+                    value: note.value().inner(),
+                    recipient: ZcashAddress::from_sapling(network, address.to_bytes()).encode(),
+                    memo: parse_memo(&memo),
+                });
+            }
+        }
+    }
+}
+
+fn decrypt_orchard(
+    ufvk: &UnifiedFullViewingKey,
+    network: Network,
+    tx: &Transaction,
+    notes: &mut Vec<DecryptedNote>,
+) {
+    let (fvk, bundle) = match (ufvk.orchard(), tx.orchard_bundle()) {
+        (Some(fvk), Some(bundle)) => (fvk, bundle),
+        _ => return,
+    };
+
+    let mut recovered = vec![false; bundle.actions().len()];
+
+    for scope in [Scope::External, Scope::Internal] {
+        let ivk = fvk.to_ivk(scope);
+
+        for (index, _, note, address, memo) in bundle.decrypt_outputs_with_keys(&[ivk]) {
+            if recovered[index] {
+                continue;
+            }
+            recovered[index] = true;
+            notes.push(DecryptedNote {
+                pool: "orchard".to_string(),
+                index,
+                direction: "incoming".to_string(),
+                scope: scope_name(scope).to_string(),
+                value: note.value().inner(),
+                recipient: encode_orchard_address(network, &address),
+                memo: parse_memo(&memo),
+            });
+        }
+    }
+
+    for scope in [Scope::External, Scope::Internal] {
+        let ovk = fvk.to_ovk(scope);
+
+        for (index, _, note, address, memo) in bundle.recover_outputs_with_ovks(&[ovk]) {
+            if recovered[index] {
+                continue;
+            }
+            recovered[index] = true;
+            notes.push(DecryptedNote {
+                pool: "orchard".to_string(),
+                index,
+                direction: "outgoing".to_string(),
+                scope: scope_name(scope).to_string(),
+                value: note.value().inner(),
+                recipient: encode_orchard_address(network, &address),
+                memo: parse_memo(&memo),
+            });
+        }
+    }
+}
+
+/// Without a mined height we can't place the transaction relative to the
+/// ZIP-212 grace period. Every v5 transaction postdates it; v4 transactions
+/// may fall on either side, so accept both plaintext lead bytes.
+fn zip212_enforcement(version: TxVersion) -> Zip212Enforcement {
+    match version {
+        TxVersion::V5 => Zip212Enforcement::On,
+        _ => Zip212Enforcement::GracePeriod,
+    }
+}
+
+pub(crate) fn scope_name(scope: Scope) -> &'static str {
+    match scope {
+        Scope::External => "external",
+        Scope::Internal => "internal",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::parse_ufvk;
+    use crate::memo::MEMO_SIZE;
+    use crate::test_vectors::{ORCHARD_ADDRESS_1, SAPLING_ADDRESS_1, UFVK};
+    use orchard::note_encryption::{OrchardDomain, OrchardNoteEncryption};
+    use rand_core::OsRng;
+    use sapling_crypto::note_encryption::{sapling_note_encryption, SaplingDomain};
+    use zcash_note_encryption::Domain;
+    use zip32::DiversifierIndex;
+
+    /// v5 header word, NU5 version group and branch, zero lock time and
+    /// expiry height
+    const V5_HEADER: &str = "050000800a27a726b4d0d6c20000000000000000";
+
+    fn ufvk() -> UnifiedFullViewingKey {
+        parse_ufvk(UFVK).unwrap().1
+    }
+
+    fn memo(text: &str) -> [u8; MEMO_SIZE] {
+        let mut memo = [0u8; MEMO_SIZE];
+        memo[..text.len()].copy_from_slice(text.as_bytes());
+        memo
+    }
+
+    /// Serialized Sapling output paying `value` to `recipient`, encrypted to
+    /// `ovk` as the sending wallet would
+    ///
+    /// Nothing checks value commitments when decrypting, so the ephemeral key
+    /// doubles as a well-formed `cv`.
+    fn sapling_output(
+        ovk: Option<sapling_crypto::keys::OutgoingViewingKey>,
+        recipient: sapling_crypto::PaymentAddress,
+        value: u64,
+        text: &str,
+    ) -> Vec<u8> {
+        let note = sapling_crypto::Note::from_parts(
+            recipient,
+            sapling_crypto::value::NoteValue::from_raw(value),
+            sapling_crypto::Rseed::AfterZip212([7; 32]),
+        );
+        let cmu = note.cmu();
+        let encryptor = sapling_note_encryption(ovk, note, memo(text), &mut OsRng);
+        let epk = SaplingDomain::epk_bytes(encryptor.epk()).0;
+        let cv = sapling_crypto::value::ValueCommitment::from_bytes_not_small_order(&epk).unwrap();
+
+        let mut output = Vec::new();
+        output.extend(epk);
+        output.extend(cmu.to_bytes());
+        output.extend(epk);
+        output.extend(encryptor.encrypt_note_plaintext());
+        output.extend(encryptor.encrypt_outgoing_plaintext(&cv, &cmu, &mut OsRng));
+        output
+    }
+
+    /// Serialized Orchard action creating a note for `recipient`, encrypted
+    /// to `ovk`
+    fn orchard_action(
+        ovk: Option<orchard::keys::OutgoingViewingKey>,
+        recipient: orchard::Address,
+        value: u64,
+        text: &str,
+    ) -> Vec<u8> {
+        // The action's nullifier is the new note's rho
+        let rho = orchard::note::Rho::from_bytes(&[0; 32]).unwrap();
+        let rseed = orchard::note::RandomSeed::from_bytes([7; 32], &rho).unwrap();
+        let note = orchard::Note::from_parts(
+            recipient,
+            orchard::value::NoteValue::from_raw(value),
+            rho,
+            rseed,
+            orchard::NoteVersion::V2,
+        )
+        .unwrap();
+        let cmx = orchard::note::ExtractedNoteCommitment::from(note.commitment());
+        let encryptor = OrchardNoteEncryption::new(ovk, note, memo(text));
+        let epk = OrchardDomain::epk_bytes(encryptor.epk()).0;
+        let cv_net = orchard::value::ValueCommitment::from_bytes(&epk).unwrap();
+
+        let mut action = Vec::new();
+        action.extend(epk);
+        action.extend(rho.to_bytes());
+        // Any valid point will do for the randomized verification key
+        action.extend(epk);
+        action.extend(cmx.to_bytes());
+        action.extend(epk);
+        action.extend(encryptor.encrypt_note_plaintext());
+        action.extend(encryptor.encrypt_outgoing_plaintext(&cv_net, &cmx, &mut OsRng));
+        action
+    }
+
+    /// v5 transaction with the given Sapling outputs and Orchard actions and
+    /// zeroed proofs and signatures
+    fn v5_transaction(sapling: &[Vec<u8>], orchard: &[Vec<u8>]) -> Vec<u8> {
+        let mut tx = hex::decode(V5_HEADER).unwrap();
+        // No transparent inputs or outputs, no Sapling spends
+        tx.extend([0x00, 0x00, 0x00]);
+
+        tx.push(sapling.len() as u8);
+        for output in sapling {
+            tx.extend(output);
+        }
+        if !sapling.is_empty() {
+            // valueBalanceSapling, output proofs, binding signature
+            tx.extend([0u8; 8]);
+            tx.extend(vec![0u8; 192 * sapling.len() + 64]);
+        }
+
+        tx.push(orchard.len() as u8);
+        for action in orchard {
+            tx.extend(action);
+        }
+        if !orchard.is_empty() {
+            // Flags, valueBalanceOrchard and anchor, an empty proof, then the
+            // spend authorization and binding signatures
+            tx.push(0b11);
+            tx.extend([0u8; 8 + 32]);
+            tx.push(0x00);
+            tx.extend(vec![0u8; 64 * orchard.len() + 64]);
+        }
+        tx
+    }
+
+    fn sapling_ovk() -> Option<sapling_crypto::keys::OutgoingViewingKey> {
+        Some(ufvk().sapling().unwrap().to_ovk(Scope::External))
+    }
+
+    fn orchard_ovk() -> Option<orchard::keys::OutgoingViewingKey> {
+        Some(ufvk().orchard().unwrap().to_ovk(Scope::External))
+    }
+
+    /// Sapling and Orchard addresses of an unrelated wallet
+    fn stranger() -> (sapling_crypto::PaymentAddress, orchard::Address) {
+        let sapling = sapling_crypto::zip32::ExtendedSpendingKey::master(&[1; 32])
+            .default_address()
+            .1;
+        let sk = orchard::keys::SpendingKey::from_bytes([1; 32]).unwrap();
+        let orchard = orchard::keys::FullViewingKey::from(&sk).address_at(0u32, Scope::External);
+        (sapling, orchard)
+    }
+
+    #[test]
+    fn decrypts_incoming_notes_in_both_pools() {
+        let ufvk = ufvk();
+        let sapling = ufvk
+            .sapling()
+            .unwrap()
+            .address(DiversifierIndex::from(1u32))
+            .unwrap();
+        let orchard = ufvk.orchard().unwrap().address_at(1u32, Scope::External);
+        let tx = v5_transaction(
+            &[sapling_output(
+                None,
+                sapling,
+                150_000,
+                "Thanks for the coffee",
+            )],
+            &[orchard_action(None, orchard, 2_500_000, "Rent for March")],
+        );
+
+        let notes = decrypt_transaction(&ufvk, Network::Main, &tx, None).unwrap();
+        let summary: Vec<_> = notes
+            .iter()
+            .map(|n| {
+                (
+                    n.pool.as_str(),
+                    n.index,
+                    n.direction.as_str(),
+                    n.scope.as_str(),
+                    n.value,
+                    n.recipient.as_str(),
+                    n.memo.text.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "orchard",
+                    0,
+                    "incoming",
+                    "external",
+                    2_500_000,
+                    ORCHARD_ADDRESS_1,
+                    Some("Rent for March")
+                ),
+                (
+                    "sapling",
+                    0,
+                    "incoming",
+                    "external",
+                    150_000,
+                    SAPLING_ADDRESS_1,
+                    Some("Thanks for the coffee")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn change_is_decrypted_with_the_internal_scope() {
+        let ufvk = ufvk();
+        let (_, change) = ufvk.sapling().unwrap().change_address();
+        let tx = v5_transaction(&[sapling_output(None, change, 40_000, "")], &[]);
+
+        let notes = decrypt_transaction(&ufvk, Network::Main, &tx, None).unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(
+            (notes[0].direction.as_str(), notes[0].scope.as_str()),
+            ("incoming", "internal")
+        );
+        assert_eq!(notes[0].value, 40_000);
+        assert_eq!(notes[0].memo.text.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn recovers_sent_notes_with_the_ovk() {
+        let (sapling, orchard) = stranger();
+        let tx = v5_transaction(
+            &[sapling_output(sapling_ovk(), sapling, 10_000, "Invoice 42")],
+            &[orchard_action(orchard_ovk(), orchard, 20_000, "Invoice 43")],
+        );
+
+        let notes = decrypt_transaction(&ufvk(), Network::Main, &tx, None).unwrap();
+        assert_eq!(notes.len(), 2);
+
+        assert_eq!(notes[0].pool, "orchard");
+        assert_eq!(notes[0].direction, "outgoing");
+        assert_eq!(notes[0].value, 20_000);
+        assert_eq!(
+            notes[0].recipient,
+            encode_orchard_address(Network::Main, &orchard)
+        );
+        assert_eq!(notes[0].memo.text.as_deref(), Some("Invoice 43"));
+
+        assert_eq!(notes[1].pool, "sapling");
+        assert_eq!(
+            (notes[1].direction.as_str(), notes[1].scope.as_str()),
+            ("outgoing", "external")
+        );
+        assert_eq!(notes[1].value, 10_000);
+        assert_eq!(
+            notes[1].recipient,
+            ZcashAddress::from_sapling(Network::Main, sapling.to_bytes()).encode()
+        );
+        assert_eq!(notes[1].memo.text.as_deref(), Some("Invoice 42"));
+    }
+
+    #[test]
+    fn skips_notes_for_other_keys() {
+        let (sapling, orchard) = stranger();
+        let tx = v5_transaction(
+            &[sapling_output(None, sapling, 10_000, "Not for you")],
+            &[orchard_action(None, orchard, 20_000, "Nor this")],
+        );
+
+        let notes = decrypt_transaction(&ufvk(), Network::Main, &tx, None).unwrap();
+        assert!(notes.is_empty());
+    }
+
+    #[test]
+    fn rejects_unparseable_transactions() {
+        let error = decrypt_transaction(&ufvk(), Network::Main, &[0x05, 0x00], None).unwrap_err();
+        assert!(
+            error.starts_with("Failed to parse transaction"),
+            "{}",
+            error
+        );
+    }
+}
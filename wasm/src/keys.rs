@@ -0,0 +1,283 @@
+use blake2b_simd::Params;
+use ripemd::Ripemd160;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zcash_address::unified::{Container, Encoding, Fvk, Ivk, ParseError, Ufvk, Uivk};
+pub use zcash_keys::keys::UnifiedFullViewingKey;
+
+use crate::network::{network_name, Network, NetworkParams};
+
+const TYPECODE_P2PKH: u32 = 0x00;
+const TYPECODE_SAPLING: u32 = 0x02;
+const TYPECODE_ORCHARD: u32 = 0x03;
+
+/// Metadata recovered from a unified viewing key without deriving anything
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ViewingKeyInfo {
+    /// "ufvk" or "uivk"
+    pub kind: String,
+    /// "main", "test" or "regtest"
+    pub network: String,
+    /// Items in ZIP 316 typecode order
+    pub items: Vec<KeyItem>,
+    /// Fingerprint of the preferred shielded item (Orchard, then Sapling),
+    /// so the same account is identified the same way across encodings
+    pub key_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyItem {
+    pub typecode: u32,
+    /// "p2pkh", "sapling", "orchard" or "unknown"
+    pub kind: String,
+    /// Encoded item length in bytes
+    pub length: usize,
+    /// ZIP 32 FVK fingerprint for Sapling and Orchard full viewing keys,
+    /// BIP 32 key identifier (HASH160 of the public key) for P2PKH items
+    pub fingerprint: Option<String>,
+}
+
+/// Decode a ZIP-316 Unified Full Viewing Key
+///
+/// The network is taken from the key's prefix: `uview` (mainnet),
+/// `uviewtest` (testnet) or `uviewregtest` (regtest).
+pub fn parse_ufvk(encoded: &str) -> Result<(Network, UnifiedFullViewingKey), String> {
+    let encoded = encoded.trim();
+
+    let (network, _) = Ufvk::decode(encoded).map_err(|e| e.to_string())?;
+    let ufvk = UnifiedFullViewingKey::decode(&NetworkParams::new(network), encoded)?;
+
+    Ok((network, ufvk))
+}
+
+/// Decode a UFVK or UIVK (Bech32m, then F4Jumble, then the ZIP 316 item
+/// encoding) and describe its contents
+pub fn inspect_viewing_key(encoded: &str) -> Result<ViewingKeyInfo, String> {
+    let encoded = encoded.trim();
+
+    match Ufvk::decode(encoded) {
+        Ok((network, ufvk)) => return Ok(describe_ufvk(network, &ufvk)),
+        Err(ParseError::UnknownPrefix(_)) => {}
+        Err(e) => return Err(e.to_string()),
+    }
+
+    match Uivk::decode(encoded) {
+        Ok((network, uivk)) => Ok(describe_uivk(network, &uivk)),
+        Err(ParseError::UnknownPrefix(prefix)) => Err(format!(
+            "Unknown prefix '{}': expected a unified full or incoming viewing key",
+            prefix
+        )),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn describe_ufvk(network: Network, ufvk: &Ufvk) -> ViewingKeyInfo {
+    // Parsing enforces ZIP 316's ascending typecode order; `items()` would
+    // give receiver priority order, Orchard first
+    let items: Vec<KeyItem> = ufvk
+        .items_as_parsed()
+        .iter()
+        .map(|item| match item {
+            Fvk::P2pkh(data) => KeyItem {
+                typecode: TYPECODE_P2PKH,
+                kind: "p2pkh".to_string(),
+                length: data.len(),
+                // chain code (32 bytes) || compressed public key (33 bytes)
+                fingerprint: Some(hash160(&data[32..])),
+            },
+            Fvk::Sapling(data) => KeyItem {
+                typecode: TYPECODE_SAPLING,
+                kind: "sapling".to_string(),
+                length: data.len(),
+                // ak || nk || ovk, without the trailing diversifier key
+                fingerprint: Some(fvk_fingerprint(b"ZcashSaplingFVFP", &data[..96])),
+            },
+            Fvk::Orchard(data) => KeyItem {
+                typecode: TYPECODE_ORCHARD,
+                kind: "orchard".to_string(),
+                length: data.len(),
+                // ak || nk || rivk
+                fingerprint: Some(fvk_fingerprint(b"ZcashOrchardFVFP", data)),
+            },
+            Fvk::Unknown { typecode, data } => unknown_item(*typecode, data),
+        })
+        .collect();
+
+    let key_id = preferred_fingerprint(&items);
+
+    ViewingKeyInfo {
+        kind: "ufvk".to_string(),
+        network: network_name(network).to_string(),
+        items,
+        key_id,
+    }
+}
+
+fn describe_uivk(network: Network, uivk: &Uivk) -> ViewingKeyInfo {
+    let items: Vec<KeyItem> = uivk
+        .items_as_parsed()
+        .iter()
+        .map(|item| match item {
+            Ivk::P2pkh(data) => KeyItem {
+                typecode: TYPECODE_P2PKH,
+                kind: "p2pkh".to_string(),
+                length: data.len(),
+                fingerprint: Some(hash160(&data[32..])),
+            },
+            // Incoming viewing keys have no standardised fingerprint
+            Ivk::Sapling(data) => KeyItem {
+                typecode: TYPECODE_SAPLING,
+                kind: "sapling".to_string(),
+                length: data.len(),
+                fingerprint: None,
+            },
+            Ivk::Orchard(data) => KeyItem {
+                typecode: TYPECODE_ORCHARD,
+                kind: "orchard".to_string(),
+                length: data.len(),
+                fingerprint: None,
+            },
+            Ivk::Unknown { typecode, data } => unknown_item(*typecode, data),
+        })
+        .collect();
+
+    let key_id = preferred_fingerprint(&items);
+
+    ViewingKeyInfo {
+        kind: "uivk".to_string(),
+        network: network_name(network).to_string(),
+        items,
+        key_id,
+    }
+}
+
+fn unknown_item(typecode: u32, data: &[u8]) -> KeyItem {
+    KeyItem {
+        typecode,
+        kind: "unknown".to_string(),
+        length: data.len(),
+        fingerprint: None,
+    }
+}
+
+fn preferred_fingerprint(items: &[KeyItem]) -> Option<String> {
+    [TYPECODE_ORCHARD, TYPECODE_SAPLING, TYPECODE_P2PKH]
+        .iter()
+        .find_map(|typecode| {
+            items
+                .iter()
+                .find(|item| item.typecode == *typecode)
+                .and_then(|item| item.fingerprint.clone())
+        })
+}
+
+/// ZIP 32 full viewing key fingerprint: BLAKE2b-256 of the encoded key
+fn fvk_fingerprint(personalization: &[u8; 16], encoded: &[u8]) -> String {
+    let hash = Params::new()
+        .hash_length(32)
+        .personal(personalization)
+        .to_state()
+        .update(encoded)
+        .finalize();
+
+    hex::encode(hash.as_bytes())
+}
+
+/// BIP 32 key identifier: RIPEMD-160(SHA-256(public key))
+fn hash160(pubkey: &[u8]) -> String {
+    hex::encode(Ripemd160::digest(Sha256::digest(pubkey)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{
+        ORCHARD_ADDRESS_0, ORCHARD_FINGERPRINT, P2PKH_FINGERPRINT, SAPLING_FINGERPRINT, UFVK,
+        UFVK_TESTNET, UFVK_TRANSPARENT,
+    };
+
+    #[test]
+    fn parses_ufvk_network_and_components() {
+        let (network, ufvk) = parse_ufvk(&format!(" {}\n", UFVK)).unwrap();
+        assert_eq!(network, Network::Main);
+        assert!(ufvk.sapling().is_some() && ufvk.orchard().is_some());
+        assert!(ufvk.transparent().is_none());
+
+        let (network, ufvk) = parse_ufvk(UFVK_TRANSPARENT).unwrap();
+        assert_eq!(network, Network::Main);
+        assert!(ufvk.transparent().is_some());
+
+        let (network, _) = parse_ufvk(UFVK_TESTNET).unwrap();
+        assert_eq!(network, Network::Test);
+    }
+
+    #[test]
+    fn describes_ufvk_items_with_fingerprints() {
+        let info = inspect_viewing_key(UFVK).unwrap();
+        assert_eq!(
+            (info.kind.as_str(), info.network.as_str()),
+            ("ufvk", "main")
+        );
+
+        let items: Vec<(u32, &str, usize, Option<&str>)> = info
+            .items
+            .iter()
+            .map(|i| {
+                (
+                    i.typecode,
+                    i.kind.as_str(),
+                    i.length,
+                    i.fingerprint.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            items,
+            [
+                (2, "sapling", 128, Some(SAPLING_FINGERPRINT)),
+                (3, "orchard", 96, Some(ORCHARD_FINGERPRINT)),
+            ]
+        );
+        // Orchard is preferred for the key ID
+        assert_eq!(info.key_id.as_deref(), Some(ORCHARD_FINGERPRINT));
+    }
+
+    #[test]
+    fn p2pkh_items_use_the_bip32_identifier() {
+        let info = inspect_viewing_key(UFVK_TRANSPARENT).unwrap();
+
+        let p2pkh = &info.items[0];
+        assert_eq!(
+            (p2pkh.typecode, p2pkh.kind.as_str(), p2pkh.length),
+            (0, "p2pkh", 65)
+        );
+        assert_eq!(p2pkh.fingerprint.as_deref(), Some(P2PKH_FINGERPRINT));
+        // The shielded items and key ID are unchanged by the extra item
+        assert_eq!(
+            info.items[1].fingerprint.as_deref(),
+            Some(SAPLING_FINGERPRINT)
+        );
+        assert_eq!(info.key_id.as_deref(), Some(ORCHARD_FINGERPRINT));
+    }
+
+    #[test]
+    fn fingerprints_do_not_depend_on_the_network() {
+        let info = inspect_viewing_key(UFVK_TESTNET).unwrap();
+        assert_eq!(info.network, "test");
+        assert_eq!(info.key_id.as_deref(), Some(ORCHARD_FINGERPRINT));
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        // Changing a data character breaks the Bech32m checksum
+        let tampered = UFVK.replacen("9av5", "9av6", 1);
+        assert!(inspect_viewing_key(&tampered).is_err());
+        assert!(parse_ufvk(&tampered).is_err());
+
+        // A well-formed unified encoding of something other than a key
+        let error = inspect_viewing_key(ORCHARD_ADDRESS_0).unwrap_err();
+        assert!(error.starts_with("Unknown prefix 'u'"), "{}", error);
+
+        assert!(parse_ufvk("").is_err());
+    }
+}
@@ -1,6 +1,29 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
 
+pub mod address;
+pub mod block;
+pub mod compact;
+pub mod decrypt;
+pub mod keys;
+pub mod memo;
+pub mod network;
+pub mod ownership;
+pub mod transaction;
+pub mod txid;
+pub mod validate;
+
+#[cfg(test)]
+mod test_vectors;
+
+use address::{DerivedAddress, ReceiverSelection};
+use decrypt::DecryptedNote;
+use keys::ViewingKeyInfo;
+use memo::TypedMemo;
+use network::Network;
+use ownership::OwnershipResult;
+use transaction::DecodedTransaction;
+
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
@@ -14,32 +37,42 @@ pub fn init_panic_hook() {
 
 #[wasm_bindgen]
 pub fn greet() -> String {
-    "Zcash Mainnet Cryptographic Decryption Module 🔐".to_string()
+    "Zcash Cryptographic Decryption Module 🔐 (mainnet, testnet, regtest)".to_string()
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct DecryptResult {
     pub success: bool,
-    pub memo: String,
+    /// Transaction ID computed from the supplied bytes
+    pub txid: Option<String>,
+    pub memo: Option<TypedMemo>,
+    pub notes: Vec<DecryptedNote>,
     pub error: Option<String>,
 }
 
 /// Decrypt a memo from a shielded Zcash transaction
 ///
-/// This implementation demonstrates the complete decryption workflow.
-/// For full production decryption, this would need:
-/// 1. Full zcash_primitives library (doesn't compile to WASM easily)
-/// 2. Access to Zcash node or light wallet server for blockchain data
-/// 3. Implementation of trial decryption algorithms
+/// Every Sapling output and Orchard action is trial-decrypted with the UFVK's
+/// incoming viewing keys (external and internal scope), then with its
+/// outgoing viewing keys so that transactions the key holder sent are
+/// recovered too. `memo` holds the memo of the first recovered note; `notes`
+/// lists all of them with pool, index, direction, value and recipient.
+///
+/// Memos are interpreted per ZIP 302: UTF-8 text (trailing zeros stripped),
+/// the empty "no memo" marker, arbitrary binary data, or reserved formats,
+/// the latter two as hex.
 ///
 /// # Arguments
-/// * `viewing_key` - Unified Full Viewing Key (mainnet starts with "uview1")
+/// * `viewing_key` - Unified Full Viewing Key; its prefix (`uview1`,
+///   `uviewtest1` or `uviewregtest1`) selects the network
 /// * `tx_hex` - Raw transaction in hexadecimal format
+/// * `expected_txid` - Optional txid the hex claims to be; the transaction is
+///   rejected unless its computed txid matches
 ///
 /// # Returns
 /// JSON string containing DecryptResult
 #[wasm_bindgen]
-pub fn decrypt_memo(viewing_key: &str, tx_hex: &str) -> String {
+pub fn decrypt_memo(viewing_key: &str, tx_hex: &str, expected_txid: Option<String>) -> String {
     log(&format!("🔐 Decryption request: VK length={}, TX length={}", viewing_key.len(), tx_hex.len()));
     
     // Validate inputs
@@ -47,56 +80,64 @@ pub fn decrypt_memo(viewing_key: &str, tx_hex: &str) -> String {
         return error_result("Viewing key and transaction hex are required");
     }
 
-    // Validate viewing key format
-    if !viewing_key.starts_with("uview") {
-        return error_result("Invalid viewing key format. Mainnet UFVKs start with 'uview1'");
-    }
+    // Check the key's shape before decoding it
+    let viewing_key = match validate::viewing_key(viewing_key) {
+        Ok(key) => key,
+        Err(e) => return error_result(&e.to_string()),
+    };
+
+    // Decode the viewing key
+    let (network, ufvk) = match keys::parse_ufvk(viewing_key) {
+        Ok(parsed) => parsed,
+        Err(e) => return error_result(&format!("Invalid viewing key: {}", e)),
+    };
 
     // Decode and validate transaction hex
-    let tx_bytes = match hex::decode(tx_hex) {
+    let tx_bytes = match hex::decode(tx_hex.trim()) {
         Ok(bytes) => bytes,
         Err(e) => return error_result(&format!("Invalid transaction hex: {}", e)),
     };
 
     log(&format!("✅ Transaction hex decoded: {} bytes", tx_bytes.len()));
 
+    // Make sure the bytes are the transaction the caller thinks they are
+    let computed = match expected_txid.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        Some(expected) => validate::txid(expected)
+            .map_err(String::from)
+            .and_then(|expected| txid::verify_txid(&tx_bytes, &expected)),
+        None => txid::compute_txid(&tx_bytes),
+    };
+    let txid = match computed {
+        Ok(txid) => txid,
+        Err(e) => return error_result(&e),
+    };
+
     // Parse transaction structure
-    let tx_info = parse_transaction_structure(&tx_bytes);
+    let tx_info = parse_transaction_structure(&tx_bytes, network);
     
     log(&format!("📊 Transaction analysis: {}", tx_info));
 
-    // Demonstrate cryptographic capabilities
-    let demo_decryption = perform_crypto_demo(viewing_key, &tx_bytes);
+    let notes = match decrypt::decrypt_transaction(&ufvk, network, &tx_bytes, None) {
+        Ok(notes) => notes,
+        Err(e) => return error_result(&e),
+    };
+
+    log(&format!("🔓 Decrypted {} note(s)", notes.len()));
+
+    let memo = match notes.first() {
+        Some(note) => Some(note.memo.clone()),
+        None => {
+            return error_result(
+                "No outputs in this transaction could be decrypted with the provided viewing key",
+            )
+        }
+    };
 
     let result = DecryptResult {
         success: true,
-        memo: format!(
-            "🔐 ZCASH CRYPTOGRAPHIC DECRYPTION MODULE\n\n\
-             ✅ WebAssembly Loaded Successfully\n\
-             ✅ Transaction Parsed: {} bytes\n\
-             ✅ Viewing Key Validated: {}...\n\n\
-             📊 TRANSACTION ANALYSIS:\n{}\n\n\
-             🔬 CRYPTOGRAPHIC DEMO:\n{}\n\n\
-             ⚡ CAPABILITIES:\n\
-             • Blake2b hashing ✅\n\
-             • ChaCha20Poly1305 encryption ✅\n\
-             • Bech32 encoding ✅\n\
-             • Hex/Base58 operations ✅\n\n\
-             📝 FOR FULL DECRYPTION:\n\
-             The complete Zcash cryptographic stack (zcash_primitives, \n\
-             zcash_note_encryption, sapling-crypto, orchard) requires \n\
-             native compilation due to complex cryptographic operations.\n\n\
-             ALTERNATIVE APPROACHES:\n\
-             1. Server-side decryption API\n\
-             2. Native desktop app (Electron + Rust)\n\
-             3. Light wallet SDK integration\n\n\
-             This module demonstrates the complete workflow and\n\
-             validates all inputs correctly for production use.",
-            tx_bytes.len(),
-            &viewing_key[..20],
-            tx_info,
-            demo_decryption
-        ),
+        txid: Some(txid),
+        memo,
+        notes,
         error: None,
     };
 
@@ -106,81 +147,263 @@ pub fn decrypt_memo(viewing_key: &str, tx_hex: &str) -> String {
 fn error_result(message: &str) -> String {
     let result = DecryptResult {
         success: false,
-        memo: String::new(),
+        txid: None,
+        memo: None,
+        notes: Vec::new(),
         error: Some(message.to_string()),
     };
     serde_json::to_string(&result).unwrap()
 }
 
-fn parse_transaction_structure(tx_bytes: &[u8]) -> String {
-    // Basic transaction structure parsing
-    let version = if tx_bytes.len() >= 4 {
-        u32::from_le_bytes([tx_bytes[0], tx_bytes[1], tx_bytes[2], tx_bytes[3]])
-    } else {
-        0
-    };
-
-    // Detect transaction version and format
-    let tx_format = match version {
-        4 => "Sapling (v4)",
-        5 => "NU5 with Orchard (v5)",
-        _ => "Legacy or Unknown",
+fn parse_transaction_structure(tx_bytes: &[u8], network: Network) -> String {
+    let tx = match transaction::decode_transaction(tx_bytes, network) {
+        Ok(tx) => tx,
+        Err(e) => return format!("• Size: {} bytes\n• Header invalid: {}", tx_bytes.len(), e),
     };
 
     format!(
         "• Version: {} ({})\n\
+         • Version group: {}\n\
          • Size: {} bytes\n\
-         • Format: {}\n\
+         • Transparent: {} in / {} out\n\
+         • Sapling: {} spends / {} outputs\n\
+         • Orchard: {} actions\n\
          • Header validated: ✅",
-        version, 
-        if version == 5 { "NU5" } else { "Legacy" },
-        tx_bytes.len(),
-        tx_format
+        tx.header.version,
+        tx.header.format_name(),
+        tx.header
+            .version_group_id
+            .map(|id| format!("{:08x}", id))
+            .unwrap_or_else(|| "none".to_string()),
+        tx.size,
+        tx.transparent_inputs.len(),
+        tx.transparent_outputs.len(),
+        tx.sapling_spends.len(),
+        tx.sapling_outputs.len(),
+        tx.orchard.as_ref().map_or(0, |bundle| bundle.actions.len())
     )
 }
 
-fn perform_crypto_demo(viewing_key: &str, tx_bytes: &[u8]) -> String {
-    use blake2b_simd::Params;
-    
-    // Demonstrate Blake2b hash (used in Zcash)
-    let hash = Params::new()
-        .hash_length(32)
-        .personal(b"ZcashDemo")
-        .to_state()
-        .update(tx_bytes)
-        .finalize();
-
-    let hash_hex = hex::encode(hash.as_bytes());
-
-    // Demonstrate key derivation
-    let vk_hash = Params::new()
-        .hash_length(32)
-        .personal(b"ZcashVK")
-        .to_state()
-        .update(viewing_key.as_bytes())
-        .finalize();
-
-    let vk_fingerprint = hex::encode(&vk_hash.as_bytes()[..8]);
+#[derive(Serialize)]
+pub struct DecodeResult {
+    pub success: bool,
+    pub transaction: Option<DecodedTransaction>,
+    pub error: Option<String>,
+}
 
-    format!(
-        "• TX Blake2b Hash: {}...\n\
-         • VK Fingerprint: {}\n\
-         • Crypto primitives: Active\n\
-         • Hash operations: Functional ✅",
-        &hash_hex[..16],
-        vk_fingerprint
-    )
+/// Decode a raw transaction into a structured document
+///
+/// Covers v1 through v5: header fields, transparent inputs and outputs,
+/// Sapling spends and outputs, Orchard actions with flags and anchor,
+/// JoinSplits, value balances, lock time and expiry height.
+///
+/// # Arguments
+/// * `tx_hex` - Raw transaction in hexadecimal format
+/// * `network` - "main", "test" or "regtest", for transparent address prefixes
+///
+/// # Returns
+/// JSON string containing DecodeResult
+#[wasm_bindgen]
+pub fn decode_transaction(tx_hex: &str, network: &str) -> String {
+    let decoded = network::parse_network(network).and_then(|network| {
+        hex::decode(tx_hex.trim())
+            .map_err(|e| format!("Invalid transaction hex: {}", e))
+            .and_then(|bytes| transaction::decode_transaction(&bytes, network))
+    });
+
+    let result = match decoded {
+        Ok(tx) => DecodeResult {
+            success: true,
+            transaction: Some(tx),
+            error: None,
+        },
+        Err(e) => DecodeResult {
+            success: false,
+            transaction: None,
+            error: Some(e),
+        },
+    };
+
+    serde_json::to_string(&result).unwrap()
+}
+
+#[derive(Serialize)]
+pub struct TxidResult {
+    pub success: bool,
+    pub txid: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Compute the transaction ID of a raw transaction
+///
+/// Uses the ZIP 244 digest for v5 transactions and double SHA-256 for
+/// earlier versions. The txid is returned in the usual reversed byte order,
+/// as shown by block explorers.
+///
+/// # Arguments
+/// * `tx_hex` - Raw transaction in hexadecimal format
+///
+/// # Returns
+/// JSON string containing TxidResult
+#[wasm_bindgen]
+pub fn compute_txid(tx_hex: &str) -> String {
+    let computed = hex::decode(tx_hex.trim())
+        .map_err(|e| format!("Invalid transaction hex: {}", e))
+        .and_then(|bytes| txid::compute_txid(&bytes));
+
+    let result = match computed {
+        Ok(txid) => TxidResult {
+            success: true,
+            txid: Some(txid),
+            error: None,
+        },
+        Err(e) => TxidResult {
+            success: false,
+            txid: None,
+            error: Some(e),
+        },
+    };
+
+    serde_json::to_string(&result).unwrap()
+}
+
+#[derive(Serialize)]
+pub struct InspectResult {
+    pub success: bool,
+    pub key: Option<ViewingKeyInfo>,
+    pub error: Option<String>,
+}
+
+/// Inspect a ZIP-316 unified full or incoming viewing key
+///
+/// Reports the network, every item the key carries (P2PKH, Sapling, Orchard
+/// and unknown typecodes) and ZIP 32 / BIP 32 fingerprints. Malformed keys
+/// are rejected with the specific decoding error (bad checksum, F4Jumble
+/// length, duplicate or misordered typecodes, ...).
+///
+/// # Arguments
+/// * `viewing_key` - UFVK (`uview...`) or UIVK (`uivk...`)
+///
+/// # Returns
+/// JSON string containing InspectResult
+#[wasm_bindgen]
+pub fn inspect_viewing_key(viewing_key: &str) -> String {
+    let result = match keys::inspect_viewing_key(viewing_key) {
+        Ok(key) => InspectResult {
+            success: true,
+            key: Some(key),
+            error: None,
+        },
+        Err(e) => InspectResult {
+            success: false,
+            key: None,
+            error: Some(e),
+        },
+    };
+
+    serde_json::to_string(&result).unwrap()
+}
+
+#[derive(Serialize)]
+pub struct DeriveResult {
+    pub success: bool,
+    pub addresses: Vec<DerivedAddress>,
+    pub error: Option<String>,
+}
+
+/// Derive unified addresses from a UFVK at a range of diversifier indices
+///
+/// # Arguments
+/// * `viewing_key` - Unified Full Viewing Key
+/// * `start` - First diversifier index
+/// * `count` - Number of consecutive indices to derive (at most 1000)
+/// * `receivers` - Comma-separated subset of "orchard,sapling,p2pkh";
+///   empty includes every receiver the key supports
+///
+/// # Returns
+/// JSON string containing DeriveResult
+#[wasm_bindgen]
+pub fn derive_addresses(viewing_key: &str, start: u32, count: u32, receivers: &str) -> String {
+    let derived = keys::parse_ufvk(viewing_key)
+        .map_err(|e| format!("Invalid viewing key: {}", e))
+        .and_then(|(network, ufvk)| {
+            let selection = ReceiverSelection::from_names(receivers.split(','))?;
+            address::derive_addresses(&ufvk, network, start, count, selection)
+        });
+
+    let result = match derived {
+        Ok(addresses) => DeriveResult {
+            success: true,
+            addresses,
+            error: None,
+        },
+        Err(e) => DeriveResult {
+            success: false,
+            addresses: Vec::new(),
+            error: Some(e),
+        },
+    };
+
+    serde_json::to_string(&result).unwrap()
+}
+
+#[derive(Serialize)]
+pub struct OwnershipCheckResult {
+    pub success: bool,
+    pub ownership: Option<OwnershipResult>,
+    pub error: Option<String>,
+}
+
+/// Check whether an address belongs to a viewing key
+///
+/// Accepts Sapling (`zs1...`), transparent (`t1...`) and unified (`u1...`)
+/// addresses. Each receiver is checked separately; shielded receivers report
+/// the scope and diversifier index they were derived at, transparent ones the
+/// child index if it is among the first 1000 of either chain.
+///
+/// # Arguments
+/// * `viewing_key` - Unified Full Viewing Key
+/// * `address` - Address to check
+///
+/// # Returns
+/// JSON string containing OwnershipCheckResult
+#[wasm_bindgen]
+pub fn check_address_ownership(viewing_key: &str, address: &str) -> String {
+    let checked = keys::parse_ufvk(viewing_key)
+        .map_err(|e| format!("Invalid viewing key: {}", e))
+        .and_then(|(network, ufvk)| ownership::check_address_ownership(&ufvk, network, address));
+
+    let result = match checked {
+        Ok(ownership) => OwnershipCheckResult {
+            success: true,
+            ownership: Some(ownership),
+            error: None,
+        },
+        Err(e) => OwnershipCheckResult {
+            success: false,
+            ownership: None,
+            error: Some(e),
+        },
+    };
+
+    serde_json::to_string(&result).unwrap()
 }
 
 /// Scan recent blocks for transactions to a viewing key
 #[wasm_bindgen]
 pub fn scan_for_transactions(viewing_key: &str, blocks_to_scan: u32) -> String {
     log(&format!("📡 Scan request: {} blocks", blocks_to_scan));
-    
+
+    let viewing_key = match validate::viewing_key(viewing_key) {
+        Ok(key) => key,
+        Err(e) => return serde_json::json!({ "status": "error", "error": e.to_string() }).to_string(),
+    };
+
     serde_json::json!({
         "status": "ready",
         "message": format!("Inbox scanner ready for {} blocks", blocks_to_scan),
-        "viewing_key_fingerprint": &viewing_key[..15],
+        // Validated keys are ASCII, so any prefix is a character boundary
+        "viewing_key_fingerprint": viewing_key.get(..15).unwrap_or(viewing_key),
         "blocks_to_scan": blocks_to_scan,
         "transactions_found": 0,
         "required": [
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+
+/// Size of a decrypted note plaintext memo field
+pub const MEMO_SIZE: usize = 512;
+
+/// A memo field interpreted according to ZIP 302
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TypedMemo {
+    /// "text", "empty", "arbitrary", "reserved" or "invalid"
+    ///
+    /// * `text` - first byte 0x00..=0xF4 and valid UTF-8
+    /// * `empty` - 0xF6 followed by zeros, the "no memo" marker
+    /// * `arbitrary` - 0xFF, application-defined binary data
+    /// * `reserved` - 0xF5, 0xF7..=0xFE, or 0xF6 followed by non-zero bytes
+    /// * `invalid` - starts like text but is not valid UTF-8
+    pub variant: String,
+    /// Decoded text with trailing zero padding stripped (`text` only)
+    pub text: Option<String>,
+    /// All 512 memo bytes as hex (every variant except `text` and `empty`)
+    pub hex: Option<String>,
+}
+
+impl TypedMemo {
+    /// Human-readable rendering: the text, nothing for an empty memo, or the
+    /// raw bytes as hex
+    pub fn display(&self) -> String {
+        self.text
+            .clone()
+            .or_else(|| self.hex.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Interpret the 512-byte memo field of a note plaintext
+pub fn parse_memo(bytes: &[u8; MEMO_SIZE]) -> TypedMemo {
+    let (variant, text) = match bytes[0] {
+        0x00..=0xF4 => {
+            let end = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+            match std::str::from_utf8(&bytes[..end]) {
+                Ok(text) => ("text", Some(text.to_string())),
+                Err(_) => ("invalid", None),
+            }
+        }
+        0xF6 if bytes[1..].iter().all(|&b| b == 0) => ("empty", None),
+        0xFF => ("arbitrary", None),
+        _ => ("reserved", None),
+    };
+
+    let hex = match variant {
+        "text" | "empty" => None,
+        _ => Some(hex::encode(bytes)),
+    };
+
+    TypedMemo {
+        variant: variant.to_string(),
+        text,
+        hex,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memo_of(prefix: &[u8]) -> [u8; MEMO_SIZE] {
+        let mut bytes = [0u8; MEMO_SIZE];
+        bytes[..prefix.len()].copy_from_slice(prefix);
+        bytes
+    }
+
+    #[test]
+    fn text_memos_drop_zero_padding() {
+        let memo = parse_memo(&memo_of("Thanks for the coffee ☕".as_bytes()));
+        assert_eq!(memo.variant, "text");
+        assert_eq!(memo.text.as_deref(), Some("Thanks for the coffee ☕"));
+        assert_eq!(memo.hex, None);
+        assert_eq!(memo.display(), "Thanks for the coffee ☕");
+
+        // A full-length memo keeps every byte
+        let full = parse_memo(&[b'z'; MEMO_SIZE]);
+        assert_eq!(full.text.map(|t| t.len()), Some(MEMO_SIZE));
+
+        // An all-zero field is valid, empty text
+        let zeros = parse_memo(&[0u8; MEMO_SIZE]);
+        assert_eq!(
+            (zeros.variant.as_str(), zeros.text.as_deref()),
+            ("text", Some(""))
+        );
+    }
+
+    #[test]
+    fn no_memo_marker_is_empty() {
+        let memo = parse_memo(&memo_of(&[0xF6]));
+        assert_eq!(memo.variant, "empty");
+        assert_eq!((memo.text, memo.hex), (None, None));
+        assert_eq!(parse_memo(&memo_of(&[0xF6])).display(), "");
+    }
+
+    #[test]
+    fn non_text_memos_are_returned_as_hex() {
+        let arbitrary = memo_of(&[0xFF, 0x01, 0x02]);
+        let memo = parse_memo(&arbitrary);
+        assert_eq!(memo.variant, "arbitrary");
+        assert_eq!(memo.text, None);
+        assert_eq!(memo.hex.as_deref(), Some(hex::encode(arbitrary).as_str()));
+        assert!(memo.display().starts_with("ff0102000000"));
+
+        for prefix in [&[0xF5][..], &[0xF6, 0x00, 0x01], &[0xF7], &[0xFE]] {
+            let memo = parse_memo(&memo_of(prefix));
+            assert_eq!(memo.variant, "reserved", "{:02x?}", prefix);
+            assert_eq!(memo.hex.map(|h| h.len()), Some(2 * MEMO_SIZE));
+        }
+    }
+
+    #[test]
+    fn text_that_is_not_utf8_is_invalid() {
+        // A lone continuation byte, then a truncated two-byte sequence
+        for prefix in [&[b'a', 0x80][..], &[0xC3]] {
+            let memo = parse_memo(&memo_of(prefix));
+            assert_eq!(memo.variant, "invalid", "{:02x?}", prefix);
+            assert_eq!(memo.text, None);
+            assert!(memo.hex.is_some());
+        }
+    }
+}
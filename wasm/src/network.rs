@@ -0,0 +1,63 @@
+pub use zcash_protocol::consensus::NetworkType as Network;
+use zcash_protocol::consensus::{
+    BlockHeight, BranchId, NetworkUpgrade, Parameters, MAIN_NETWORK, TEST_NETWORK,
+};
+
+/// Consensus parameters for mainnet, testnet or a local regtest chain
+///
+/// Regtest has no fixed activation heights; this follows the usual
+/// lightwalletd test setups where every upgrade activates at height 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetworkParams {
+    network: Network,
+}
+
+impl NetworkParams {
+    pub fn new(network: Network) -> Self {
+        NetworkParams { network }
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Consensus branch in force at `height`
+    pub fn branch_id(&self, height: u32) -> BranchId {
+        BranchId::for_height(self, BlockHeight::from_u32(height))
+    }
+}
+
+impl Parameters for NetworkParams {
+    fn network_type(&self) -> Network {
+        self.network
+    }
+
+    fn activation_height(&self, nu: NetworkUpgrade) -> Option<BlockHeight> {
+        match self.network {
+            Network::Main => MAIN_NETWORK.activation_height(nu),
+            Network::Test => TEST_NETWORK.activation_height(nu),
+            Network::Regtest => Some(BlockHeight::from_u32(1)),
+        }
+    }
+}
+
+/// Parse a network name: "main"/"mainnet", "test"/"testnet" or "regtest"
+pub fn parse_network(name: &str) -> Result<Network, String> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "main" | "mainnet" => Ok(Network::Main),
+        "test" | "testnet" => Ok(Network::Test),
+        "regtest" => Ok(Network::Regtest),
+        other => Err(format!(
+            "Unknown network '{}': expected main, test or regtest",
+            other
+        )),
+    }
+}
+
+pub fn network_name(network: Network) -> &'static str {
+    match network {
+        Network::Main => "main",
+        Network::Test => "test",
+        Network::Regtest => "regtest",
+    }
+}
@@ -0,0 +1,383 @@
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use zcash_address::unified::{self, Container, Receiver};
+use zcash_address::{ConversionError, TryFromAddress, ZcashAddress};
+use zcash_keys::keys::UnifiedFullViewingKey;
+use zcash_transparent::address::TransparentAddress;
+use zcash_transparent::keys::{IncomingViewingKey, NonHardenedChildIndex};
+use zip32::Scope;
+
+use crate::decrypt::scope_name;
+use crate::network::{network_name, Network};
+
+/// How many child indices per transparent chain are searched; transparent
+/// addresses carry no encrypted index, so they can only be matched by
+/// derivation
+pub const TRANSPARENT_SEARCH_LIMIT: u32 = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OwnershipResult {
+    /// True when at least one receiver could be checked and every
+    /// checkable receiver belongs to the viewing key
+    pub owned: bool,
+    /// "sapling", "unified", "p2pkh" or "p2sh"
+    pub address_type: String,
+    pub network: String,
+    /// In ZIP 316 typecode order
+    pub receivers: Vec<ReceiverOwnership>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReceiverOwnership {
+    /// "orchard", "sapling", "p2pkh", "p2sh" or "unknown"
+    pub kind: String,
+    pub owned: bool,
+    /// Whether this receiver type can be checked against a UFVK at all
+    pub checkable: bool,
+    /// "external" or "internal"
+    pub scope: Option<String>,
+    /// Decimal, as shielded diversifier indices are 88 bits wide
+    pub diversifier_index: Option<String>,
+}
+
+/// Decoded address with the network it was encoded for
+enum ParsedAddress {
+    Sapling(Network, [u8; 43]),
+    Unified(Network, unified::Address),
+    P2pkh(Network, [u8; 20]),
+    P2sh(Network),
+}
+
+impl ParsedAddress {
+    fn network(&self) -> Network {
+        match self {
+            ParsedAddress::Sapling(net, _)
+            | ParsedAddress::Unified(net, _)
+            | ParsedAddress::P2pkh(net, _)
+            | ParsedAddress::P2sh(net) => *net,
+        }
+    }
+}
+
+impl TryFromAddress for ParsedAddress {
+    type Error = Infallible;
+
+    fn try_from_sapling(
+        net: Network,
+        data: [u8; 43],
+    ) -> Result<Self, ConversionError<Self::Error>> {
+        Ok(ParsedAddress::Sapling(net, data))
+    }
+
+    fn try_from_unified(
+        net: Network,
+        data: unified::Address,
+    ) -> Result<Self, ConversionError<Self::Error>> {
+        Ok(ParsedAddress::Unified(net, data))
+    }
+
+    fn try_from_transparent_p2pkh(
+        net: Network,
+        data: [u8; 20],
+    ) -> Result<Self, ConversionError<Self::Error>> {
+        Ok(ParsedAddress::P2pkh(net, data))
+    }
+
+    fn try_from_transparent_p2sh(
+        net: Network,
+        _data: [u8; 20],
+    ) -> Result<Self, ConversionError<Self::Error>> {
+        Ok(ParsedAddress::P2sh(net))
+    }
+}
+
+/// Check whether a Sapling, unified or transparent address was derived from
+/// the viewing key, recovering its scope and diversifier index
+///
+/// Shielded diversifier indices are recovered by decrypting the diversifier
+/// with the diversifier key; transparent indices by searching the first
+/// [`TRANSPARENT_SEARCH_LIMIT`] children of the external and internal chains.
+pub fn check_address_ownership(
+    ufvk: &UnifiedFullViewingKey,
+    key_network: Network,
+    address: &str,
+) -> Result<OwnershipResult, String> {
+    let parsed = ZcashAddress::try_from_encoded(address.trim())
+        .map_err(|e| format!("Invalid address: {}", e))?
+        .convert::<ParsedAddress>()
+        .map_err(|e| format!("Unsupported address: {}", e))?;
+
+    // Before any receiver check, which may derive thousands of transparent
+    // keys
+    let network = parsed.network();
+    if network != key_network {
+        return Err(format!(
+            "Address is for the {} network but the viewing key is for {}",
+            network_name(network),
+            network_name(key_network)
+        ));
+    }
+
+    let (address_type, receivers) = match parsed {
+        ParsedAddress::Sapling(_, data) => ("sapling", vec![check_sapling(ufvk, &data)]),
+        ParsedAddress::P2pkh(_, hash) => ("p2pkh", vec![check_p2pkh(ufvk, &hash)]),
+        ParsedAddress::P2sh(_) => ("p2sh", vec![unchecked("p2sh")]),
+        ParsedAddress::Unified(_, ua) => {
+            // In typecode order, as encoded
+            let receivers = ua
+                .items_as_parsed()
+                .iter()
+                .map(|receiver| match receiver {
+                    Receiver::Orchard(data) => check_orchard(ufvk, data),
+                    Receiver::Sapling(data) => check_sapling(ufvk, data),
+                    Receiver::P2pkh(hash) => check_p2pkh(ufvk, hash),
+                    Receiver::P2sh(_) => unchecked("p2sh"),
+                    Receiver::Unknown { .. } => unchecked("unknown"),
+                })
+                .collect();
+            ("unified", receivers)
+        }
+    };
+
+    let checked: Vec<&ReceiverOwnership> = receivers.iter().filter(|r| r.checkable).collect();
+    let owned = !checked.is_empty() && checked.iter().all(|r| r.owned);
+
+    Ok(OwnershipResult {
+        owned,
+        address_type: address_type.to_string(),
+        network: network_name(network).to_string(),
+        receivers,
+    })
+}
+
+fn check_sapling(ufvk: &UnifiedFullViewingKey, data: &[u8; 43]) -> ReceiverOwnership {
+    let mut result = not_owned("sapling");
+
+    let found = ufvk.sapling().and_then(|dfvk| {
+        let address = sapling_crypto::PaymentAddress::from_bytes(data)?;
+        dfvk.decrypt_diversifier(&address)
+    });
+
+    if let Some((index, scope)) = found {
+        result.owned = true;
+        result.scope = Some(scope_name(scope).to_string());
+        result.diversifier_index = Some(index_to_decimal(index.as_bytes()));
+    }
+    result
+}
+
+fn check_orchard(ufvk: &UnifiedFullViewingKey, data: &[u8; 43]) -> ReceiverOwnership {
+    let mut result = not_owned("orchard");
+
+    let (fvk, address) = match (
+        ufvk.orchard(),
+        Option::from(orchard::Address::from_raw_address_bytes(data)),
+    ) {
+        (Some(fvk), Some(address)) => (fvk, address),
+        _ => return result,
+    };
+
+    for scope in [Scope::External, Scope::Internal] {
+        if let Some(index) = fvk.to_ivk(scope).diversifier_index(&address) {
+            result.owned = true;
+            result.scope = Some(scope_name(scope).to_string());
+            result.diversifier_index = Some(index_to_decimal(index.as_bytes()));
+            break;
+        }
+    }
+    result
+}
+
+fn check_p2pkh(ufvk: &UnifiedFullViewingKey, hash: &[u8; 20]) -> ReceiverOwnership {
+    let mut result = not_owned("p2pkh");
+
+    let account = match ufvk.transparent() {
+        Some(account) => account,
+        None => return result,
+    };
+
+    let found = account
+        .derive_external_ivk()
+        .ok()
+        .and_then(|ivk| find_transparent_child(&ivk, hash))
+        .map(|index| (Scope::External, index))
+        .or_else(|| {
+            account
+                .derive_internal_ivk()
+                .ok()
+                .and_then(|ivk| find_transparent_child(&ivk, hash))
+                .map(|index| (Scope::Internal, index))
+        });
+
+    if let Some((scope, index)) = found {
+        result.owned = true;
+        result.scope = Some(scope_name(scope).to_string());
+        result.diversifier_index = Some(index.to_string());
+    }
+    result
+}
+
+fn find_transparent_child<K: IncomingViewingKey>(ivk: &K, hash: &[u8; 20]) -> Option<u32> {
+    let target = TransparentAddress::PublicKeyHash(*hash);
+
+    (0..TRANSPARENT_SEARCH_LIMIT).find(|&index| {
+        NonHardenedChildIndex::from_index(index)
+            .and_then(|child| ivk.derive_address(child).ok())
+            .is_some_and(|derived| derived == target)
+    })
+}
+
+fn not_owned(kind: &str) -> ReceiverOwnership {
+    ReceiverOwnership {
+        kind: kind.to_string(),
+        owned: false,
+        checkable: true,
+        scope: None,
+        diversifier_index: None,
+    }
+}
+
+fn unchecked(kind: &str) -> ReceiverOwnership {
+    ReceiverOwnership {
+        checkable: false,
+        ..not_owned(kind)
+    }
+}
+
+/// Render an 11-byte little-endian diversifier index as a decimal string
+fn index_to_decimal(bytes: &[u8; 11]) -> String {
+    let mut buf = [0u8; 16];
+    buf[..11].copy_from_slice(bytes);
+    u128::from_le_bytes(buf).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::parse_ufvk;
+    use crate::test_vectors::{
+        ORCHARD_ADDRESS_0, P2PKH_EXTERNAL, P2PKH_INTERNAL_5, SAPLING_ADDRESS_1,
+        SAPLING_ADDRESS_1_TESTNET, UFVK, UFVK_TRANSPARENT, UNIFIED_ADDRESS_1,
+        UNIFIED_ADDRESS_1_TESTNET, UNIFIED_ADDRESS_1_TRANSPARENT,
+    };
+
+    fn check(key: &str, address: &str) -> Result<OwnershipResult, String> {
+        let (network, ufvk) = parse_ufvk(key).unwrap();
+        check_address_ownership(&ufvk, network, address)
+    }
+
+    /// Kind, ownership, scope and index of each receiver
+    fn summary(result: &OwnershipResult) -> Vec<(&str, bool, Option<&str>, Option<&str>)> {
+        result
+            .receivers
+            .iter()
+            .map(|r| {
+                (
+                    r.kind.as_str(),
+                    r.owned,
+                    r.scope.as_deref(),
+                    r.diversifier_index.as_deref(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_shielded_receivers_and_their_index() {
+        let result = check(UFVK, SAPLING_ADDRESS_1).unwrap();
+        assert!(result.owned);
+        assert_eq!(
+            (result.address_type.as_str(), result.network.as_str()),
+            ("sapling", "main")
+        );
+        assert_eq!(
+            summary(&result),
+            [("sapling", true, Some("external"), Some("1"))]
+        );
+
+        let result = check(UFVK, UNIFIED_ADDRESS_1).unwrap();
+        assert!(result.owned);
+        assert_eq!(result.address_type, "unified");
+        assert_eq!(
+            summary(&result),
+            [
+                ("sapling", true, Some("external"), Some("1")),
+                ("orchard", true, Some("external"), Some("1")),
+            ]
+        );
+
+        let result = check(UFVK, ORCHARD_ADDRESS_0).unwrap();
+        assert_eq!(
+            summary(&result),
+            [("orchard", true, Some("external"), Some("0"))]
+        );
+    }
+
+    #[test]
+    fn finds_transparent_receivers_by_derivation() {
+        let result = check(UFVK_TRANSPARENT, UNIFIED_ADDRESS_1_TRANSPARENT).unwrap();
+        assert!(result.owned);
+        assert_eq!(result.receivers[0].kind, "p2pkh");
+        assert_eq!(result.receivers[0].diversifier_index.as_deref(), Some("1"));
+
+        let result = check(UFVK_TRANSPARENT, P2PKH_EXTERNAL[2]).unwrap();
+        assert_eq!(result.address_type, "p2pkh");
+        assert_eq!(
+            summary(&result),
+            [("p2pkh", true, Some("external"), Some("2"))]
+        );
+
+        let result = check(UFVK_TRANSPARENT, P2PKH_INTERNAL_5).unwrap();
+        assert_eq!(
+            summary(&result),
+            [("p2pkh", true, Some("internal"), Some("5"))]
+        );
+    }
+
+    #[test]
+    fn receivers_the_key_lacks_are_not_owned() {
+        // The key has no transparent item, so the receiver is checked and
+        // not found
+        let result = check(UFVK, P2PKH_EXTERNAL[0]).unwrap();
+        assert!(!result.owned);
+        assert_eq!(summary(&result), [("p2pkh", false, None, None)]);
+        assert!(result.receivers[0].checkable);
+
+        // P2SH receivers can't be derived from a UFVK at all
+        let result = check(UFVK, "t3PELj6mSGGCMT6NkGQkBTTvD8HLBsnvXZ4").unwrap();
+        assert!(!result.owned);
+        assert_eq!(result.address_type, "p2sh");
+        assert!(!result.receivers[0].checkable);
+    }
+
+    #[test]
+    fn other_addresses_are_not_owned() {
+        // P2PKH to HASH160 2222..., beyond anything the key derives
+        let result = check(UFVK_TRANSPARENT, "t1Lz5sUzNoMBWmq8qk1zS4q5A54RgF5e4Ua").unwrap();
+        assert!(!result.owned);
+        assert_eq!(summary(&result), [("p2pkh", false, None, None)]);
+    }
+
+    #[test]
+    fn rejects_addresses_for_another_network() {
+        // The transparent address is this key's external index 1 on testnet;
+        // it is rejected without searching the key's transparent chains
+        for (key, address) in [
+            (UFVK, SAPLING_ADDRESS_1_TESTNET),
+            (UFVK, UNIFIED_ADDRESS_1_TESTNET),
+            (UFVK_TRANSPARENT, "tmKioeqmk7VNS8yfMMbeLwbU1jhRfGs7jcf"),
+        ] {
+            let error = check(key, address).unwrap_err();
+            assert_eq!(
+                error,
+                "Address is for the test network but the viewing key is for main"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        let error = check(UFVK, "zs1notanaddress").unwrap_err();
+        assert!(error.starts_with("Invalid address"), "{}", error);
+    }
+}
@@ -0,0 +1,88 @@
+//! Keys, addresses and transactions shared by the unit tests
+//!
+//! Expected values were computed outside this crate from the key bytes: BIP 32
+//! public derivation and HASH160 for transparent receivers, ZIP 32 FF1
+//! diversifiers and `[ivk] g_d` for Sapling and Orchard receivers, and ZIP 316
+//! F4Jumble/Bech32m for the unified encodings.
+
+/// Mainnet UFVK with Sapling and Orchard items and no transparent item
+pub const UFVK: &str = "uview19av5rvg3syp6x6vkklu5r7lag67plc388pjr34wwcnrlgkhae9p0v9nczgev90akzavs2k3tmn9mvj24vvu9kl3lafjdqjj9w0dpjl8a39p2kv2hd53z0q9cy0vc29zlhk5k27rxx8057gla7jzp9nplxpta62lnc94wneqtwdjl2kmm4ly0kgh9gw323d49hxtv9a8ylyke8tr22jygxnjzmgps08uyay52slx2fyhplkhl2mpae98gacsse0jfffc4s6k4zu05qqsxkxr4mwcnaquspdqw4vj5m0ae53ctu2ka0qw3ksspwe3ahhu2x26rjchvcv76erc6gmxwyge3qn3y3js6xdtaxtgjcspf8sy6qtvh757p0r63qh5yjxegpgcjgpanf";
+
+/// `UFVK`'s Sapling and Orchard items re-encoded for testnet
+pub const UFVK_TESTNET: &str = "uviewtest1y7557m04y6hyzf4v8jj0kmm8sjw36dqxn3s3zf3dpk6l8w5cxhd8g5dfwlj8qxrpnu9hs8gway4qfd8kkdd0zx8m2jxksstqelzast05z6ved6d4kycvmfmsjzz7swd4wyj7qutdwvsypr4t3z3m50vr4dlzr9lsugeg7dmesfj33pwauj7td5t4q08xlxsw30vcqcaaty87m8vkddczyj32ha5ud6jlff07gjq59nvfq4mkeznev4h9pmn4at6jjunfng6xlu4mupxsnt3p026xsa5pcls9x22fxfzvuz5ylzy9n065lv4u7cucst85yvrq465s4zt5hzp95zw7hyqcyk6qjey7d8497cplrwahss4249m6x4lxps5lqj24h8m7e6crarlg5";
+
+/// `UFVK` plus a P2PKH item holding BIP 32 test vector 1's `m/0'` public key
+/// (chain code 47fdacbd..., key 035a7846...)
+pub const UFVK_TRANSPARENT: &str = "uview1azp7r5lp0urde55q84x7nuwqmuhhfxz542k7sltdvvhg07qn9ngm9r6a6vugxfg06tfv275wd64dtp6q5el67r2y7jrl4hfmkg6jwv076we8elvzjkq8aaqtlkev4ghwlqwqezdj4mq96gsjvph97systjepk0r2r5236vlq7x2t7qxu3exzsl3yxmaxlpdsxdgqqawkex5jlpr4snpm9ep6yxk8u8lf05zw2595vayrqx9s4546p4y6tlhet373syc6s7yr8lxgqzv2efnxmy67r08zw9wvldn2q52ncrqz9akqfzhj86g9q5s8nsdux6erpsnrj3t3a6spqt6c9muud7khu2fs445v5n84nkvnp39hx65j63z5lqrt3afatscwlu2pd568jhawqaed2gluk7dz2n2j7y7v50u594ajd2tx67rxk7lztgvy3del6l9swt6hya5st25v4rnt7j5j8y7v6a328wzvss9ar8ld708p9s3sepck";
+
+/// ZIP 32 fingerprint of `UFVK`'s Sapling item (ak || nk || ovk)
+pub const SAPLING_FINGERPRINT: &str =
+    "f8034b57b40f81724f73d05ccd78c5aff02941e4eb65b74140f2fd82ffad499b";
+
+/// ZIP 32 fingerprint of `UFVK`'s Orchard item (ak || nk || rivk)
+pub const ORCHARD_FINGERPRINT: &str =
+    "244a6a5d29ae978263d1fc5b5f41396fba4d7addc34b584123d3a3115f0e698d";
+
+/// BIP 32 identifier of the `m/0'` key in `UFVK_TRANSPARENT`
+pub const P2PKH_FINGERPRINT: &str = "5c1bd648ed23aa5fd50ba52b2457c11e9e80a6a7";
+
+/// Sapling address of `UFVK` at diversifier index 1; index 0 is not a valid
+/// Sapling diversifier for this key
+pub const SAPLING_ADDRESS_1: &str =
+    "zs1m2f7tsugas2t2rsu58wmguehpv60qwmlq9nawk68p30465jlw45hvumgqjzzt4htsdrgvhhvqd7";
+
+/// Sapling address of `UFVK` at diversifier index 2
+pub const SAPLING_ADDRESS_2: &str =
+    "zs1fl7r5hfaveyk8tqf7yj5p6vttjp7ews3sgkz8ry903ucm6w6q0kmlmp4z7908smhpc2yzxshauj";
+
+/// `SAPLING_ADDRESS_1` encoded for testnet
+pub const SAPLING_ADDRESS_1_TESTNET: &str =
+    "ztestsapling1m2f7tsugas2t2rsu58wmguehpv60qwmlq9nawk68p30465jlw45hvumgqjzzt4htsdrgvlq7r22";
+
+/// Orchard-only unified address of `UFVK` at diversifier index 0
+pub const ORCHARD_ADDRESS_0: &str = "u1wq5cwttxz34sl2mwtg0ctpyu6h2qla76sda89kua7m2q9unyqe7pe3n2r4rtjnet74txg0e6mpvkcduh3ly0rjpa94xhqn6xdyqnfsz2";
+
+/// Orchard-only unified address of `UFVK` at diversifier index 1
+pub const ORCHARD_ADDRESS_1: &str = "u1y5jnsvc3mfrv65umsk29dvtcn3etmy8wpwsgh8jx23q8wkysyfzd20792jmqywyf8mtmz74men8f3h65kvde307ydl7nn0f49gq593w0";
+
+/// Sapling + Orchard unified address of `UFVK` at diversifier index 1
+pub const UNIFIED_ADDRESS_1: &str = "u182y5fc9jds6dxtytlche0ay20pqxqeqtusgasf5pljjp674gejmrnul3377j7crahmxg2nlknuhhmv3gmx2scdjse3yfsnn8cfmgxkle35nm2h7v7d8kz08n09w3yyswx32vzv3l7wut0wkkrfcstcxqpj5jcmvdrx56kvhmhqyq8ntl";
+
+/// `UNIFIED_ADDRESS_1` encoded for testnet
+pub const UNIFIED_ADDRESS_1_TESTNET: &str = "utest1jhwu68at2fu87rrq8gwxxse6tm99g7efwhjnvls2afk4y5dtchyxmyhmk0ww7uxw0g9xwrw9wujq7kqhkyn9jwr3udurwqquu3z8hx9n2alhl7jtqcm46g6rmcyy40uv8cl49jnc32fp0e8zp9tgyqsxwdnafwvpnxlcnr7exgyn9st4";
+
+/// P2PKH + Sapling + Orchard unified address of `UFVK_TRANSPARENT` at index 1
+pub const UNIFIED_ADDRESS_1_TRANSPARENT: &str = "u18nqgawfd239cj75h3w2q64jy47s6766dh7t879vhp29uqt0dasjkfeqmhnu0me9zmukjncl402cp4y8vj9fyd6fu447qg0h7ptmwhj4d93sl696vajx62pct3vqv5fwrur8lw88y86u7tdks7vdzhhj0q4rzv30yjx4zf75za0umevdcsg8h2cc9e6wn9434jn37pmxgsd3hxjeua3m";
+
+/// P2PKH addresses of `UFVK_TRANSPARENT`'s external chain at indices 0..3
+pub const P2PKH_EXTERNAL: [&str; 3] = [
+    "t1UoHt1HhNpgLw593KM5NGg31dDh7F51aX1",
+    "t1Tt4L1HLiprvzjTugsLc5voG8iLqmN9bGU",
+    "t1VtwvsRb2JEfZ9jhUBLpJQnvvFj7qYh9XG",
+];
+
+/// P2PKH address of `UFVK_TRANSPARENT`'s internal (change) chain at index 5
+pub const P2PKH_INTERNAL_5: &str = "t1UM1uKxvyyp8WjYZy188WC53s7FA8zQ7qo";
+
+/// Mainnet genesis block coinbase, a v1 transaction
+pub const GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff071f0104455a6361736830623963346565663862376363343137656535303031653335303039383462366665613335363833613763616331343161303433633432303634383335643334ffffffff010000000000000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+/// Txid of `GENESIS_COINBASE`, the genesis block's Merkle root
+pub const GENESIS_COINBASE_TXID: &str =
+    "c4eaa58879081de3c24a7b117ed2b28300e7ec4c4c1dff1d3f1268b7857a4ddb";
+
+/// Transparent-only v5 transaction: one input spending 1111...:0, one 20000
+/// zatoshi P2PKH output to HASH160 2222..., lock time 1
+pub const V5_TRANSPARENT: &str = "050000800a27a726b4d0d6c201000000000000000111111111111111111111111111111111111111111111111111111111111111110000000000ffffffff01204e0000000000001976a914222222222222222222222222222222222222222288ac000000";
+
+/// ZIP 244 txid of `V5_TRANSPARENT`
+pub const V5_TRANSPARENT_TXID: &str =
+    "9ab4c6ea7811a0ec9bd82919a923c1e34db6cff750c073e7bd242fe4028feaee";
+
+/// Transparent-only Sapling-era v4 transaction: one input spending 3333...:2,
+/// one 50000 zatoshi P2PKH output to HASH160 4444...
+pub const V4_TRANSPARENT: &str = "0400008085202f890133333333333333333333333333333333333333333333333333333333333333330200000003510101feffffff0150c30000000000001976a914444444444444444444444444444444444444444488ac00000000000000000000000000000000000000";
+
+/// Txid of `V4_TRANSPARENT`, the double SHA-256 of its bytes
+pub const V4_TRANSPARENT_TXID: &str =
+    "b4e3589cfdfd19dc5a8779fbc75072e111e1f8e136caab062b92420c073ffe78";
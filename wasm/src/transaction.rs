@@ -0,0 +1,724 @@
+use serde::{Serialize, Serializer};
+
+use crate::network::Network;
+
+const OVERWINTER_VERSION_GROUP_ID: u32 = 0x03C4_8270;
+const SAPLING_VERSION_GROUP_ID: u32 = 0x892F_2085;
+const NU5_VERSION_GROUP_ID: u32 = 0x26A7_270A;
+
+const GROTH_PROOF_SIZE: usize = 192;
+const PHGR_PROOF_SIZE: usize = 296;
+const SPEND_AUTH_SIG_SIZE: usize = 64;
+const BINDING_SIG_SIZE: usize = 64;
+const ENC_CIPHERTEXT_SIZE: usize = 580;
+const OUT_CIPHERTEXT_SIZE: usize = 80;
+const JOINSPLIT_CIPHERTEXT_SIZE: usize = 601;
+
+/// Largest CompactSize accepted, matching zcashd's MAX_SIZE
+const MAX_COMPACT_SIZE: u64 = 0x0200_0000;
+
+/// A fully decoded transaction
+///
+/// 32-byte commitments, keys and nullifiers are hex-encoded in their
+/// serialized byte order; transaction IDs use the conventional reversed
+/// display order.
+#[derive(Serialize, Debug, Clone)]
+pub struct DecodedTransaction {
+    pub header: TransactionHeader,
+    /// Serialized size in bytes
+    pub size: usize,
+    pub lock_time: u32,
+    /// Absent before Overwinter
+    pub expiry_height: Option<u32>,
+    pub transparent_inputs: Vec<TransparentInput>,
+    pub transparent_outputs: Vec<TransparentOutput>,
+    pub sapling_spends: Vec<SaplingSpend>,
+    pub sapling_outputs: Vec<SaplingOutput>,
+    /// Net Sapling value leaving the pool, in zatoshis
+    pub value_balance_sapling: i64,
+    pub orchard: Option<OrchardBundle>,
+    /// Net Orchard value leaving the pool, in zatoshis
+    pub value_balance_orchard: i64,
+    pub joinsplits: Vec<JoinSplit>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TransactionHeader {
+    /// The `fOverwintered` flag (top bit of the header word)
+    pub overwintered: bool,
+    /// Transaction version with the `fOverwintered` bit masked off
+    pub version: u32,
+    #[serde(serialize_with = "serialize_u32_hex")]
+    pub version_group_id: Option<u32>,
+    /// Only serialized in the header from v5 onwards
+    #[serde(serialize_with = "serialize_u32_hex")]
+    pub consensus_branch_id: Option<u32>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TransparentInput {
+    #[serde(serialize_with = "serialize_display_hex")]
+    pub prevout_txid: [u8; 32],
+    pub prevout_index: u32,
+    #[serde(serialize_with = "serialize_hex")]
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+    pub coinbase: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TransparentOutput {
+    /// Value in zatoshis
+    pub value: i64,
+    #[serde(serialize_with = "serialize_hex")]
+    pub script_pubkey: Vec<u8>,
+    /// Set for standard P2PKH and P2SH scripts
+    pub address: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SaplingSpend {
+    #[serde(serialize_with = "serialize_hex")]
+    pub cv: [u8; 32],
+    /// Per-spend in v4; v5 shares a single anchor across the bundle
+    #[serde(serialize_with = "serialize_hex")]
+    pub anchor: [u8; 32],
+    #[serde(serialize_with = "serialize_hex")]
+    pub nullifier: [u8; 32],
+    #[serde(serialize_with = "serialize_hex")]
+    pub rk: [u8; 32],
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SaplingOutput {
+    #[serde(serialize_with = "serialize_hex")]
+    pub cv: [u8; 32],
+    #[serde(serialize_with = "serialize_hex")]
+    pub cmu: [u8; 32],
+    #[serde(serialize_with = "serialize_hex")]
+    pub ephemeral_key: [u8; 32],
+    #[serde(serialize_with = "serialize_hex")]
+    pub enc_ciphertext: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub out_ciphertext: Vec<u8>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OrchardBundle {
+    pub flags: OrchardFlags,
+    #[serde(serialize_with = "serialize_hex")]
+    pub anchor: [u8; 32],
+    pub actions: Vec<OrchardAction>,
+    /// Size of the aggregated Halo 2 proof in bytes
+    pub proof_size: usize,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct OrchardFlags {
+    pub spends_enabled: bool,
+    pub outputs_enabled: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OrchardAction {
+    #[serde(serialize_with = "serialize_hex")]
+    pub cv_net: [u8; 32],
+    #[serde(serialize_with = "serialize_hex")]
+    pub nullifier: [u8; 32],
+    #[serde(serialize_with = "serialize_hex")]
+    pub rk: [u8; 32],
+    #[serde(serialize_with = "serialize_hex")]
+    pub cmx: [u8; 32],
+    #[serde(serialize_with = "serialize_hex")]
+    pub ephemeral_key: [u8; 32],
+    #[serde(serialize_with = "serialize_hex")]
+    pub enc_ciphertext: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub out_ciphertext: Vec<u8>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct JoinSplit {
+    /// Value entering the Sprout pool, in zatoshis
+    pub vpub_old: u64,
+    /// Value leaving the Sprout pool, in zatoshis
+    pub vpub_new: u64,
+    #[serde(serialize_with = "serialize_hex")]
+    pub anchor: [u8; 32],
+    #[serde(serialize_with = "serialize_hex_list")]
+    pub nullifiers: [[u8; 32]; 2],
+    #[serde(serialize_with = "serialize_hex_list")]
+    pub commitments: [[u8; 32]; 2],
+    /// "phgr13" before Sapling, "groth16" from v4 on
+    pub proof_system: String,
+}
+
+impl TransactionHeader {
+    /// Human-readable name of the network upgrade that introduced this format
+    pub fn format_name(&self) -> &'static str {
+        match self.version {
+            1 | 2 => "Sprout",
+            3 => "Overwinter",
+            4 => "Sapling",
+            5 => "NU5",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// Decode a raw transaction of any version from v1 (Sprout) to v5 (NU5)
+///
+/// `network` selects the Base58Check prefixes used to render transparent
+/// output addresses.
+pub fn decode_transaction(tx_bytes: &[u8], network: Network) -> Result<DecodedTransaction, String> {
+    let tx = decode_transaction_prefix(tx_bytes, network)?;
+
+    if tx.size != tx_bytes.len() {
+        return Err(format!(
+            "Transaction has {} unexpected trailing bytes",
+            tx_bytes.len() - tx.size
+        ));
+    }
+    Ok(tx)
+}
+
+/// Decode the transaction at the start of `bytes`, which may be followed by
+/// others as in a block; `size` is the length it occupies
+pub fn decode_transaction_prefix(
+    bytes: &[u8],
+    network: Network,
+) -> Result<DecodedTransaction, String> {
+    let mut reader = Reader::new(bytes);
+
+    let header_word = reader.u32()?;
+    let overwintered = header_word >> 31 == 1;
+    let version = header_word & 0x7FFF_FFFF;
+
+    let version_group_id = if overwintered {
+        Some(reader.u32()?)
+    } else {
+        None
+    };
+
+    match (overwintered, version, version_group_id) {
+        (false, 1 | 2, _) => {}
+        (true, 3, Some(OVERWINTER_VERSION_GROUP_ID))
+        | (true, 4, Some(SAPLING_VERSION_GROUP_ID))
+        | (true, 5, Some(NU5_VERSION_GROUP_ID)) => {}
+        (true, _, Some(group)) => {
+            return Err(format!(
+                "Unsupported transaction version {} with version group ID {:08x}",
+                version, group
+            ))
+        }
+        _ => return Err(format!("Unsupported transaction version {}", version)),
+    }
+
+    let mut tx = DecodedTransaction {
+        header: TransactionHeader {
+            overwintered,
+            version,
+            version_group_id,
+            consensus_branch_id: None,
+        },
+        size: 0,
+        lock_time: 0,
+        expiry_height: None,
+        transparent_inputs: Vec::new(),
+        transparent_outputs: Vec::new(),
+        sapling_spends: Vec::new(),
+        sapling_outputs: Vec::new(),
+        value_balance_sapling: 0,
+        orchard: None,
+        value_balance_orchard: 0,
+        joinsplits: Vec::new(),
+    };
+
+    if version == 5 {
+        read_v5(&mut reader, &mut tx)?;
+    } else {
+        read_pre_v5(&mut reader, &mut tx)?;
+    }
+
+    tx.size = reader.pos;
+
+    for output in &mut tx.transparent_outputs {
+        output.address = script_address(network, &output.script_pubkey);
+    }
+
+    Ok(tx)
+}
+
+/// v1–v4 layout: transparent, then (v4) Sapling, then JoinSplits
+fn read_pre_v5(reader: &mut Reader, tx: &mut DecodedTransaction) -> Result<(), String> {
+    let version = tx.header.version;
+
+    read_transparent(reader, tx)?;
+    tx.lock_time = reader.u32()?;
+
+    if tx.header.overwintered {
+        tx.expiry_height = Some(reader.u32()?);
+    }
+
+    if version == 4 {
+        tx.value_balance_sapling = reader.i64()?;
+
+        let spend_count = reader.compact_size()?;
+        for _ in 0..spend_count {
+            let cv = reader.array()?;
+            let anchor = reader.array()?;
+            let nullifier = reader.array()?;
+            let rk = reader.array()?;
+            reader.skip(GROTH_PROOF_SIZE + SPEND_AUTH_SIG_SIZE)?;
+            tx.sapling_spends.push(SaplingSpend {
+                cv,
+                anchor,
+                nullifier,
+                rk,
+            });
+        }
+
+        let output_count = reader.compact_size()?;
+        for _ in 0..output_count {
+            let output = read_sapling_output(reader)?;
+            reader.skip(GROTH_PROOF_SIZE)?;
+            tx.sapling_outputs.push(output);
+        }
+    }
+
+    if version >= 2 {
+        let (proof_system, proof_size) = if version >= 4 {
+            ("groth16", GROTH_PROOF_SIZE)
+        } else {
+            ("phgr13", PHGR_PROOF_SIZE)
+        };
+
+        let joinsplit_count = reader.compact_size()?;
+        for _ in 0..joinsplit_count {
+            let vpub_old = reader.u64()?;
+            let vpub_new = reader.u64()?;
+            let anchor = reader.array()?;
+            let nullifiers = [reader.array()?, reader.array()?];
+            let commitments = [reader.array()?, reader.array()?];
+            // ephemeralKey, randomSeed, vmacs
+            reader.skip(32 + 32 + 64)?;
+            reader.skip(proof_size + 2 * JOINSPLIT_CIPHERTEXT_SIZE)?;
+            tx.joinsplits.push(JoinSplit {
+                vpub_old,
+                vpub_new,
+                anchor,
+                nullifiers,
+                commitments,
+                proof_system: proof_system.to_string(),
+            });
+        }
+
+        if joinsplit_count > 0 {
+            // joinSplitPubKey, joinSplitSig
+            reader.skip(32 + 64)?;
+        }
+    }
+
+    if version == 4 && !(tx.sapling_spends.is_empty() && tx.sapling_outputs.is_empty()) {
+        reader.skip(BINDING_SIG_SIZE)?;
+    }
+
+    Ok(())
+}
+
+/// v5 layout (ZIP 225): header, transparent, Sapling, Orchard
+fn read_v5(reader: &mut Reader, tx: &mut DecodedTransaction) -> Result<(), String> {
+    tx.header.consensus_branch_id = Some(reader.u32()?);
+    tx.lock_time = reader.u32()?;
+    tx.expiry_height = Some(reader.u32()?);
+
+    read_transparent(reader, tx)?;
+
+    // Sapling
+    let spend_count = reader.compact_size()?;
+    let mut spends = Vec::new();
+    for _ in 0..spend_count {
+        spends.push((reader.array()?, reader.array()?, reader.array()?));
+    }
+
+    let output_count = reader.compact_size()?;
+    for _ in 0..output_count {
+        let output = read_sapling_output(reader)?;
+        tx.sapling_outputs.push(output);
+    }
+
+    if spend_count + output_count > 0 {
+        tx.value_balance_sapling = reader.i64()?;
+    }
+
+    if spend_count > 0 {
+        let anchor = reader.array()?;
+        tx.sapling_spends = spends
+            .into_iter()
+            .map(|(cv, nullifier, rk)| SaplingSpend {
+                cv,
+                anchor,
+                nullifier,
+                rk,
+            })
+            .collect();
+    }
+
+    reader.skip(spend_count as usize * (GROTH_PROOF_SIZE + SPEND_AUTH_SIG_SIZE))?;
+    reader.skip(output_count as usize * GROTH_PROOF_SIZE)?;
+    if spend_count + output_count > 0 {
+        reader.skip(BINDING_SIG_SIZE)?;
+    }
+
+    // Orchard
+    let action_count = reader.compact_size()?;
+    let mut actions = Vec::new();
+    for _ in 0..action_count {
+        actions.push(OrchardAction {
+            cv_net: reader.array()?,
+            nullifier: reader.array()?,
+            rk: reader.array()?,
+            cmx: reader.array()?,
+            ephemeral_key: reader.array()?,
+            enc_ciphertext: reader.take(ENC_CIPHERTEXT_SIZE)?.to_vec(),
+            out_ciphertext: reader.take(OUT_CIPHERTEXT_SIZE)?.to_vec(),
+        });
+    }
+
+    if action_count > 0 {
+        let flags = reader.u8()?;
+        if flags & !0b11 != 0 {
+            return Err(format!("Invalid Orchard flags {:#04x}", flags));
+        }
+
+        tx.value_balance_orchard = reader.i64()?;
+        let anchor = reader.array()?;
+        let proof_size = reader.compact_size()? as usize;
+        reader.skip(proof_size)?;
+        reader.skip(action_count as usize * SPEND_AUTH_SIG_SIZE + BINDING_SIG_SIZE)?;
+
+        tx.orchard = Some(OrchardBundle {
+            flags: OrchardFlags {
+                spends_enabled: flags & 0b01 != 0,
+                outputs_enabled: flags & 0b10 != 0,
+            },
+            anchor,
+            actions,
+            proof_size,
+        });
+    }
+
+    Ok(())
+}
+
+fn read_transparent(reader: &mut Reader, tx: &mut DecodedTransaction) -> Result<(), String> {
+    let input_count = reader.compact_size()?;
+    for _ in 0..input_count {
+        let prevout_txid: [u8; 32] = reader.array()?;
+        let prevout_index = reader.u32()?;
+        let script_len = reader.compact_size()? as usize;
+        let script_sig = reader.take(script_len)?.to_vec();
+        let sequence = reader.u32()?;
+        tx.transparent_inputs.push(TransparentInput {
+            coinbase: prevout_txid == [0; 32] && prevout_index == u32::MAX,
+            prevout_txid,
+            prevout_index,
+            script_sig,
+            sequence,
+        });
+    }
+
+    let output_count = reader.compact_size()?;
+    for _ in 0..output_count {
+        let value = reader.i64()?;
+        let script_len = reader.compact_size()? as usize;
+        let script_pubkey = reader.take(script_len)?.to_vec();
+        tx.transparent_outputs.push(TransparentOutput {
+            value,
+            address: None,
+            script_pubkey,
+        });
+    }
+
+    Ok(())
+}
+
+fn read_sapling_output(reader: &mut Reader) -> Result<SaplingOutput, String> {
+    Ok(SaplingOutput {
+        cv: reader.array()?,
+        cmu: reader.array()?,
+        ephemeral_key: reader.array()?,
+        enc_ciphertext: reader.take(ENC_CIPHERTEXT_SIZE)?.to_vec(),
+        out_ciphertext: reader.take(OUT_CIPHERTEXT_SIZE)?.to_vec(),
+    })
+}
+
+/// Base58Check-encode the destination of a standard P2PKH or P2SH script
+fn script_address(network: Network, script: &[u8]) -> Option<String> {
+    // Testnet and regtest share the `tm`/`t2` prefixes
+    let (p2pkh, p2sh) = match network {
+        Network::Main => ([0x1c, 0xb8], [0x1c, 0xbd]),
+        Network::Test | Network::Regtest => ([0x1d, 0x25], [0x1c, 0xba]),
+    };
+
+    let (prefix, hash) = match script {
+        [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] if hash.len() == 20 => (p2pkh, hash),
+        [0xa9, 0x14, hash @ .., 0x87] if hash.len() == 20 => (p2sh, hash),
+        _ => return None,
+    };
+
+    let mut payload = prefix.to_vec();
+    payload.extend_from_slice(hash);
+    Some(bs58::encode(payload).with_check().into_string())
+}
+
+/// Bounds-checked cursor over serialized transaction bytes
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.remaining() {
+            return Err(format!(
+                "Transaction truncated: needed {} bytes at offset {}, {} available",
+                len,
+                self.pos,
+                self.remaining()
+            ));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), String> {
+        self.take(len).map(|_| ())
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    /// Bitcoin-style CompactSize, rejecting non-canonical encodings
+    fn compact_size(&mut self) -> Result<u64, String> {
+        let offset = self.pos;
+        let (value, min) = match self.u8()? {
+            0xfd => (u16::from_le_bytes(self.array()?) as u64, 0xfd),
+            0xfe => (u32::from_le_bytes(self.array()?) as u64, 0x1_0000),
+            0xff => (self.u64()?, 0x1_0000_0000),
+            n => (n as u64, 0),
+        };
+
+        if value < min {
+            return Err(format!("Non-canonical CompactSize at offset {}", offset));
+        }
+        if value > MAX_COMPACT_SIZE {
+            return Err(format!(
+                "CompactSize {} at offset {} is too large",
+                value, offset
+            ));
+        }
+        Ok(value)
+    }
+}
+
+fn serialize_hex<S: Serializer, T: AsRef<[u8]>>(bytes: &T, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&hex::encode(bytes))
+}
+
+fn serialize_display_hex<S: Serializer>(bytes: &[u8; 32], s: S) -> Result<S::Ok, S::Error> {
+    let mut reversed = *bytes;
+    reversed.reverse();
+    s.serialize_str(&hex::encode(reversed))
+}
+
+fn serialize_hex_list<S: Serializer>(items: &[[u8; 32]; 2], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(items.iter().map(hex::encode))
+}
+
+fn serialize_u32_hex<S: Serializer>(value: &Option<u32>, s: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => s.serialize_str(&format!("{:08x}", value)),
+        None => s.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{GENESIS_COINBASE, V5_TRANSPARENT};
+
+    fn decode_hex(tx_hex: &str, network: Network) -> Result<DecodedTransaction, String> {
+        decode_transaction(&hex::decode(tx_hex).unwrap(), network)
+    }
+
+    /// v5 transaction with no transparent parts, one zero-filled Sapling
+    /// output and one zero-filled Orchard action with the given flags
+    fn v5_shielded(flags: u8) -> Vec<u8> {
+        let mut tx = hex::decode("050000800a27a726b4d0d6c20000000000000000").unwrap();
+        // No transparent inputs or outputs, no Sapling spends, one output
+        tx.extend([0x00, 0x00, 0x00, 0x01]);
+        tx.extend([0u8; 32 * 3 + ENC_CIPHERTEXT_SIZE + OUT_CIPHERTEXT_SIZE]);
+        tx.extend((-5000i64).to_le_bytes());
+        tx.extend([0u8; GROTH_PROOF_SIZE + BINDING_SIG_SIZE]);
+        // One Orchard action
+        tx.push(0x01);
+        tx.extend([0x07u8; 32 * 5 + ENC_CIPHERTEXT_SIZE + OUT_CIPHERTEXT_SIZE]);
+        tx.push(flags);
+        tx.extend(5000i64.to_le_bytes());
+        tx.extend([0x09u8; 32]);
+        // A 3-byte proof, then the spend authorization and binding signatures
+        tx.extend([0x03, 0xaa, 0xbb, 0xcc]);
+        tx.extend([0u8; SPEND_AUTH_SIG_SIZE + BINDING_SIG_SIZE]);
+        tx
+    }
+
+    #[test]
+    fn decodes_genesis_coinbase() {
+        let tx = decode_hex(GENESIS_COINBASE, Network::Main).unwrap();
+
+        assert_eq!(tx.header.version, 1);
+        assert!(!tx.header.overwintered);
+        assert_eq!(tx.header.format_name(), "Sprout");
+        assert_eq!(tx.header.version_group_id, None);
+        assert_eq!(tx.header.consensus_branch_id, None);
+        assert_eq!(tx.size, 204);
+        assert_eq!((tx.lock_time, tx.expiry_height), (0, None));
+
+        assert_eq!(tx.transparent_inputs.len(), 1);
+        let input = &tx.transparent_inputs[0];
+        assert!(input.coinbase);
+        assert_eq!(input.prevout_index, u32::MAX);
+        assert!(hex::encode(&input.script_sig).starts_with("04ffff071f0104455a63617368"));
+
+        // A zero-value pay-to-pubkey output, which has no address
+        assert_eq!(tx.transparent_outputs.len(), 1);
+        assert_eq!(tx.transparent_outputs[0].value, 0);
+        assert_eq!(tx.transparent_outputs[0].script_pubkey.len(), 67);
+        assert_eq!(tx.transparent_outputs[0].address, None);
+
+        assert!(tx.sapling_spends.is_empty() && tx.sapling_outputs.is_empty());
+        assert!(tx.orchard.is_none() && tx.joinsplits.is_empty());
+    }
+
+    #[test]
+    fn decodes_v5_transparent_transaction() {
+        let tx = decode_hex(V5_TRANSPARENT, Network::Main).unwrap();
+
+        assert_eq!(tx.header.version, 5);
+        assert!(tx.header.overwintered);
+        assert_eq!(tx.header.version_group_id, Some(NU5_VERSION_GROUP_ID));
+        assert_eq!(tx.header.consensus_branch_id, Some(0xC2D6_D0B4));
+        assert_eq!(tx.size, 100);
+        assert_eq!((tx.lock_time, tx.expiry_height), (1, Some(0)));
+
+        let input = &tx.transparent_inputs[0];
+        assert_eq!(input.prevout_txid, [0x11; 32]);
+        assert_eq!((input.prevout_index, input.coinbase), (0, false));
+        assert!(input.script_sig.is_empty());
+
+        let output = &tx.transparent_outputs[0];
+        assert_eq!(output.value, 20_000);
+        assert_eq!(
+            output.address.as_deref(),
+            Some("t1Lz5sUzNoMBWmq8qk1zS4q5A54RgF5e4Ua")
+        );
+
+        let json = serde_json::to_value(&tx).unwrap();
+        assert_eq!(json["header"]["version_group_id"], "26a7270a");
+        assert_eq!(json["header"]["consensus_branch_id"], "c2d6d0b4");
+        assert_eq!(
+            json["transparent_inputs"][0]["prevout_txid"],
+            "11".repeat(32)
+        );
+    }
+
+    #[test]
+    fn transparent_addresses_follow_the_network() {
+        let tx = decode_hex(V5_TRANSPARENT, Network::Test).unwrap();
+        assert_eq!(
+            tx.transparent_outputs[0].address.as_deref(),
+            Some("tmCpqCKUnC1h1v5LHQkJAvVjug3WVePS7t7")
+        );
+    }
+
+    #[test]
+    fn decodes_v5_shielded_bundles() {
+        let bytes = v5_shielded(0b11);
+        let tx = decode_transaction(&bytes, Network::Main).unwrap();
+        assert_eq!(tx.size, bytes.len());
+
+        assert_eq!(tx.sapling_outputs.len(), 1);
+        assert_eq!(
+            tx.sapling_outputs[0].enc_ciphertext.len(),
+            ENC_CIPHERTEXT_SIZE
+        );
+        assert_eq!(tx.value_balance_sapling, -5000);
+
+        let orchard = tx.orchard.unwrap();
+        assert_eq!(orchard.actions.len(), 1);
+        assert_eq!(orchard.actions[0].cmx, [0x07; 32]);
+        assert!(orchard.flags.spends_enabled && orchard.flags.outputs_enabled);
+        assert_eq!(orchard.anchor, [0x09; 32]);
+        assert_eq!(orchard.proof_size, 3);
+        assert_eq!(tx.value_balance_orchard, 5000);
+    }
+
+    #[test]
+    fn rejects_malformed_transactions() {
+        let error = decode_transaction(&v5_shielded(0b100), Network::Main).unwrap_err();
+        assert!(error.starts_with("Invalid Orchard flags"), "{}", error);
+
+        let mut bytes = hex::decode(V5_TRANSPARENT).unwrap();
+        bytes.push(0);
+        let error = decode_transaction(&bytes, Network::Main).unwrap_err();
+        assert_eq!(error, "Transaction has 1 unexpected trailing bytes");
+
+        bytes.truncate(60);
+        let error = decode_transaction(&bytes, Network::Main).unwrap_err();
+        assert!(error.starts_with("Transaction truncated"), "{}", error);
+
+        // v5 header word with the Sapling version group ID
+        let error = decode_hex("0500008085202f89", Network::Main).unwrap_err();
+        assert!(error.contains("version group ID 892f2085"), "{}", error);
+    }
+
+    #[test]
+    fn prefix_decoding_allows_following_data() {
+        let mut bytes = hex::decode(GENESIS_COINBASE).unwrap();
+        bytes.extend(hex::decode(V5_TRANSPARENT).unwrap());
+
+        let first = decode_transaction_prefix(&bytes, Network::Main).unwrap();
+        assert_eq!(first.size, 204);
+        let second = decode_transaction_prefix(&bytes[first.size..], Network::Main).unwrap();
+        assert_eq!(second.header.version, 5);
+    }
+}
@@ -0,0 +1,103 @@
+use std::io::Cursor;
+
+use zcash_primitives::transaction::Transaction;
+use zcash_protocol::consensus::BranchId;
+
+/// Compute the transaction ID of a raw transaction, in the usual reversed
+/// display order
+///
+/// v5 transactions use the ZIP 244 non-malleable digest; earlier versions
+/// the double SHA-256 of the serialized transaction. Both come from
+/// `zcash_primitives`, which also rejects bytes that are not exactly one
+/// transaction.
+pub fn compute_txid(tx_bytes: &[u8]) -> Result<String, String> {
+    let mut reader = Cursor::new(tx_bytes);
+    // v5 transactions carry their own branch ID, and the branch doesn't
+    // enter the txid of earlier versions
+    let tx = Transaction::read(&mut reader, BranchId::Nu5)
+        .map_err(|e| format!("Failed to parse transaction: {}", e))?;
+
+    let trailing = tx_bytes.len() as u64 - reader.position();
+    if trailing > 0 {
+        return Err(format!(
+            "Transaction is followed by {} unexpected byte(s)",
+            trailing
+        ));
+    }
+
+    Ok(tx.txid().to_string())
+}
+
+/// Check that raw transaction bytes hash to the txid they were fetched or
+/// supplied under, returning the computed txid
+pub fn verify_txid(tx_bytes: &[u8], expected: &str) -> Result<String, String> {
+    let computed = compute_txid(tx_bytes)?;
+
+    if !computed.eq_ignore_ascii_case(expected.trim()) {
+        return Err(format!(
+            "Transaction data does not match txid {}: it hashes to {}",
+            expected.trim(),
+            computed
+        ));
+    }
+    Ok(computed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{
+        GENESIS_COINBASE, GENESIS_COINBASE_TXID, V4_TRANSPARENT, V4_TRANSPARENT_TXID,
+        V5_TRANSPARENT, V5_TRANSPARENT_TXID,
+    };
+
+    fn txid(tx_hex: &str) -> Result<String, String> {
+        compute_txid(&hex::decode(tx_hex).unwrap())
+    }
+
+    #[test]
+    fn computes_txids_of_every_version() {
+        assert_eq!(txid(GENESIS_COINBASE).unwrap(), GENESIS_COINBASE_TXID);
+        assert_eq!(txid(V4_TRANSPARENT).unwrap(), V4_TRANSPARENT_TXID);
+        assert_eq!(txid(V5_TRANSPARENT).unwrap(), V5_TRANSPARENT_TXID);
+    }
+
+    #[test]
+    fn v5_txid_commits_to_the_header() {
+        // Lock time follows the version, version group and branch ID
+        let mut bytes = hex::decode(V5_TRANSPARENT).unwrap();
+        bytes[12] ^= 1;
+        assert_ne!(compute_txid(&bytes).unwrap(), V5_TRANSPARENT_TXID);
+    }
+
+    #[test]
+    fn verifies_txid_case_insensitively() {
+        let bytes = hex::decode(V4_TRANSPARENT).unwrap();
+        let upper = V4_TRANSPARENT_TXID.to_uppercase();
+        assert_eq!(verify_txid(&bytes, &upper).unwrap(), V4_TRANSPARENT_TXID);
+
+        let error = verify_txid(&bytes, V5_TRANSPARENT_TXID).unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "Transaction data does not match txid {}: it hashes to {}",
+                V5_TRANSPARENT_TXID, V4_TRANSPARENT_TXID
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_and_padded_transactions() {
+        let bytes = hex::decode(V5_TRANSPARENT).unwrap();
+        assert!(compute_txid(&bytes[..bytes.len() - 1])
+            .unwrap_err()
+            .starts_with("Failed to parse transaction"));
+
+        let mut padded = bytes;
+        padded.push(0);
+        assert_eq!(
+            compute_txid(&padded).unwrap_err(),
+            "Transaction is followed by 1 unexpected byte(s)"
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! Checks on caller-supplied input, run before anything parses, slices or
+//! sends it anywhere
+//!
+//! Each check takes arbitrary strings (empty, truncated, non-ASCII) without
+//! panicking and reports which kind of input was wrong.
+
+use std::fmt;
+
+/// Longest viewing key accepted; a UFVK with transparent, Sapling and Orchard
+/// items is about 500 characters
+pub const MAX_VIEWING_KEY_LEN: usize = 2048;
+
+/// Longest URL accepted
+pub const MAX_URL_LEN: usize = 2048;
+
+/// Human-readable parts of the unified full viewing keys of each network
+const UFVK_PREFIXES: [&str; 3] = ["uview", "uviewtest", "uviewregtest"];
+
+/// Bech32 data characters
+const BECH32_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Bech32m checksum length in characters
+const CHECKSUM_LEN: usize = 6;
+
+/// Input rejected by a check, by kind, with the reason
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    Txid(String),
+    Height(String),
+    Range(String),
+    Url(String),
+    ViewingKey(String),
+}
+
+impl ValidationError {
+    /// The reason without the kind of input
+    pub fn reason(&self) -> &str {
+        match self {
+            ValidationError::Txid(reason)
+            | ValidationError::Height(reason)
+            | ValidationError::Range(reason)
+            | ValidationError::Url(reason)
+            | ValidationError::ViewingKey(reason) => reason,
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            ValidationError::Txid(_) => "txid",
+            ValidationError::Height(_) => "height",
+            ValidationError::Range(_) => "height range",
+            ValidationError::Url(_) => "URL",
+            ValidationError::ViewingKey(_) => "viewing key",
+        };
+        write!(f, "Invalid {}: {}", kind, self.reason())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<ValidationError> for String {
+    fn from(e: ValidationError) -> String {
+        e.to_string()
+    }
+}
+
+/// A txid as 64 hex characters in display order, returned trimmed and
+/// lowercased
+pub fn txid(input: &str) -> Result<String, ValidationError> {
+    let txid = input.trim();
+
+    if !txid.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ValidationError::Txid(
+            "expected hexadecimal characters only".to_string(),
+        ));
+    }
+    if txid.len() != 64 {
+        return Err(ValidationError::Txid(format!(
+            "expected 64 hex characters, got {}",
+            txid.len()
+        )));
+    }
+    Ok(txid.to_ascii_lowercase())
+}
+
+/// A block height, which the protocol limits to 32 bits
+pub fn height(value: u64) -> Result<u32, ValidationError> {
+    u32::try_from(value).map_err(|_| {
+        ValidationError::Height(format!("{} is larger than the maximum {}", value, u32::MAX))
+    })
+}
+
+/// The inclusive range `start..=end`, at most `max_span` blocks long
+pub fn height_range(start: u64, end: u64, max_span: u64) -> Result<(u32, u32), ValidationError> {
+    let start_height = height(start)?;
+    let end_height = height(end)?;
+
+    if start > end {
+        return Err(ValidationError::Range(format!(
+            "start height {} is after end height {}",
+            start, end
+        )));
+    }
+    let span = end - start + 1;
+    if span > max_span {
+        return Err(ValidationError::Range(format!(
+            "{} blocks requested, more than the limit of {}",
+            span, max_span
+        )));
+    }
+    Ok((start_height, end_height))
+}
+
+/// An `http` or `https` URL, split into the parts needed to connect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidUrl {
+    /// The URL as given, trimmed
+    pub url: String,
+    pub https: bool,
+    /// Lowercase host name or IP address, without IPv6 brackets
+    pub host: String,
+    /// Explicit port, or the scheme's default
+    pub port: u16,
+}
+
+/// An absolute `http` or `https` URL with a host and no credentials
+pub fn url(input: &str) -> Result<ValidUrl, ValidationError> {
+    let invalid = |reason: &str| ValidationError::Url(reason.to_string());
+    let url = input.trim();
+
+    if url.is_empty() {
+        return Err(invalid("empty"));
+    }
+    if url.len() > MAX_URL_LEN {
+        return Err(ValidationError::Url(format!(
+            "longer than {} characters",
+            MAX_URL_LEN
+        )));
+    }
+    if !url.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(invalid(
+            "contains whitespace, control or non-ASCII characters",
+        ));
+    }
+
+    let (https, rest) = match url.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => (true, rest),
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => (false, rest),
+        _ => return Err(invalid("expected an http:// or https:// URL")),
+    };
+
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if authority.contains('@') {
+        return Err(invalid("credentials are not allowed in the URL"));
+    }
+
+    let (host, port) = match authority.strip_prefix('[') {
+        // IPv6 literal, e.g. [::1]:9067
+        Some(bracketed) => {
+            let (host, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| invalid("unclosed '[' in host"))?;
+            if host.is_empty()
+                || !host
+                    .chars()
+                    .all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.')
+            {
+                return Err(invalid("malformed IPv6 address"));
+            }
+            let port = match after {
+                "" => None,
+                after => Some(
+                    after
+                        .strip_prefix(':')
+                        .ok_or_else(|| invalid("unexpected characters after the host"))?,
+                ),
+            };
+            (host, port)
+        }
+        None => {
+            let (host, port) = match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            };
+            if host.is_empty() {
+                return Err(invalid("missing host"));
+            }
+            if !host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+            {
+                return Err(invalid(
+                    "host has characters other than letters, digits, '-' and '.'",
+                ));
+            }
+            (host, port)
+        }
+    };
+
+    let port = match port {
+        None => {
+            if https {
+                443
+            } else {
+                80
+            }
+        }
+        Some(port) => port
+            .parse::<u16>()
+            .ok()
+            .filter(|port| *port != 0)
+            .ok_or_else(|| ValidationError::Url(format!("invalid port '{}'", port)))?,
+    };
+
+    Ok(ValidUrl {
+        url: url.to_string(),
+        https,
+        host: host.to_ascii_lowercase(),
+        port,
+    })
+}
+
+/// A Bech32m-encoded unified full viewing key for any network, returned
+/// trimmed
+///
+/// Only the shape is checked here: the prefix, alphabet and length. Decoding
+/// (checksum, F4Jumble and the key items) is `keys::parse_ufvk`'s job.
+pub fn viewing_key(input: &str) -> Result<&str, ValidationError> {
+    let invalid = |reason: &str| ValidationError::ViewingKey(reason.to_string());
+    let key = input.trim();
+
+    if key.is_empty() {
+        return Err(invalid("empty"));
+    }
+    if key.len() > MAX_VIEWING_KEY_LEN {
+        return Err(ValidationError::ViewingKey(format!(
+            "longer than {} characters",
+            MAX_VIEWING_KEY_LEN
+        )));
+    }
+    if !key.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return Err(invalid("expected only Bech32m letters and digits"));
+    }
+    if key.bytes().any(|b| b.is_ascii_lowercase()) && key.bytes().any(|b| b.is_ascii_uppercase()) {
+        return Err(invalid("mixes upper and lower case"));
+    }
+
+    // The data part can't contain '1', so the last one is the separator
+    let lower = key.to_ascii_lowercase();
+    let (prefix, data) = match lower.rsplit_once('1') {
+        Some((prefix, data)) if UFVK_PREFIXES.contains(&prefix) => (prefix, data),
+        _ => return Err(invalid(
+            "expected a unified full viewing key starting with uview1, uviewtest1 or uviewregtest1",
+        )),
+    };
+    if let Some(c) = data.chars().find(|c| !BECH32_CHARSET.contains(*c)) {
+        return Err(ValidationError::ViewingKey(format!(
+            "'{}' is not a Bech32m character",
+            c
+        )));
+    }
+    if data.len() <= CHECKSUM_LEN {
+        return Err(ValidationError::ViewingKey(format!(
+            "too short for a {}1 key",
+            prefix
+        )));
+    }
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UFVK: &str = "uview19av5rvg3syp6x6vkklu5r7lag67plc388pjr34wwcnrlgkhae9p0v9nczgev90akzavs2k3tmn9mvj24vvu9kl3lafjdqjj9w0dpjl8a39p2kv2hd53z0q9cy0vc29zlhk5k27rxx8057gla7jzp9nplxpta62lnc94wneqtwdjl2kmm4ly0kgh9gw323d49hxtv9a8ylyke8tr22jygxnjzmgps08uyay52slx2fyhplkhl2mpae98gacsse0jfffc4s6k4zu05qqsxkxr4mwcnaquspdqw4vj5m0ae53ctu2ka0qw3ksspwe3ahhu2x26rjchvcv76erc6gmxwyge3qn3y3js6xdtaxtgjcspf8sy6qtvh757p0r63qh5yjxegpgcjgpanf";
+
+    /// Deterministic xorshift generator, so failures reproduce
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        /// A string mixing ASCII, multi-byte characters and fragments of
+        /// valid input
+        fn string(&mut self) -> String {
+            const PIECES: [&str; 16] = [
+                "uview1",
+                "uviewtest1",
+                "https://",
+                "http://",
+                "[",
+                "]",
+                ":",
+                "@",
+                "/",
+                "é",
+                "😀",
+                "\u{0}",
+                " ",
+                "0",
+                "ff",
+                "zec.rocks",
+            ];
+            let len = self.below(80);
+            let mut s = String::new();
+            for _ in 0..len {
+                match self.below(3) {
+                    0 => s.push_str(PIECES[self.below(PIECES.len())]),
+                    1 => s.push(char::from(b' ' + self.below(95) as u8)),
+                    _ => s.push(char::from_u32(self.below(0x11000) as u32).unwrap_or('?')),
+                }
+            }
+            s
+        }
+
+        /// Truncate `s` at a random character boundary
+        fn prefix_of<'a>(&mut self, s: &'a str) -> &'a str {
+            let cut = self.below(s.len() + 1);
+            let cut = (0..=cut)
+                .rev()
+                .find(|i| s.is_char_boundary(*i))
+                .unwrap_or(0);
+            &s[..cut]
+        }
+    }
+
+    #[test]
+    fn checks_never_panic_on_random_input() {
+        let mut rng = Rng(0x5eed_2ec5_ca11_ab1e);
+        for _ in 0..20_000 {
+            let s = rng.string();
+            let _ = txid(&s);
+            let _ = url(&s);
+            let _ = viewing_key(&s);
+            let _ = viewing_key(rng.prefix_of(UFVK));
+            let _ = height_range(rng.next(), rng.next(), rng.next());
+        }
+    }
+
+    #[test]
+    fn accepted_input_is_ascii_and_sliceable() {
+        let mut rng = Rng(42);
+        for _ in 0..20_000 {
+            let s = rng.string();
+            if let Ok(key) = viewing_key(&s) {
+                assert!(key.is_ascii(), "{:?}", key);
+            }
+            if let Ok(txid) = txid(&s) {
+                assert_eq!(txid.len(), 64);
+            }
+            if let Ok(url) = url(&s) {
+                assert!(!url.host.is_empty(), "{:?}", s);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_keys_are_rejected_or_kept_whole() {
+        let mut rng = Rng(7);
+        for _ in 0..5_000 {
+            let key = rng.prefix_of(UFVK);
+            match viewing_key(key) {
+                Ok(accepted) => assert_eq!(accepted, key),
+                Err(e) => assert!(matches!(e, ValidationError::ViewingKey(_))),
+            }
+        }
+        assert_eq!(viewing_key(UFVK), Ok(UFVK));
+        assert_eq!(viewing_key(&format!("  {}\n", UFVK)), Ok(UFVK));
+        assert!(viewing_key("uview1").is_err());
+        assert!(viewing_key("zxviews1qqq").is_err());
+        assert!(viewing_key(&UFVK.replacen('a', "A", 1)).is_err());
+    }
+
+    #[test]
+    fn txids_are_normalized_or_rejected() {
+        let valid = "9AB4C6EA7811A0EC9BD82919A923C1E34DB6CFF750C073E7BD242FE4028FEAEE";
+        assert_eq!(txid(&format!(" {} ", valid)), Ok(valid.to_lowercase()));
+
+        let mut rng = Rng(99);
+        for _ in 0..5_000 {
+            let cut = rng.prefix_of(valid);
+            assert_eq!(txid(cut).is_ok(), cut.len() == 64);
+        }
+        assert!(txid(&format!("{}é", &valid[..62])).is_err());
+        assert!(txid(&"g".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn height_ranges_respect_order_and_span() {
+        let mut rng = Rng(1234);
+        for _ in 0..20_000 {
+            let start = rng.next() % 4_000_000;
+            let end = rng.next() % 4_000_000;
+            let max_span = rng.next() % 1_000_000 + 1;
+            match height_range(start, end, max_span) {
+                Ok((s, e)) => {
+                    assert!(s <= e);
+                    assert!(u64::from(e - s) < max_span);
+                }
+                Err(ValidationError::Range(_)) => {
+                    assert!(start > end || end - start + 1 > max_span)
+                }
+                Err(e) => panic!("unexpected {:?}", e),
+            }
+        }
+        assert_eq!(height_range(5, 5, 1), Ok((5, 5)));
+        assert!(height_range(5, 6, 1).is_err());
+        assert!(matches!(
+            height_range(0, u64::from(u32::MAX) + 1, u64::MAX),
+            Err(ValidationError::Height(_))
+        ));
+    }
+
+    #[test]
+    fn urls_are_split_into_host_and_port() {
+        let parsed = url("https://zec.rocks:443").unwrap();
+        assert!(parsed.https);
+        assert_eq!((parsed.host.as_str(), parsed.port), ("zec.rocks", 443));
+
+        let parsed = url("http://[::1]:9067/").unwrap();
+        assert_eq!((parsed.host.as_str(), parsed.port), ("::1", 9067));
+
+        assert_eq!(url("HTTP://Example.COM").unwrap().port, 80);
+        assert_eq!(url("https://Example.COM").unwrap().host, "example.com");
+
+        for bad in [
+            "",
+            "zec.rocks:443",
+            "ftp://zec.rocks",
+            "https://",
+            "https://:443",
+            "https://user:pw@zec.rocks",
+            "https://zec.rocks:0",
+            "https://zec.rocks:65536",
+            "https://zec.rocks:x",
+            "https://[::1",
+            "https://[::1]x",
+            "https://zec rocks",
+            "https://zéc.rocks",
+            "https://a:1:2",
+        ] {
+            assert!(url(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -44,12 +44,31 @@ export async function POST(request: NextRequest) {
                     signal: AbortSignal.timeout(30000), // 30 second timeout
                 });
 
+                const zingoData = await response.json().catch(() => null);
+
+                // 4xx answers are about the request itself (e.g. code
+                // `not_for_key`: nothing in the transaction is for this key),
+                // so pass them on; only fall back when the service or its
+                // upstream is down (5xx)
                 if (!response.ok) {
-                    throw new Error(`Zingo service returned ${response.status}`);
+                    if (response.status < 500 && zingoData?.code) {
+                        return NextResponse.json(
+                            {
+                                success: false,
+                                txId,
+                                code: zingoData.code,
+                                error: zingoData.error,
+                                viewingKeyNetwork: validation.network,
+                                source: 'zingo-service',
+                            },
+                            { status: response.status }
+                        );
+                    }
+                    throw new Error(
+                        `Zingo service returned ${response.status}: ${zingoData?.code ?? 'no error code'}`
+                    );
                 }
 
-                const zingoData = await response.json();
-
                 return NextResponse.json({
                     success: zingoData.success,
                     txId: zingoData.txid,
@@ -57,7 +76,7 @@ export async function POST(request: NextRequest) {
                     amount: zingoData.amount,
                     viewingKeyNetwork: validation.network,
                     source: 'zingo-service',
-                    note: zingoData.error || 'Decrypted via Zingo microservice',
+                    note: 'Decrypted via Zingo microservice',
                 });
             } catch (serviceError: any) {
                 console.error('Zingo service error:', serviceError);
@@ -10,6 +10,7 @@ import {
     LightwalletdConfig,
     WalletSyncProgress,
     ShieldedBalance,
+    TypedMemo,
     ZcashTransaction
 } from '@/types/zcash';
 
@@ -192,7 +193,7 @@ export class ZcashLightClient {
     /**
      * Decrypt a single transaction's memo
      */
-    async decryptMemo(txHex: string): Promise<string> {
+    async decryptMemo(txHex: string): Promise<TypedMemo | null> {
         if (!this.ufvk) {
             throw new Error('No viewing key imported');
         }
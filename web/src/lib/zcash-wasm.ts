@@ -3,6 +3,8 @@
  * Provides client-side memo decryption using WebAssembly
  */
 
+import { TypedMemo } from '@/types/zcash';
+
 let wasmModule: any = null;
 let initPromise: Promise<void> | null = null;
 
@@ -41,7 +43,7 @@ export async function initWasm(): Promise<void> {
 
 export interface DecryptResult {
     success: boolean;
-    memo: string;
+    memo: TypedMemo | null;
     error?: string;
 }
 
@@ -60,13 +62,13 @@ export async function decryptMemo(
     if (!wasmModule) {
         return {
             success: false,
-            memo: '',
+            memo: null,
             error: 'WebAssembly module not initialized',
         };
     }
 
     try {
-        const result = wasmModule.decrypt_memo(viewingKey, txHex);
+        const result = JSON.parse(wasmModule.decrypt_memo(viewingKey, txHex));
         return {
             success: result.success,
             memo: result.memo,
@@ -75,7 +77,7 @@ export async function decryptMemo(
     } catch (error: any) {
         return {
             success: false,
-            memo: '',
+            memo: null,
             error: error.message || 'Decryption failed',
         };
     }
@@ -2,6 +2,15 @@
  * Zcash Type Definitions for Shielded Transaction Decryption
  */
 
+/**
+ * A memo field interpreted according to ZIP 302
+ */
+export interface TypedMemo {
+    variant: 'text' | 'empty' | 'arbitrary' | 'reserved' | 'invalid';
+    text: string | null; // decoded text, `text` memos only
+    hex: string | null; // all 512 memo bytes, other than `text` and `empty` memos
+}
+
 export interface ZcashTransaction {
     txid: string;
     height: number;
@@ -54,7 +63,7 @@ export interface CompactOutput {
 export interface DecryptionResult {
     success: boolean;
     amount?: number;
-    memo?: string;
+    memo?: TypedMemo | null;
     error?: string;
 }
 
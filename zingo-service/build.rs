@@ -1,10 +1,17 @@
-// Build script for compiling protobuf files
-// This would compile lightwalletd proto files if we had them
+// Build script for compiling the lightwalletd protobuf definitions
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Prefer an explicitly configured protoc, fall back to the vendored one
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+
+    // The server side backs the in-process mock used by the tests
+    tonic_build::configure()
+        .build_server(true)
+        .compile_protos(&["proto/service.proto"], &["proto"])?;
 
-fn main() {
-    // For now, we'll use manual gRPC or REST approach
-    // To add proto compilation:
-    // tonic_build::compile_protos("proto/service.proto").unwrap();
-    
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto");
+    Ok(())
 }
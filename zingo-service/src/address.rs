@@ -0,0 +1,28 @@
+use zcash_wasm::address::{self, DerivedAddress, ReceiverSelection};
+use zcash_wasm::keys::parse_ufvk;
+use zcash_wasm::validate;
+
+use crate::error::{Result, ServiceError};
+use crate::network::resolve_network;
+
+/// Derive the unified addresses of a viewing key over a range of
+/// diversifier indices
+///
+/// `receivers` picks which of "orchard", "sapling" and "p2pkh" go into each
+/// address; an empty list includes everything the key supports.
+pub fn derive_addresses(
+    ufvk: &str,
+    start: u32,
+    count: u32,
+    receivers: &[String],
+    network: Option<&str>,
+) -> Result<Vec<DerivedAddress>> {
+    let ufvk = validate::viewing_key(ufvk)?;
+    let (key_network, ufvk) = parse_ufvk(ufvk).map_err(ServiceError::InvalidViewingKey)?;
+    let network = resolve_network(key_network, network)?;
+    let selection =
+        ReceiverSelection::from_names(receivers).map_err(ServiceError::InvalidRequest)?;
+
+    address::derive_addresses(&ufvk, network, start, count, selection)
+        .map_err(ServiceError::Unprocessable)
+}
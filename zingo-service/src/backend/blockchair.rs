@@ -0,0 +1,157 @@
+//! Blockchair's REST API, which serves raw transactions and blocks for
+//! Zcash mainnet only
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde_json::Value;
+use zcash_wasm::network::{network_name, Network};
+
+use super::compact::compact_block;
+use super::lightwalletd::proto::{BlockId, CompactBlock, TreeState};
+use super::{txid_to_internal, ChainBackend, FetchedTransaction, Timeouts, TransparentOutput};
+use crate::error::{Result, ServiceError};
+
+pub const DEFAULT_URL: &str = "https://api.blockchair.com/zcash";
+
+pub struct BlockchairClient {
+    url: String,
+    api_key: Option<String>,
+    http: reqwest::Client,
+}
+
+impl BlockchairClient {
+    pub fn new(url: &str, api_key: Option<String>, timeouts: Timeouts) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.request)
+            .build()
+            .map_err(|e| anyhow!("Failed to build HTTP client: {}", e))?;
+
+        Ok(BlockchairClient {
+            url: url.trim_end_matches('/').to_string(),
+            api_key,
+            http,
+        })
+    }
+
+    /// GET `path` and return its `data` object
+    async fn get(&self, path: &str) -> Result<Value> {
+        let mut request = self.http.get(format!("{}/{}", self.url, path));
+        if let Some(key) = &self.api_key {
+            request = request.query(&[("key", key)]);
+        }
+
+        let response = request.send().await.map_err(|e| {
+            ServiceError::from_reqwest(format!("Blockchair request {} failed", path), e)
+        })?;
+        let status = response.status();
+        let body: Value = response.json().await.map_err(|e| {
+            ServiceError::from_reqwest(format!("Blockchair response to {} is not JSON", path), e)
+        })?;
+
+        if !status.is_success() {
+            let message = format!(
+                "Blockchair request {} failed with {}: {}",
+                path,
+                status,
+                body["context"]["error"].as_str().unwrap_or("no details")
+            );
+            return Err(match status {
+                reqwest::StatusCode::NOT_FOUND => ServiceError::NotFound(message),
+                _ => ServiceError::Upstream(message),
+            });
+        }
+        Ok(body["data"].clone())
+    }
+
+    async fn raw_block(&self, height: u64) -> Result<Vec<u8>> {
+        let data = self.get(&format!("raw/block/{}", height)).await?;
+        let raw = data[height.to_string()]["raw_block"]
+            .as_str()
+            .ok_or_else(|| {
+                ServiceError::NotFound(format!("Blockchair has no block at height {}", height))
+            })?;
+        hex::decode(raw).map_err(|e| {
+            ServiceError::Upstream(format!("Blockchair block {} is not hex: {}", height, e))
+        })
+    }
+}
+
+#[async_trait]
+impl ChainBackend for BlockchairClient {
+    fn describe(&self) -> String {
+        format!("Blockchair at {}", self.url)
+    }
+
+    async fn ensure_network(&self, network: Network) -> Result<()> {
+        if network != Network::Main {
+            return Err(ServiceError::ChainMismatch(format!(
+                "Blockchair only serves mainnet, but the viewing key is for {}",
+                network_name(network)
+            )));
+        }
+        Ok(())
+    }
+
+    async fn chain_tip(&self) -> Result<BlockId> {
+        let data = self.get("stats").await?;
+        let height = data["best_block_height"].as_u64().ok_or_else(|| {
+            ServiceError::Upstream("Blockchair stats have no best_block_height".to_string())
+        })?;
+        let hash = data["best_block_hash"]
+            .as_str()
+            .map(txid_to_internal)
+            .transpose()
+            .map_err(|e| ServiceError::Upstream(format!("Blockchair best block hash: {}", e)))?
+            .unwrap_or_default();
+        Ok(BlockId { height, hash })
+    }
+
+    async fn raw_transaction(&self, txid: &str) -> Result<FetchedTransaction> {
+        let txid = txid.trim().to_lowercase();
+        txid_to_internal(&txid)?;
+
+        let data = self.get(&format!("raw/transaction/{}", txid)).await?;
+        let raw = data[&txid]["raw_transaction"].as_str().ok_or_else(|| {
+            ServiceError::NotFound(format!("Transaction {} not found on Blockchair", txid))
+        })?;
+        let data_bytes = hex::decode(raw).map_err(|e| {
+            ServiceError::Upstream(format!("Blockchair transaction is not hex: {}", e))
+        })?;
+
+        // The raw endpoint doesn't say where the transaction was mined
+        let dashboard = self
+            .get(&format!("dashboards/transaction/{}", txid))
+            .await?;
+        let height = dashboard[&txid]["transaction"]["block_id"]
+            .as_i64()
+            .and_then(|height| u32::try_from(height).ok());
+
+        Ok(FetchedTransaction {
+            data: data_bytes,
+            height,
+        })
+    }
+
+    /// One request per block, so only suitable for short ranges
+    async fn block_range(&self, start: u64, end: u64) -> Result<Vec<CompactBlock>> {
+        let mut blocks = Vec::new();
+        for height in start..=end {
+            let raw = self.raw_block(height).await?;
+            blocks.push(compact_block(height, &raw, Network::Main)?);
+        }
+        Ok(blocks)
+    }
+
+    async fn tree_state(&self, _height: u64) -> Result<TreeState> {
+        Err(ServiceError::Unprocessable(
+            "Blockchair does not provide note commitment tree states".to_string(),
+        ))
+    }
+
+    async fn address_utxos(&self, _addresses: &[String]) -> Result<Vec<TransparentOutput>> {
+        Err(ServiceError::Unprocessable(
+            "The Blockchair backend does not look up transparent outputs".to_string(),
+        ))
+    }
+}
@@ -0,0 +1,518 @@
+//! On-disk cache of compact blocks in front of any backend
+//!
+//! Each block is a file, `<network>/<height>-<hash>.block`, holding the
+//! protobuf lightwalletd serves. Only blocks at least `final_depth` below the
+//! tip are cached or served from the cache; nearer the tip they always come
+//! from upstream. Upstream blocks are still checked against the cache: a
+//! different hash at the same height, or a parent hash that doesn't match
+//! the cached block below, means the cache holds a branch the chain has left,
+//! and those blocks are dropped. When the cache is full the least recently
+//! used blocks go first.
+
+use async_trait::async_trait;
+use prost::Message;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+use zcash_wasm::network::{network_name, Network};
+
+use super::{
+    BlockId, ChainBackend, CompactBlock, FetchedTransaction, TransparentOutput, TreeState,
+};
+use crate::error::Result;
+
+const EXTENSION: &str = "block";
+
+/// Share of the cache freed at once when it fills up, so eviction doesn't
+/// run on every insert
+const EVICTION_SLACK: usize = 10;
+
+#[derive(Debug)]
+pub struct BlockCache {
+    dir: PathBuf,
+    max_blocks: usize,
+    /// Confirmations a block needs before it is cached
+    final_depth: u64,
+    index: Mutex<Index>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    reorgs: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// Which blocks are on disk, by network name and height
+#[derive(Debug, Default)]
+struct Index {
+    chains: HashMap<&'static str, BTreeMap<u64, Entry>>,
+    len: usize,
+    /// Ticks on every use, for least-recently-used eviction
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    hash: Vec<u8>,
+    last_used: u64,
+}
+
+/// Cache counters since startup, for operators
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    pub blocks: usize,
+    pub max_blocks: usize,
+    /// Blocks served from the cache
+    pub hits: u64,
+    /// Blocks fetched from upstream
+    pub misses: u64,
+    /// Times the cache was found to hold blocks the chain has left
+    pub reorgs: u64,
+    /// Blocks dropped to make room
+    pub evictions: u64,
+}
+
+impl BlockCache {
+    /// Open the cache in `dir`, indexing the blocks already there
+    pub fn open(dir: &Path, max_blocks: usize, final_depth: u64) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        let mut index = Index::default();
+        for network in [Network::Main, Network::Test, Network::Regtest] {
+            let name = network_name(network);
+            let Ok(entries) = std::fs::read_dir(dir.join(name)) else {
+                continue;
+            };
+            let chain = index.chains.entry(name).or_default();
+            for entry in entries {
+                if let Some((height, hash)) = parse_file_name(&entry?.path()) {
+                    chain.insert(height, Entry { hash, last_used: 0 });
+                }
+            }
+            index.len += chain.len();
+        }
+
+        let cache = BlockCache {
+            dir: dir.to_path_buf(),
+            max_blocks,
+            final_depth,
+            index: Mutex::new(index),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            reorgs: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        };
+        let evicted = cache.evict_if_full();
+        remove_files(&evicted);
+        Ok(cache)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            blocks: self.lock().len,
+            max_blocks: self.max_blocks,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            reorgs: self.reorgs.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Heights in `start..=end` cached for `network`
+    fn heights(&self, network: &'static str, start: u64, end: u64) -> BTreeSet<u64> {
+        if start > end {
+            return BTreeSet::new();
+        }
+        self.lock()
+            .chains
+            .get(network)
+            .map(|chain| {
+                chain
+                    .range(start..=end)
+                    .map(|(height, _)| *height)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    async fn get(&self, network: &'static str, height: u64) -> Option<CompactBlock> {
+        let path = {
+            let mut index = self.lock();
+            index.clock += 1;
+            let clock = index.clock;
+            let entry = index.chains.get_mut(network)?.get_mut(&height)?;
+            entry.last_used = clock;
+            self.path(network, height, &entry.hash)
+        };
+
+        let block = tokio::fs::read(&path)
+            .await
+            .ok()
+            .and_then(|data| CompactBlock::decode(data.as_slice()).ok())
+            .filter(|block| block.height == height);
+        if block.is_none() {
+            warn!("Dropping unreadable cached block {}", path.display());
+            remove_files(&self.remove_from(network, height, height));
+        }
+        block
+    }
+
+    /// Drop cached blocks that `block`, fresh from upstream, shows to be on
+    /// a branch the chain has left
+    fn check(&self, network: &'static str, block: &CompactBlock) {
+        let stale_from = {
+            let index = self.lock();
+            let Some(chain) = index.chains.get(network) else {
+                return;
+            };
+            match (
+                chain.get(&block.height),
+                block.height.checked_sub(1).and_then(|h| chain.get(&h)),
+            ) {
+                (Some(same), _) if same.hash != block.hash => Some(block.height),
+                (_, Some(parent)) if parent.hash != block.prev_hash => Some(block.height - 1),
+                _ => None,
+            }
+        };
+
+        if let Some(height) = stale_from {
+            debug!(
+                "Cached {} blocks from {} are off the chain; dropping them",
+                network, height
+            );
+            self.reorgs.fetch_add(1, Ordering::Relaxed);
+            remove_files(&self.remove_from(network, height, u64::MAX));
+        }
+    }
+
+    async fn put(&self, network: &'static str, block: &CompactBlock) {
+        let path = self.path(network, block.height, &block.hash);
+        // Renamed into place so readers never see half a block
+        let partial = path.with_extension("partial");
+        let written = async {
+            tokio::fs::create_dir_all(self.dir.join(network)).await?;
+            tokio::fs::write(&partial, block.encode_to_vec()).await?;
+            tokio::fs::rename(&partial, &path).await
+        };
+        if let Err(e) = written.await {
+            warn!("Failed to cache block {}: {}", path.display(), e);
+            return;
+        }
+
+        let replaced = {
+            let mut index = self.lock();
+            index.clock += 1;
+            let entry = Entry {
+                hash: block.hash.clone(),
+                last_used: index.clock,
+            };
+            let replaced = index
+                .chains
+                .entry(network)
+                .or_default()
+                .insert(block.height, entry);
+            if replaced.is_none() {
+                index.len += 1;
+            }
+            replaced
+        };
+
+        // A block at the same height on another branch
+        let mut stale = Vec::new();
+        if let Some(replaced) = replaced.filter(|replaced| replaced.hash != block.hash) {
+            stale.push(self.path(network, block.height, &replaced.hash));
+        }
+        stale.extend(self.evict_if_full());
+        remove_files(&stale);
+    }
+
+    /// Remove cached heights `start..=end` from the index, returning their
+    /// files
+    fn remove_from(&self, network: &'static str, start: u64, end: u64) -> Vec<PathBuf> {
+        let mut index = self.lock();
+        let Some(chain) = index.chains.get_mut(network) else {
+            return Vec::new();
+        };
+        let heights: Vec<u64> = chain
+            .range(start..=end)
+            .map(|(height, _)| *height)
+            .collect();
+        let paths: Vec<PathBuf> = heights
+            .iter()
+            .filter_map(|height| chain.remove(height).map(|entry| (height, entry)))
+            .map(|(height, entry)| self.path(network, *height, &entry.hash))
+            .collect();
+        index.len -= paths.len();
+        paths
+    }
+
+    /// Drop the least recently used blocks once the cache is over its size,
+    /// returning their files
+    fn evict_if_full(&self) -> Vec<PathBuf> {
+        let mut index = self.lock();
+        if index.len <= self.max_blocks {
+            return Vec::new();
+        }
+
+        let target = self.max_blocks - self.max_blocks / EVICTION_SLACK;
+        let mut by_age: Vec<(u64, &'static str, u64)> = index
+            .chains
+            .iter()
+            .flat_map(|(network, chain)| {
+                chain
+                    .iter()
+                    .map(move |(height, entry)| (entry.last_used, *network, *height))
+            })
+            .collect();
+        by_age.sort_unstable();
+
+        let mut paths = Vec::new();
+        for (_, network, height) in by_age.into_iter().take(index.len - target) {
+            if let Some(entry) = index
+                .chains
+                .get_mut(network)
+                .and_then(|c| c.remove(&height))
+            {
+                paths.push(self.path(network, height, &entry.hash));
+            }
+        }
+        index.len -= paths.len();
+        self.evictions
+            .fetch_add(paths.len() as u64, Ordering::Relaxed);
+        paths
+    }
+
+    fn path(&self, network: &str, height: u64, hash: &[u8]) -> PathBuf {
+        self.dir
+            .join(network)
+            .join(format!("{}-{}.{}", height, hex::encode(hash), EXTENSION))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Index> {
+        self.index
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn parse_file_name(path: &Path) -> Option<(u64, Vec<u8>)> {
+    if path.extension()? != EXTENSION {
+        return None;
+    }
+    let (height, hash) = path.file_stem()?.to_str()?.split_once('-')?;
+    Some((height.parse().ok()?, hex::decode(hash).ok()?))
+}
+
+fn remove_files(paths: &[PathBuf]) {
+    for path in paths {
+        if let Err(e) = std::fs::remove_file(path) {
+            debug!("Failed to remove cached block {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// A backend whose compact blocks go through the cache
+pub struct CachedBackend {
+    inner: Arc<dyn ChainBackend>,
+    cache: Arc<BlockCache>,
+    network: &'static str,
+    /// Tip height last reported upstream
+    tip: Mutex<Option<u64>>,
+}
+
+impl CachedBackend {
+    pub fn new(inner: Arc<dyn ChainBackend>, cache: Arc<BlockCache>, network: Network) -> Self {
+        CachedBackend {
+            inner,
+            cache,
+            network: network_name(network),
+            tip: Mutex::new(None),
+        }
+    }
+
+    /// Fetch `start..=end` upstream, checking each block against the cache
+    /// and caching the final ones
+    async fn fetch(&self, start: u64, end: u64, final_height: u64) -> Result<Vec<CompactBlock>> {
+        let blocks = self.inner.block_range(start, end).await?;
+        self.cache
+            .misses
+            .fetch_add(blocks.len() as u64, Ordering::Relaxed);
+        for block in &blocks {
+            self.cache.check(self.network, block);
+            if block.height <= final_height {
+                self.cache.put(self.network, block).await;
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+#[async_trait]
+impl ChainBackend for CachedBackend {
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+
+    async fn ensure_network(&self, network: Network) -> Result<()> {
+        self.inner.ensure_network(network).await
+    }
+
+    async fn chain_tip(&self) -> Result<BlockId> {
+        let tip = self.inner.chain_tip().await?;
+        *self.tip.lock().unwrap_or_else(|p| p.into_inner()) = Some(tip.height);
+        Ok(tip)
+    }
+
+    async fn raw_transaction(&self, txid: &str) -> Result<FetchedTransaction> {
+        self.inner.raw_transaction(txid).await
+    }
+
+    async fn block_range(&self, start: u64, end: u64) -> Result<Vec<CompactBlock>> {
+        let tip = *self.tip.lock().unwrap_or_else(|p| p.into_inner());
+        let tip = match tip {
+            Some(tip) => tip,
+            None => self.chain_tip().await?.height,
+        };
+        let final_height = tip.saturating_sub(self.cache.final_depth);
+        let cached = self
+            .cache
+            .heights(self.network, start, end.min(final_height));
+
+        let mut blocks = Vec::new();
+        let mut from_cache = false;
+        let mut height = start;
+        while height <= end {
+            if cached.contains(&height) {
+                if let Some(block) = self.cache.get(self.network, height).await {
+                    self.cache.hits.fetch_add(1, Ordering::Relaxed);
+                    blocks.push(block);
+                    from_cache = true;
+                    height += 1;
+                    continue;
+                }
+            }
+
+            // Up to the next cached block
+            let run_end = cached
+                .range(height + 1..)
+                .next()
+                .map_or(end, |next| next - 1);
+            blocks.extend(self.fetch(height, run_end, final_height).await?);
+            height = run_end + 1;
+        }
+
+        // Cached blocks must chain onto the fetched ones; if not, the cache
+        // was on another branch and the whole range comes from upstream
+        let continuous = blocks
+            .windows(2)
+            .all(|pair| pair[1].prev_hash == pair[0].hash);
+        if from_cache && !continuous {
+            self.cache.reorgs.fetch_add(1, Ordering::Relaxed);
+            remove_files(&self.cache.remove_from(self.network, start, end));
+            return self.fetch(start, end, final_height).await;
+        }
+        Ok(blocks)
+    }
+
+    async fn tree_state(&self, height: u64) -> Result<TreeState> {
+        self.inner.tree_state(height).await
+    }
+
+    async fn address_utxos(&self, addresses: &[String]) -> Result<Vec<TransparentOutput>> {
+        self.inner.address_utxos(addresses).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_lightwalletd::fixture;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("zingo-test-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn cached(chain: &str, cache: &Arc<BlockCache>) -> CachedBackend {
+        CachedBackend::new(Arc::new(fixture(chain)), cache.clone(), Network::Main)
+    }
+
+    #[tokio::test]
+    async fn repeat_scans_are_served_from_disk() {
+        let dir = temp_dir("hits");
+        // The fixture tip is 2700003, so 2700000..=2700002 are final
+        let cache = Arc::new(BlockCache::open(&dir, 100, 1).unwrap());
+
+        let first = cached("mainnet.json", &cache)
+            .block_range(2700000, 2700003)
+            .await
+            .unwrap();
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.blocks), (0, 4, 3));
+
+        // A restart keeps the blocks
+        let cache = Arc::new(BlockCache::open(&dir, 100, 1).unwrap());
+        let second = cached("mainnet.json", &cache)
+            .block_range(2700000, 2700003)
+            .await
+            .unwrap();
+        assert_eq!(first, second);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.blocks), (3, 1, 3));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_blocks_are_evicted() {
+        let dir = temp_dir("evict");
+        let cache = Arc::new(BlockCache::open(&dir, 2, 0).unwrap());
+        let backend = cached("mainnet.json", &cache);
+
+        backend.block_range(2700000, 2700001).await.unwrap();
+        backend.block_range(2700000, 2700000).await.unwrap();
+        backend.block_range(2700002, 2700002).await.unwrap();
+
+        // 2700001 was used least recently
+        let stats = cache.stats();
+        assert_eq!((stats.blocks, stats.evictions), (2, 1));
+        assert_eq!(
+            cache.heights("main", 2700000, 2700003),
+            BTreeSet::from([2700000, 2700002])
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn blocks_from_an_abandoned_branch_are_dropped() {
+        let dir = temp_dir("reorg");
+        let cache = Arc::new(BlockCache::open(&dir, 100, 1).unwrap());
+        cached("mainnet.json", &cache)
+            .block_range(2700000, 2700003)
+            .await
+            .unwrap();
+
+        // Upstream now follows a branch that replaced 2700002 onward
+        let mut fork = fixture("mainnet.json");
+        fork.fork_at(2700002);
+        let backend = CachedBackend::new(Arc::new(fork), cache.clone(), Network::Main);
+        let blocks = backend.block_range(2700000, 2700003).await.unwrap();
+
+        let expected = fixture("mainnet.json");
+        let mut forked = fixture("mainnet.json");
+        forked.fork_at(2700002);
+        assert_eq!(blocks, forked.blocks(2700000, 2700003));
+        assert_ne!(blocks, expected.blocks(2700000, 2700003));
+        assert!(cache.stats().reorgs > 0);
+
+        // Only the new branch is cached now
+        let again = backend.block_range(2700000, 2700003).await.unwrap();
+        assert_eq!(again, blocks);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
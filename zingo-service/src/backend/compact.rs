@@ -0,0 +1,88 @@
+//! Compact blocks built from full blocks, for backends that serve raw data
+
+use zcash_wasm::block::decode_block;
+use zcash_wasm::network::Network;
+use zcash_wasm::transaction::DecodedTransaction;
+
+use super::lightwalletd::proto::{
+    CompactBlock, CompactOrchardAction, CompactSaplingOutput, CompactSaplingSpend, CompactTx,
+};
+use super::txid_to_internal;
+use crate::error::{Result, ServiceError};
+
+/// Leading part of a note ciphertext that compact blocks carry
+const COMPACT_NOTE_SIZE: usize = 52;
+
+/// Build the compact block lightwalletd would serve for a serialized block
+///
+/// As with lightwalletd, only transactions with Sapling or Orchard
+/// components are kept.
+pub fn compact_block(height: u64, raw_block: &[u8], network: Network) -> Result<CompactBlock> {
+    let block = decode_block(raw_block, network)
+        .map_err(|e| ServiceError::Upstream(format!("Block {}: {}", height, e)))?;
+
+    let mut vtx = Vec::new();
+    for (index, entry) in block.transactions.iter().enumerate() {
+        if has_shielded_parts(&entry.tx) {
+            vtx.push(compact_tx(
+                index as u64,
+                txid_to_internal(&entry.txid)?,
+                &entry.tx,
+            ));
+        }
+    }
+
+    Ok(CompactBlock {
+        proto_version: 1,
+        height,
+        hash: block.hash.to_vec(),
+        prev_hash: block.prev_hash.to_vec(),
+        time: block.time,
+        vtx,
+        ..Default::default()
+    })
+}
+
+fn has_shielded_parts(tx: &DecodedTransaction) -> bool {
+    !tx.sapling_spends.is_empty()
+        || !tx.sapling_outputs.is_empty()
+        || tx
+            .orchard
+            .as_ref()
+            .is_some_and(|bundle| !bundle.actions.is_empty())
+}
+
+fn compact_tx(index: u64, hash: Vec<u8>, tx: &DecodedTransaction) -> CompactTx {
+    CompactTx {
+        index,
+        hash,
+        fee: 0,
+        spends: tx
+            .sapling_spends
+            .iter()
+            .map(|spend| CompactSaplingSpend {
+                nf: spend.nullifier.to_vec(),
+            })
+            .collect(),
+        outputs: tx
+            .sapling_outputs
+            .iter()
+            .map(|output| CompactSaplingOutput {
+                cmu: output.cmu.to_vec(),
+                ephemeral_key: output.ephemeral_key.to_vec(),
+                ciphertext: output.enc_ciphertext[..COMPACT_NOTE_SIZE].to_vec(),
+            })
+            .collect(),
+        actions: tx
+            .orchard
+            .iter()
+            .flat_map(|bundle| &bundle.actions)
+            .map(|action| CompactOrchardAction {
+                nullifier: action.nullifier.to_vec(),
+                cmx: action.cmx.to_vec(),
+                ephemeral_key: action.ephemeral_key.to_vec(),
+                ciphertext: action.enc_ciphertext[..COMPACT_NOTE_SIZE].to_vec(),
+            })
+            .collect(),
+    }
+}
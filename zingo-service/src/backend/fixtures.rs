@@ -0,0 +1,322 @@
+//! Chain data from a JSON fixture file, for offline development and tests
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use zcash_wasm::network::{network_name, Network};
+
+use super::lightwalletd::proto::{
+    BlockId, CompactBlock, CompactOrchardAction, CompactSaplingOutput, CompactSaplingSpend,
+    CompactTx, TreeState,
+};
+use super::{ChainBackend, FetchedTransaction, TransparentOutput};
+use crate::error::{Result, ServiceError};
+
+/// Fixture file contents; hashes and txids are hex in display order, like
+/// block explorers show them
+#[derive(Deserialize)]
+struct Fixture {
+    chain_name: String,
+    #[serde(default)]
+    blocks: Vec<FixtureBlock>,
+    #[serde(default)]
+    transactions: Vec<FixtureTransaction>,
+    #[serde(default)]
+    tree_states: Vec<FixtureTreeState>,
+    /// Unspent transparent outputs at the tip
+    #[serde(default)]
+    utxos: Vec<TransparentOutput>,
+}
+
+#[derive(Deserialize)]
+struct FixtureBlock {
+    height: u64,
+    hash: String,
+    prev_hash: String,
+    time: u32,
+    #[serde(default)]
+    vtx: Vec<FixtureCompactTx>,
+}
+
+#[derive(Deserialize)]
+struct FixtureCompactTx {
+    index: u64,
+    hash: String,
+    #[serde(default)]
+    spends: Vec<String>,
+    #[serde(default)]
+    outputs: Vec<FixtureSaplingOutput>,
+    #[serde(default)]
+    actions: Vec<FixtureOrchardAction>,
+}
+
+#[derive(Deserialize)]
+struct FixtureSaplingOutput {
+    cmu: String,
+    ephemeral_key: String,
+    ciphertext: String,
+}
+
+#[derive(Deserialize)]
+struct FixtureOrchardAction {
+    nullifier: String,
+    cmx: String,
+    ephemeral_key: String,
+    ciphertext: String,
+}
+
+/// A raw transaction served for `txid`, which need not be its real hash so
+/// tests can simulate a lying server
+#[derive(Deserialize)]
+struct FixtureTransaction {
+    txid: String,
+    data: String,
+    /// Omitted for a mempool transaction
+    #[serde(default)]
+    height: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct FixtureTreeState {
+    height: u64,
+    hash: String,
+    time: u32,
+    sapling_tree: String,
+    orchard_tree: String,
+}
+
+/// A fixed chain loaded from a fixture file
+pub struct FixtureChain {
+    /// "main", "test" or "regtest", as lightwalletd names them
+    pub chain_name: String,
+    blocks: BTreeMap<u64, CompactBlock>,
+    /// Raw transactions and heights (0 if unmined), by display-order txid
+    transactions: HashMap<String, (Vec<u8>, u64)>,
+    tree_states: BTreeMap<u64, TreeState>,
+    utxos: Vec<TransparentOutput>,
+}
+
+impl FixtureChain {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read fixtures {}: {}", path.display(), e))?;
+        let fixture: Fixture = serde_json::from_str(&json)
+            .map_err(|e| anyhow!("Failed to parse fixtures {}: {}", path.display(), e))?;
+
+        FixtureChain::from_fixture(fixture)
+            .map_err(|e| anyhow!("Invalid fixtures {}: {}", path.display(), e))
+    }
+
+    fn from_fixture(fixture: Fixture) -> anyhow::Result<Self> {
+        let mut blocks = BTreeMap::new();
+        for block in fixture.blocks {
+            blocks.insert(block.height, compact_block(block)?);
+        }
+
+        let mut transactions = HashMap::new();
+        for tx in fixture.transactions {
+            let raw = (bytes(&tx.data)?, tx.height.unwrap_or(0));
+            transactions.insert(tx.txid.to_lowercase(), raw);
+        }
+
+        let tree_states = fixture
+            .tree_states
+            .into_iter()
+            .map(|state| {
+                let tree_state = TreeState {
+                    network: fixture.chain_name.clone(),
+                    height: state.height,
+                    hash: state.hash,
+                    time: state.time,
+                    sapling_tree: state.sapling_tree,
+                    orchard_tree: state.orchard_tree,
+                };
+                (state.height, tree_state)
+            })
+            .collect();
+
+        Ok(FixtureChain {
+            chain_name: fixture.chain_name,
+            blocks,
+            transactions,
+            tree_states,
+            utxos: fixture.utxos,
+        })
+    }
+
+    pub fn tip(&self) -> Option<&CompactBlock> {
+        self.blocks.values().next_back()
+    }
+
+    /// Blocks in `start..=end` that the fixture has
+    pub fn blocks(&self, start: u64, end: u64) -> Vec<CompactBlock> {
+        if start > end {
+            return vec![];
+        }
+        self.blocks
+            .range(start..=end)
+            .map(|(_, block)| block.clone())
+            .collect()
+    }
+
+    /// Raw transaction and its height (0 if unmined)
+    pub fn transaction(&self, txid: &str) -> Option<(&[u8], u64)> {
+        self.transactions
+            .get(&txid.trim().to_lowercase())
+            .map(|(data, height)| (data.as_slice(), *height))
+    }
+
+    pub fn tree_state_at(&self, height: u64) -> Option<&TreeState> {
+        self.tree_states.get(&height)
+    }
+
+    /// Unspent outputs paying any of `addresses`
+    pub fn utxos(&self, addresses: &[String]) -> Vec<TransparentOutput> {
+        self.utxos
+            .iter()
+            .filter(|utxo| addresses.contains(&utxo.address))
+            .cloned()
+            .collect()
+    }
+
+    /// Serve `tree` as the Sapling note commitment tree at `height`
+    #[cfg(test)]
+    pub fn set_sapling_tree(&mut self, height: u64, tree: &str) {
+        if let Some(state) = self.tree_states.get_mut(&height) {
+            state.sapling_tree = tree.to_string();
+        }
+    }
+
+    /// Give the blocks from `height` up new hashes, as if the chain had
+    /// reorganized onto another branch there
+    #[cfg(test)]
+    pub fn fork_at(&mut self, height: u64) {
+        let mut prev_hash = None;
+        for block in self.blocks.range_mut(height..).map(|(_, block)| block) {
+            block.hash[0] ^= 0xff;
+            if let Some(prev_hash) = prev_hash.replace(block.hash.clone()) {
+                block.prev_hash = prev_hash;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ChainBackend for FixtureChain {
+    fn describe(&self) -> String {
+        format!("fixture chain ({})", self.chain_name)
+    }
+
+    async fn ensure_network(&self, network: Network) -> Result<()> {
+        if self.chain_name != network_name(network) {
+            return Err(ServiceError::ChainMismatch(format!(
+                "Fixtures are for the {} chain, but the viewing key is for {}",
+                self.chain_name,
+                network_name(network)
+            )));
+        }
+        Ok(())
+    }
+
+    async fn chain_tip(&self) -> Result<BlockId> {
+        self.tip()
+            .map(|tip| BlockId {
+                height: tip.height,
+                hash: tip.hash.clone(),
+            })
+            .ok_or_else(|| ServiceError::Upstream("Fixture chain has no blocks".to_string()))
+    }
+
+    async fn raw_transaction(&self, txid: &str) -> Result<FetchedTransaction> {
+        let (data, height) = self.transaction(txid).ok_or_else(|| {
+            ServiceError::NotFound(format!("Transaction {} is not in the fixtures", txid))
+        })?;
+
+        Ok(FetchedTransaction {
+            data: data.to_vec(),
+            height: match height {
+                0 => None,
+                height => u32::try_from(height).ok(),
+            },
+        })
+    }
+
+    async fn block_range(&self, start: u64, end: u64) -> Result<Vec<CompactBlock>> {
+        Ok(self.blocks(start, end))
+    }
+
+    async fn tree_state(&self, height: u64) -> Result<TreeState> {
+        self.tree_state_at(height).cloned().ok_or_else(|| {
+            ServiceError::NotFound(format!(
+                "No tree state at height {} in the fixtures",
+                height
+            ))
+        })
+    }
+
+    async fn address_utxos(&self, addresses: &[String]) -> Result<Vec<TransparentOutput>> {
+        Ok(self.utxos(addresses))
+    }
+}
+
+fn compact_block(block: FixtureBlock) -> anyhow::Result<CompactBlock> {
+    let mut vtx = Vec::new();
+    for tx in block.vtx {
+        vtx.push(CompactTx {
+            index: tx.index,
+            hash: internal_bytes(&tx.hash)?,
+            fee: 0,
+            spends: tx
+                .spends
+                .iter()
+                .map(|nf| Ok(CompactSaplingSpend { nf: bytes(nf)? }))
+                .collect::<anyhow::Result<_>>()?,
+            outputs: tx
+                .outputs
+                .iter()
+                .map(|output| {
+                    Ok(CompactSaplingOutput {
+                        cmu: bytes(&output.cmu)?,
+                        ephemeral_key: bytes(&output.ephemeral_key)?,
+                        ciphertext: bytes(&output.ciphertext)?,
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+            actions: tx
+                .actions
+                .iter()
+                .map(|action| {
+                    Ok(CompactOrchardAction {
+                        nullifier: bytes(&action.nullifier)?,
+                        cmx: bytes(&action.cmx)?,
+                        ephemeral_key: bytes(&action.ephemeral_key)?,
+                        ciphertext: bytes(&action.ciphertext)?,
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+        });
+    }
+
+    Ok(CompactBlock {
+        proto_version: 1,
+        height: block.height,
+        hash: internal_bytes(&block.hash)?,
+        prev_hash: internal_bytes(&block.prev_hash)?,
+        time: block.time,
+        vtx,
+        ..Default::default()
+    })
+}
+
+fn bytes(hex_str: &str) -> anyhow::Result<Vec<u8>> {
+    hex::decode(hex_str).map_err(|e| anyhow!("Bad hex '{}': {}", hex_str, e))
+}
+
+/// Display-order hash to the internal byte order used on the wire
+fn internal_bytes(hash: &str) -> anyhow::Result<Vec<u8>> {
+    let mut hash = bytes(hash)?;
+    hash.reverse();
+    Ok(hash)
+}
@@ -0,0 +1,269 @@
+//! A zebrad or zcashd node's JSON-RPC interface
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use zcash_wasm::network::{network_name, Network};
+
+use super::compact::compact_block;
+use super::lightwalletd::proto::{BlockId, CompactBlock, TreeState};
+use super::{txid_to_internal, ChainBackend, FetchedTransaction, Timeouts, TransparentOutput};
+use crate::error::{Result, ServiceError};
+
+/// zcashd's code for a transaction it doesn't have (`RPC_INVALID_ADDRESS_OR_KEY`)
+const RPC_NOT_FOUND: i64 = -5;
+
+/// zcashd's code for a block height past the tip (`RPC_INVALID_PARAMETER`)
+const RPC_INVALID_PARAMETER: i64 = -8;
+
+pub const DEFAULT_URL: &str = "http://127.0.0.1:8232";
+
+/// How to authenticate to the node
+#[derive(Debug, Clone)]
+pub enum RpcAuth {
+    None,
+    /// The node's `.cookie` file, re-read on every request so a node
+    /// restart doesn't break the service
+    Cookie(PathBuf),
+    UserPassword {
+        user: String,
+        password: String,
+    },
+}
+
+pub struct JsonRpcClient {
+    url: String,
+    auth: RpcAuth,
+    http: reqwest::Client,
+}
+
+impl JsonRpcClient {
+    pub fn new(url: &str, auth: RpcAuth, timeouts: Timeouts) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.request)
+            .build()
+            .map_err(|e| anyhow!("Failed to build HTTP client: {}", e))?;
+
+        Ok(JsonRpcClient {
+            url: url.to_string(),
+            auth,
+            http,
+        })
+    }
+
+    fn credentials(&self) -> Result<Option<(String, String)>> {
+        match &self.auth {
+            RpcAuth::None => Ok(None),
+            RpcAuth::UserPassword { user, password } => Ok(Some((user.clone(), password.clone()))),
+            RpcAuth::Cookie(path) => {
+                let cookie = std::fs::read_to_string(path).map_err(|e| {
+                    ServiceError::Internal(format!(
+                        "Failed to read RPC cookie {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                let (user, password) = cookie.trim().split_once(':').ok_or_else(|| {
+                    ServiceError::Internal(format!("RPC cookie {} is malformed", path.display()))
+                })?;
+                Ok(Some((user.to_string(), password.to_string())))
+            }
+        }
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": "zingo-service",
+            "method": method,
+            "params": params,
+        });
+
+        let mut request = self.http.post(&self.url).json(&body);
+        if let Some((user, password)) = self.credentials()? {
+            request = request.basic_auth(user, Some(password));
+        }
+
+        let response = request.send().await.map_err(|e| {
+            ServiceError::from_reqwest(format!("RPC {} at {} failed", method, self.url), e)
+        })?;
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(ServiceError::Upstream(format!(
+                "RPC {} at {} was refused: check the credentials",
+                method, self.url
+            )));
+        }
+
+        // Nodes report RPC errors in the body, often with a 500 status
+        let reply: Value = response.json().await.map_err(|e| {
+            ServiceError::from_reqwest(
+                format!(
+                    "RPC {} at {} returned {} without JSON",
+                    method, self.url, status
+                ),
+                e,
+            )
+        })?;
+        if !reply["error"].is_null() {
+            let code = reply["error"]["code"].as_i64();
+            let message = format!(
+                "RPC {} at {} failed: {} (code {})",
+                method,
+                self.url,
+                reply["error"]["message"]
+                    .as_str()
+                    .unwrap_or("unknown error"),
+                reply["error"]["code"]
+            );
+            return Err(match code {
+                Some(RPC_NOT_FOUND) => ServiceError::NotFound(message),
+                Some(RPC_INVALID_PARAMETER) if method == "getblock" => {
+                    ServiceError::NotFound(message)
+                }
+                _ => ServiceError::Upstream(message),
+            });
+        }
+        Ok(reply["result"].clone())
+    }
+}
+
+#[async_trait]
+impl ChainBackend for JsonRpcClient {
+    fn describe(&self) -> String {
+        format!("JSON-RPC node at {}", self.url)
+    }
+
+    async fn ensure_network(&self, network: Network) -> Result<()> {
+        let info = self.call("getblockchaininfo", json!([])).await?;
+        let chain = info["chain"].as_str().unwrap_or_default();
+
+        if chain != network_name(network) {
+            return Err(ServiceError::ChainMismatch(format!(
+                "Node at {} follows the {} chain, but the viewing key is for {}",
+                self.url,
+                chain,
+                network_name(network)
+            )));
+        }
+        Ok(())
+    }
+
+    async fn chain_tip(&self) -> Result<BlockId> {
+        let info = self.call("getblockchaininfo", json!([])).await?;
+        let height = info["blocks"]
+            .as_u64()
+            .ok_or_else(|| upstream("getblockchaininfo has no block count"))?;
+        let hash = info["bestblockhash"]
+            .as_str()
+            .map(txid_to_internal)
+            .transpose()
+            .map_err(|e| upstream(&format!("getblockchaininfo best block hash: {}", e)))?
+            .unwrap_or_default();
+        Ok(BlockId { height, hash })
+    }
+
+    async fn raw_transaction(&self, txid: &str) -> Result<FetchedTransaction> {
+        let tx = self
+            .call("getrawtransaction", json!([txid.trim(), 1]))
+            .await?;
+        let data = tx["hex"]
+            .as_str()
+            .ok_or_else(|| upstream("getrawtransaction returned no hex"))?;
+
+        Ok(FetchedTransaction {
+            data: hex::decode(data)
+                .map_err(|e| upstream(&format!("getrawtransaction hex: {}", e)))?,
+            // Absent (or -1) while in the mempool
+            height: tx["height"].as_u64().and_then(|h| u32::try_from(h).ok()),
+        })
+    }
+
+    async fn block_range(&self, start: u64, end: u64) -> Result<Vec<CompactBlock>> {
+        let mut blocks = Vec::new();
+        for height in start..=end {
+            let raw = self
+                .call("getblock", json!([height.to_string(), 0]))
+                .await?;
+            let raw = raw
+                .as_str()
+                .ok_or_else(|| upstream(&format!("getblock {} returned no hex", height)))?;
+            let raw = hex::decode(raw)
+                .map_err(|e| upstream(&format!("getblock {} hex: {}", height, e)))?;
+            // The network only affects transparent address rendering, which
+            // compact blocks don't carry
+            blocks.push(compact_block(height, &raw, Network::Main)?);
+        }
+        Ok(blocks)
+    }
+
+    async fn tree_state(&self, height: u64) -> Result<TreeState> {
+        let state = self
+            .call("z_gettreestate", json!([height.to_string()]))
+            .await?;
+        // Nodes that can't serve a tree send a `skipHash` instead of its
+        // commitments; scanning must not take that for an empty tree
+        let final_state = |pool: &str| {
+            state[pool]["commitments"]["finalState"]
+                .as_str()
+                .filter(|tree| !tree.is_empty())
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    upstream(&format!(
+                        "z_gettreestate has no {} note commitment tree at height {}",
+                        pool, height
+                    ))
+                })
+        };
+
+        let info = self.call("getblockchaininfo", json!([])).await?;
+
+        Ok(TreeState {
+            network: info["chain"].as_str().unwrap_or_default().to_string(),
+            height: state["height"].as_u64().unwrap_or(height),
+            hash: state["hash"].as_str().unwrap_or_default().to_string(),
+            time: state["time"].as_u64().unwrap_or(0) as u32,
+            sapling_tree: final_state("sapling")?,
+            orchard_tree: final_state("orchard")?,
+        })
+    }
+
+    /// Needs a node that keeps an address index: zebrad, or zcashd with
+    /// `-insightexplorer` or `-lightwalletd`
+    async fn address_utxos(&self, addresses: &[String]) -> Result<Vec<TransparentOutput>> {
+        let utxos = self
+            .call("getaddressutxos", json!([{ "addresses": addresses }]))
+            .await?;
+        let utxos = utxos
+            .as_array()
+            .ok_or_else(|| upstream("getaddressutxos returned no list"))?;
+
+        utxos
+            .iter()
+            .map(|utxo| {
+                let field = |name: &str| {
+                    utxo[name]
+                        .as_u64()
+                        .ok_or_else(|| upstream(&format!("getaddressutxos output has no {}", name)))
+                };
+                Ok(TransparentOutput {
+                    address: utxo["address"].as_str().unwrap_or_default().to_string(),
+                    txid: utxo["txid"]
+                        .as_str()
+                        .ok_or_else(|| upstream("getaddressutxos output has no txid"))?
+                        .to_string(),
+                    index: u32::try_from(field("outputIndex")?)
+                        .map_err(|_| upstream("getaddressutxos output index out of range"))?,
+                    value: field("satoshis")?,
+                    height: field("height")?,
+                })
+            })
+            .collect()
+    }
+}
+
+fn upstream(message: &str) -> ServiceError {
+    ServiceError::Upstream(message.to_string())
+}
@@ -0,0 +1,268 @@
+use async_trait::async_trait;
+use hyper_util::rt::TokioIo;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint, Uri};
+use tonic::Code;
+use tower::service_fn;
+use zcash_wasm::network::{network_name, Network};
+
+pub mod proto {
+    tonic::include_proto!("cash.z.wallet.sdk.rpc");
+}
+
+use proto::compact_tx_streamer_client::CompactTxStreamerClient;
+use proto::{
+    BlockId, BlockRange, ChainSpec, CompactBlock, Empty, GetAddressUtxosArg, LightdInfo,
+    RawTransaction, TreeState, TxFilter,
+};
+
+use super::{
+    display_hex, txid_to_internal, ChainBackend, FetchedTransaction, Timeouts, TransparentOutput,
+};
+use crate::error::{Result, ServiceError};
+
+/// lightwalletd reports this height for transactions not in the main chain
+const UNMINED_HEIGHT: u64 = u64::MAX;
+
+/// Client for a lightwalletd (or Zaino) `CompactTxStreamer` endpoint
+#[derive(Clone)]
+pub struct LightwalletdClient {
+    url: String,
+    inner: CompactTxStreamerClient<Channel>,
+}
+
+impl LightwalletdClient {
+    /// Connect to `url`; `https://` endpoints use TLS with the Mozilla
+    /// root store
+    pub async fn connect(url: &str, timeouts: Timeouts) -> Result<Self> {
+        let endpoint = Self::endpoint(url, timeouts)?;
+        let channel = endpoint
+            .connect()
+            .await
+            .map_err(|e| connect_error(url, e))?;
+        Ok(Self::new(url, channel))
+    }
+
+    /// Connect to `url` at one of `addresses`, which its host was checked to
+    /// resolve to, without looking the host up again
+    ///
+    /// The host name is still what TLS verifies and sends as SNI.
+    pub async fn connect_to(
+        url: &str,
+        addresses: Vec<SocketAddr>,
+        timeouts: Timeouts,
+    ) -> Result<Self> {
+        let endpoint = Self::endpoint(url, timeouts)?;
+        let addresses: Arc<[SocketAddr]> = addresses.into();
+        let connector = service_fn(move |_: Uri| {
+            let addresses = addresses.clone();
+            async move { TcpStream::connect(&addresses[..]).await.map(TokioIo::new) }
+        });
+        let channel = endpoint
+            .connect_with_connector(connector)
+            .await
+            .map_err(|e| connect_error(url, e))?;
+        Ok(Self::new(url, channel))
+    }
+
+    fn new(url: &str, channel: Channel) -> Self {
+        LightwalletdClient {
+            url: url.to_string(),
+            inner: CompactTxStreamerClient::new(channel),
+        }
+    }
+
+    fn endpoint(url: &str, timeouts: Timeouts) -> Result<Endpoint> {
+        let mut endpoint = Endpoint::from_shared(url.to_string())
+            .map_err(|e| {
+                ServiceError::InvalidRequest(format!("Invalid lightwalletd URL '{}': {}", url, e))
+            })?
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.request);
+
+        if url.starts_with("https://") {
+            endpoint = endpoint
+                .tls_config(ClientTlsConfig::new().with_webpki_roots())
+                .map_err(|e| {
+                    ServiceError::Internal(format!("TLS setup for {} failed: {}", url, e))
+                })?;
+        }
+
+        Ok(endpoint)
+    }
+
+    /// Server version, chain name and tip height
+    pub async fn lightd_info(&self) -> Result<LightdInfo> {
+        let response = self
+            .inner
+            .clone()
+            .get_lightd_info(Empty {})
+            .await
+            .map_err(|e| self.rpc_error("GetLightdInfo", e))?;
+        Ok(response.into_inner())
+    }
+
+    fn rpc_error(&self, method: &str, status: tonic::Status) -> ServiceError {
+        let message = format!(
+            "lightwalletd {} at {} failed: {} ({:?})",
+            method,
+            self.url,
+            status.message(),
+            status.code()
+        );
+        match status.code() {
+            Code::NotFound => ServiceError::NotFound(message),
+            // tonic cancels requests that outlive the endpoint's timeout
+            Code::DeadlineExceeded | Code::Cancelled => ServiceError::UpstreamTimeout(message),
+            Code::Unavailable => ServiceError::UpstreamUnavailable(message),
+            _ => ServiceError::Upstream(message),
+        }
+    }
+}
+
+fn connect_error(url: &str, e: tonic::transport::Error) -> ServiceError {
+    ServiceError::UpstreamUnavailable(format!(
+        "Failed to connect to lightwalletd at {}: {}",
+        url, e
+    ))
+}
+
+#[async_trait]
+impl ChainBackend for LightwalletdClient {
+    fn describe(&self) -> String {
+        format!("lightwalletd at {}", self.url)
+    }
+
+    /// Check the server follows the same chain as the viewing key
+    async fn ensure_network(&self, network: Network) -> Result<()> {
+        let info = self.lightd_info().await?;
+
+        if info.chain_name != network_name(network) {
+            return Err(ServiceError::ChainMismatch(format!(
+                "lightwalletd at {} serves the {} chain, but the viewing key is for {}",
+                self.url,
+                info.chain_name,
+                network_name(network)
+            )));
+        }
+        Ok(())
+    }
+
+    async fn chain_tip(&self) -> Result<BlockId> {
+        let response = self
+            .inner
+            .clone()
+            .get_latest_block(ChainSpec {})
+            .await
+            .map_err(|e| self.rpc_error("GetLatestBlock", e))?;
+        Ok(response.into_inner())
+    }
+
+    async fn raw_transaction(&self, txid: &str) -> Result<FetchedTransaction> {
+        // TxFilter takes the hash in internal byte order
+        let filter = TxFilter {
+            block: None,
+            index: 0,
+            hash: txid_to_internal(txid)?,
+        };
+
+        let RawTransaction { data, height } = self
+            .inner
+            .clone()
+            .get_transaction(filter)
+            .await
+            .map_err(|e| self.rpc_error("GetTransaction", e))?
+            .into_inner();
+
+        Ok(FetchedTransaction {
+            data,
+            height: match height {
+                0 | UNMINED_HEIGHT => None,
+                height => u32::try_from(height).ok(),
+            },
+        })
+    }
+
+    async fn block_range(&self, start: u64, end: u64) -> Result<Vec<CompactBlock>> {
+        let range = BlockRange {
+            start: Some(BlockId {
+                height: start,
+                hash: vec![],
+            }),
+            end: Some(BlockId {
+                height: end,
+                hash: vec![],
+            }),
+        };
+
+        let mut stream = self
+            .inner
+            .clone()
+            .get_block_range(range)
+            .await
+            .map_err(|e| self.rpc_error("GetBlockRange", e))?
+            .into_inner();
+
+        let mut blocks = Vec::new();
+        while let Some(block) = stream
+            .message()
+            .await
+            .map_err(|e| self.rpc_error("GetBlockRange", e))?
+        {
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    async fn tree_state(&self, height: u64) -> Result<TreeState> {
+        let response = self
+            .inner
+            .clone()
+            .get_tree_state(BlockId {
+                height,
+                hash: vec![],
+            })
+            .await
+            .map_err(|e| self.rpc_error("GetTreeState", e))?;
+        Ok(response.into_inner())
+    }
+
+    async fn address_utxos(&self, addresses: &[String]) -> Result<Vec<TransparentOutput>> {
+        let request = GetAddressUtxosArg {
+            addresses: addresses.to_vec(),
+            start_height: 0,
+            max_entries: 0,
+        };
+        let replies = self
+            .inner
+            .clone()
+            .get_address_utxos(request)
+            .await
+            .map_err(|e| self.rpc_error("GetAddressUtxos", e))?
+            .into_inner()
+            .address_utxos;
+
+        replies
+            .into_iter()
+            .map(|utxo| {
+                let (Ok(index), Ok(value)) =
+                    (u32::try_from(utxo.index), u64::try_from(utxo.value_zat))
+                else {
+                    return Err(ServiceError::UpstreamRejected(format!(
+                        "GetAddressUtxos at {} returned output {} worth {}",
+                        self.url, utxo.index, utxo.value_zat
+                    )));
+                };
+                Ok(TransparentOutput {
+                    address: utxo.address,
+                    txid: display_hex(&utxo.txid),
+                    index,
+                    value,
+                    height: utxo.height,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,331 @@
+//! Sources of chain data: transactions, compact blocks, the chain tip, note
+//! commitment tree states and unspent transparent outputs
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use zcash_wasm::network::{network_name, Network};
+use zcash_wasm::validate::ValidUrl;
+
+pub mod blockchair;
+pub mod cache;
+pub mod compact;
+pub mod fixtures;
+pub mod jsonrpc;
+pub mod lightwalletd;
+
+pub use lightwalletd::proto::{BlockId, CompactBlock, TreeState};
+
+use blockchair::BlockchairClient;
+use cache::{BlockCache, CachedBackend};
+use fixtures::FixtureChain;
+use jsonrpc::{JsonRpcClient, RpcAuth};
+use lightwalletd::LightwalletdClient;
+
+use crate::error::{Result, ServiceError};
+use crate::network::default_lightwalletd_url;
+use crate::upstream::UpstreamPolicy;
+
+/// A chain data source the decrypt and scan flows can run against
+///
+/// Hashes inside returned blocks are in internal byte order, as lightwalletd
+/// serves them; txids passed in are display-order hex. Errors distinguish a
+/// missing transaction or block (`NotFound`) from the backend being down or
+/// slow, so the API can report them apart.
+#[async_trait]
+pub trait ChainBackend: Send + Sync {
+    /// Short description for errors and logs, e.g. "lightwalletd at <url>"
+    fn describe(&self) -> String;
+
+    /// Fail with `ChainMismatch` unless the backend follows `network`'s chain
+    async fn ensure_network(&self, network: Network) -> Result<()>;
+
+    /// Height and hash of the best chain tip
+    async fn chain_tip(&self) -> Result<BlockId>;
+
+    /// Fetch a full transaction by its txid
+    async fn raw_transaction(&self, txid: &str) -> Result<FetchedTransaction>;
+
+    /// Compact blocks for `start..=end` in height order
+    ///
+    /// Everything is held in memory, so callers page through long ranges.
+    async fn block_range(&self, start: u64, end: u64) -> Result<Vec<CompactBlock>>;
+
+    /// Sapling and Orchard note commitment tree state as of `height`
+    async fn tree_state(&self, height: u64) -> Result<TreeState>;
+
+    /// Outputs paying any of the transparent `addresses` that are unspent
+    /// as of the chain tip
+    async fn address_utxos(&self, addresses: &[String]) -> Result<Vec<TransparentOutput>>;
+}
+
+/// A full transaction with the height it was mined at, if it has been
+pub struct FetchedTransaction {
+    pub data: Vec<u8>,
+    pub height: Option<u32>,
+}
+
+/// An unspent transparent output
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TransparentOutput {
+    pub address: String,
+    /// Display-order txid of the transaction that created it
+    pub txid: String,
+    pub index: u32,
+    /// In zatoshis
+    pub value: u64,
+    /// Height of the block it was mined in
+    pub height: u64,
+}
+
+/// Which backend serves requests, chosen by the operator
+#[derive(Debug, Clone)]
+pub enum BackendConfig {
+    /// lightwalletd (or Zaino); the public server for the key's network
+    /// unless `url` is set
+    Lightwalletd { url: Option<String> },
+    /// A zebrad or zcashd node's JSON-RPC interface
+    JsonRpc { url: String, auth: RpcAuth },
+    /// Blockchair's REST API (mainnet only)
+    Blockchair {
+        url: String,
+        api_key: Option<String>,
+    },
+    /// A JSON fixture file in the format of `tests/fixtures`
+    Fixtures { path: PathBuf },
+}
+
+/// How long to wait on upstream servers
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub connect: Duration,
+    /// Whole request, including streaming a block range
+    pub request: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(10),
+            request: Duration::from_secs(60),
+        }
+    }
+}
+
+/// How backends are reached, whichever kind is configured
+#[derive(Debug, Clone, Default)]
+pub struct BackendOptions {
+    /// Servers a request may name in `lightwalletd_url`
+    pub upstreams: UpstreamPolicy,
+    pub timeouts: Timeouts,
+    /// Only serve keys for this network; `None` serves all
+    pub network: Option<Network>,
+    /// Where compact blocks are cached, if anywhere
+    pub cache: Option<Arc<BlockCache>>,
+}
+
+/// Resolves the backend for each request from the configured one
+pub struct Backends {
+    config: BackendConfig,
+    /// Shared instance for backends that don't depend on the network
+    shared: Option<Arc<dyn ChainBackend>>,
+    options: BackendOptions,
+}
+
+impl Backends {
+    pub fn new(config: BackendConfig, options: BackendOptions) -> anyhow::Result<Self> {
+        let timeouts = options.timeouts;
+        let shared: Option<Arc<dyn ChainBackend>> =
+            match &config {
+                BackendConfig::Lightwalletd { .. } => None,
+                BackendConfig::JsonRpc { url, auth } => {
+                    Some(Arc::new(JsonRpcClient::new(url, auth.clone(), timeouts)?))
+                }
+                BackendConfig::Blockchair { url, api_key } => Some(Arc::new(
+                    BlockchairClient::new(url, api_key.clone(), timeouts)?,
+                )),
+                BackendConfig::Fixtures { path } => Some(Arc::new(FixtureChain::load(path)?)),
+            };
+
+        Ok(Backends {
+            config,
+            shared,
+            options,
+        })
+    }
+
+    /// The configured backend, for startup logs (API keys left out)
+    pub fn describe(&self) -> String {
+        match (&self.shared, &self.config) {
+            (Some(shared), _) => shared.describe(),
+            (None, BackendConfig::Lightwalletd { url: Some(url) }) => {
+                format!("lightwalletd at {}", url)
+            }
+            (None, _) => "public lightwalletd for each network".to_string(),
+        }
+    }
+
+    /// Servers requests may choose from
+    pub fn upstreams(&self) -> &UpstreamPolicy {
+        &self.options.upstreams
+    }
+
+    pub fn block_cache(&self) -> Option<&BlockCache> {
+        self.options.cache.as_deref()
+    }
+
+    /// Backend for a request on `network`, checked to follow that chain
+    ///
+    /// A lightwalletd URL in the request takes precedence over a configured
+    /// lightwalletd server, if the upstream policy approves it. Requests
+    /// can't bypass a JSON-RPC, Blockchair or fixture backend that way.
+    ///
+    /// Only the operator's backends share the block cache: a server the
+    /// caller picked could claim a fork and overwrite honest cached blocks.
+    pub async fn for_request(
+        &self,
+        network: Network,
+        lightwalletd_url: Option<&ValidUrl>,
+    ) -> Result<Arc<dyn ChainBackend>> {
+        if let Some(served) = self.options.network {
+            if served != network {
+                return Err(ServiceError::NetworkMismatch(format!(
+                    "This service only serves {} keys, but the viewing key is for {}",
+                    network_name(served),
+                    network_name(network)
+                )));
+            }
+        }
+
+        let timeouts = self.options.timeouts;
+        let backend: Arc<dyn ChainBackend> = match (lightwalletd_url, &self.shared) {
+            (Some(_), Some(_)) => {
+                return Err(ServiceError::InvalidRequest(
+                    "This service reads chain data from its own backend; requests may not set \
+                     lightwalletd_url"
+                        .to_string(),
+                ))
+            }
+            (Some(url), None) => {
+                let addresses = self.options.upstreams.check(url).await?;
+                Arc::new(LightwalletdClient::connect_to(&url.url, addresses, timeouts).await?)
+            }
+            (None, Some(shared)) => shared.clone(),
+            (None, None) => {
+                let url = match &self.config {
+                    BackendConfig::Lightwalletd { url: Some(url) } => url.as_str(),
+                    _ => default_lightwalletd_url(network),
+                };
+                Arc::new(LightwalletdClient::connect(url, timeouts).await?)
+            }
+        };
+
+        backend.ensure_network(network).await?;
+        Ok(match (&self.options.cache, lightwalletd_url) {
+            (Some(cache), None) => Arc::new(CachedBackend::new(backend, cache.clone(), network)),
+            _ => backend,
+        })
+    }
+}
+
+/// Display-order hex txid to the internal byte order used on the wire
+pub fn txid_to_internal(txid: &str) -> Result<Vec<u8>> {
+    let mut hash = hex::decode(txid.trim())
+        .map_err(|e| ServiceError::InvalidRequest(format!("Invalid txid: {}", e)))?;
+    if hash.len() != 32 {
+        return Err(ServiceError::InvalidRequest(format!(
+            "Invalid txid: expected 32 bytes, got {}",
+            hash.len()
+        )));
+    }
+    hash.reverse();
+    Ok(hash)
+}
+
+/// Internal byte order hash to display-order hex, as explorers show it
+pub fn display_hex(hash: &[u8]) -> String {
+    let mut hash = hash.to_vec();
+    hash.reverse();
+    hex::encode(hash)
+}
+
+/// Notes in a note commitment tree as `TreeState` serializes it: the left
+/// and right leaves, then a parent for each level above, each optional
+///
+/// An empty tree, including one for a pool that hasn't activated yet, is
+/// `000000`. An empty string means upstream didn't send the tree at all.
+pub fn commitment_tree_size(tree: &str) -> Result<u64> {
+    let bytes = hex::decode(tree).map_err(|e| invalid_tree(&e.to_string()))?;
+    if bytes.is_empty() {
+        return Err(invalid_tree("missing"));
+    }
+
+    let mut rest = bytes.as_slice();
+    let mut size = u64::from(tree_node(&mut rest)?) + u64::from(tree_node(&mut rest)?);
+    // A compact size, but trees are at most 32 levels deep
+    let parents = match rest.split_first() {
+        Some((&parents, tail)) if parents <= 32 => {
+            rest = tail;
+            parents
+        }
+        _ => return Err(invalid_tree("bad parent count")),
+    };
+    for level in 0..parents {
+        if tree_node(&mut rest)? {
+            size += 1 << (level + 1);
+        }
+    }
+    Ok(size)
+}
+
+/// Read an optional tree node, returning whether it is present
+fn tree_node(rest: &mut &[u8]) -> Result<bool> {
+    let (&flag, tail) = rest
+        .split_first()
+        .ok_or_else(|| invalid_tree("truncated"))?;
+    *rest = match flag {
+        0 => tail,
+        1 => tail.get(32..).ok_or_else(|| invalid_tree("truncated"))?,
+        _ => return Err(invalid_tree("bad node flag")),
+    };
+    Ok(flag == 1)
+}
+
+fn invalid_tree(reason: &str) -> ServiceError {
+    ServiceError::UpstreamRejected(format!("Invalid note commitment tree: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commitment_tree_size_counts_leaves_and_parents() {
+        let node = |present: bool| {
+            if present {
+                format!("01{}", "ab".repeat(32))
+            } else {
+                "00".to_string()
+            }
+        };
+
+        assert_eq!(commitment_tree_size("000000").unwrap(), 0);
+        let tree = format!(
+            "{}{}02{}{}",
+            node(true),
+            node(false),
+            node(false),
+            node(true)
+        );
+        assert_eq!(commitment_tree_size(&tree).unwrap(), 5);
+        let tree = format!("{}{}01{}", node(true), node(true), node(true));
+        assert_eq!(commitment_tree_size(&tree).unwrap(), 4);
+
+        assert!(commitment_tree_size("").is_err());
+        assert!(commitment_tree_size("01ab").is_err());
+        assert!(commitment_tree_size(&format!("{}{}", node(true), node(true))).is_err());
+        assert!(commitment_tree_size("zz").is_err());
+    }
+}
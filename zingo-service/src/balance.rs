@@ -0,0 +1,244 @@
+//! Spendable balances from the notes a scan found
+//!
+//! Every note received counts until a transaction reveals its nullifier,
+//! which spends it. What is left is split by pool and by whether the block
+//! it was mined in has `min_confirmations` as of the chain tip. Scans only
+//! read mined blocks, so "unconfirmed" notes are mined, just too recently to
+//! rely on. Compact blocks carry no transparent outputs, so transparent
+//! funds are the unspent outputs the backend reports for the key's
+//! transparent addresses, counted the same way.
+
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::backend::TransparentOutput;
+use crate::scan::NoteSpend;
+use crate::Transaction;
+
+/// Confirmations a note needs to count as confirmed unless configured
+/// otherwise; ZIP 315 suggests 10 for funds received from others
+pub const DEFAULT_MIN_CONFIRMATIONS: u32 = 10;
+
+/// Unspent value of the notes found, in zatoshis
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Balance {
+    /// Notes with at least `min_confirmations`
+    pub confirmed: i64,
+    /// Notes mined too recently to count as confirmed
+    pub unconfirmed: i64,
+    pub total: i64,
+    pub pools: PoolBalances,
+    /// Height confirmations were counted to
+    pub chain_tip: u64,
+    pub min_confirmations: u32,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct PoolBalances {
+    /// Null when the key has no transparent item or the backend can't look
+    /// up transparent outputs
+    pub transparent: Option<PoolBalance>,
+    pub sapling: PoolBalance,
+    pub orchard: PoolBalance,
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PoolBalance {
+    pub confirmed: i64,
+    pub unconfirmed: i64,
+    pub total: i64,
+}
+
+impl PoolBalance {
+    fn add(&mut self, value: i64, confirmed: bool) {
+        if confirmed {
+            self.confirmed += value;
+        } else {
+            self.unconfirmed += value;
+        }
+        self.total += value;
+    }
+}
+
+/// Balance of the notes in `transactions` that none of `spends` spent and
+/// of the unspent `transparent` outputs, if known, counting confirmations up
+/// to `chain_tip`
+///
+/// Sapling notes found without a nullifier can't be matched to their spend
+/// and always count.
+pub fn balance(
+    transactions: &[Transaction],
+    spends: &[NoteSpend],
+    transparent: Option<&[TransparentOutput]>,
+    chain_tip: u64,
+    min_confirmations: u32,
+) -> Balance {
+    let spent: HashSet<&str> = spends
+        .iter()
+        .map(|spend| spend.nullifier.as_str())
+        .collect();
+    let mut sapling = PoolBalance::default();
+    let mut orchard = PoolBalance::default();
+
+    let confirmed = |height: u64| {
+        // A transaction in the tip block has one confirmation
+        (chain_tip + 1).saturating_sub(height) >= u64::from(min_confirmations)
+    };
+
+    for transaction in transactions {
+        let confirmed = confirmed(transaction.height);
+
+        for note in &transaction.notes {
+            if note
+                .nullifier
+                .as_deref()
+                .is_some_and(|nullifier| spent.contains(nullifier))
+            {
+                continue;
+            }
+            let pool = match note.pool.as_str() {
+                "sapling" => &mut sapling,
+                "orchard" => &mut orchard,
+                _ => continue,
+            };
+            pool.add(note.value as i64, confirmed);
+        }
+    }
+
+    let transparent = transparent.map(|outputs| {
+        let mut pool = PoolBalance::default();
+        for output in outputs {
+            pool.add(output.value as i64, confirmed(output.height));
+        }
+        pool
+    });
+
+    let pools = [transparent.unwrap_or_default(), sapling, orchard];
+    Balance {
+        confirmed: pools.iter().map(|pool| pool.confirmed).sum(),
+        unconfirmed: pools.iter().map(|pool| pool.unconfirmed).sum(),
+        total: pools.iter().map(|pool| pool.total).sum(),
+        pools: PoolBalances {
+            transparent,
+            sapling,
+            orchard,
+        },
+        chain_tip,
+        min_confirmations,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zcash_wasm::compact::CompactNote;
+
+    fn note(pool: &str, value: u64, nullifier: Option<&str>) -> CompactNote {
+        CompactNote {
+            pool: pool.to_string(),
+            index: 0,
+            scope: "external".to_string(),
+            value,
+            nullifier: nullifier.map(str::to_string),
+        }
+    }
+
+    fn transaction(height: u64, notes: Vec<CompactNote>) -> Transaction {
+        Transaction {
+            txid: format!("{:064x}", height),
+            height,
+            block_hash: String::new(),
+            amount: notes.iter().map(|note| note.value as i64).sum(),
+            change: 0,
+            memo: None,
+            timestamp: None,
+            notes,
+        }
+    }
+
+    fn spend(height: u64, nullifier: &str) -> NoteSpend {
+        NoteSpend {
+            txid: format!("{:064x}", height + 1000),
+            height,
+            nullifier: nullifier.to_string(),
+        }
+    }
+
+    #[test]
+    fn spent_notes_leave_the_balance() {
+        let transactions = [
+            transaction(100, vec![note("orchard", 5000, Some("aa"))]),
+            transaction(
+                101,
+                vec![
+                    note("orchard", 3000, Some("bb")),
+                    note("sapling", 2000, Some("cc")),
+                ],
+            ),
+        ];
+        let spends = [spend(101, "aa"), spend(102, "dd")];
+
+        let balance = balance(&transactions, &spends, None, 200, 10);
+        assert_eq!(balance.total, 5000);
+        assert_eq!(balance.confirmed, 5000);
+        assert_eq!(balance.pools.orchard.total, 3000);
+        assert_eq!(balance.pools.sapling.total, 2000);
+        assert_eq!(balance.pools.transparent, None);
+    }
+
+    #[test]
+    fn recent_notes_are_unconfirmed() {
+        let transactions = [
+            transaction(191, vec![note("sapling", 1000, Some("aa"))]),
+            transaction(192, vec![note("sapling", 2000, None)]),
+            transaction(200, vec![note("orchard", 4000, Some("bb"))]),
+        ];
+
+        // Block 191 has exactly ten confirmations at a tip of 200
+        let balance = balance(&transactions, &[spend(201, "cc")], None, 200, 10);
+        assert_eq!(
+            balance.pools.sapling,
+            PoolBalance {
+                confirmed: 1000,
+                unconfirmed: 2000,
+                total: 3000,
+            }
+        );
+        assert_eq!(balance.pools.orchard.unconfirmed, 4000);
+        assert_eq!((balance.confirmed, balance.unconfirmed), (1000, 6000));
+
+        // Every mined note has at least one confirmation
+        let balance = super::balance(&transactions, &[], None, 200, 0);
+        assert_eq!(balance.confirmed, 7000);
+    }
+
+    #[test]
+    fn transparent_outputs_count_toward_the_totals() {
+        let output = |height: u64, value: u64| TransparentOutput {
+            address: "t1UoHt1HhNpgLw593KM5NGg31dDh7F51aX1".to_string(),
+            txid: format!("{:064x}", height),
+            index: 0,
+            value,
+            height,
+        };
+        let transactions = [transaction(150, vec![note("orchard", 4000, Some("aa"))])];
+        let outputs = [output(180, 1000), output(195, 500)];
+
+        let balance = balance(&transactions, &[], Some(&outputs), 200, 10);
+        assert_eq!(
+            balance.pools.transparent,
+            Some(PoolBalance {
+                confirmed: 1000,
+                unconfirmed: 500,
+                total: 1500,
+            })
+        );
+        assert_eq!((balance.confirmed, balance.unconfirmed), (5000, 500));
+        assert_eq!(balance.total, 5500);
+
+        // A key with transparent addresses but no unspent outputs has an
+        // empty transparent pool, not an unknown one
+        let balance = super::balance(&transactions, &[], Some(&[]), 200, 10);
+        assert_eq!(balance.pools.transparent, Some(PoolBalance::default()));
+    }
+}
@@ -0,0 +1,736 @@
+//! Service configuration, layered from a TOML file, then environment
+//! variables, then command-line flags, each overriding the one before
+//!
+//! Everything is checked at startup so a bad setting stops the service
+//! instead of failing the first request that needs it.
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::cors::AllowOrigin;
+use zcash_wasm::network::parse_network;
+use zcash_wasm::validate;
+
+use crate::backend::cache::BlockCache;
+use crate::backend::jsonrpc::{self, RpcAuth};
+use crate::backend::{blockchair, BackendConfig, BackendOptions, Timeouts};
+use crate::balance::DEFAULT_MIN_CONFIRMATIONS;
+use crate::jobs::JobOptions;
+use crate::upstream::UpstreamPolicy;
+
+/// Shown instead of secrets by `--print-config`
+const REDACTED: &str = "<redacted>";
+
+/// Command-line flags; each overrides the file and environment
+#[derive(Parser, Debug, Default)]
+#[command(
+    name = "zingo-service",
+    version,
+    about = "Zcash viewing key decryption service"
+)]
+pub struct Cli {
+    /// TOML configuration file (also ZINGO_CONFIG)
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Print the effective configuration as TOML, secrets redacted, and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Address and port to listen on
+    #[arg(long, value_name = "ADDR")]
+    pub bind: Option<String>,
+
+    /// Only serve keys for this network: main, test or regtest
+    #[arg(long)]
+    pub network: Option<String>,
+
+    #[arg(long)]
+    pub log_format: Option<LogFormat>,
+
+    /// Chain data backend
+    #[arg(long, value_name = "KIND")]
+    pub backend: Option<BackendKind>,
+
+    #[arg(long, value_name = "URL")]
+    pub lightwalletd_url: Option<String>,
+
+    #[arg(long, value_name = "URL")]
+    pub rpc_url: Option<String>,
+
+    #[arg(long, value_name = "PATH")]
+    pub rpc_cookie_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "URL")]
+    pub blockchair_url: Option<String>,
+
+    #[arg(long, value_name = "PATH")]
+    pub fixtures_path: Option<PathBuf>,
+
+    /// lightwalletd server requests may name (repeatable; `*` for any)
+    #[arg(long = "upstream", value_name = "URL")]
+    pub upstreams: Vec<String>,
+
+    /// Admit upstream servers on private, loopback and link-local addresses
+    #[arg(long)]
+    pub allow_private_upstreams: bool,
+
+    /// Origin allowed to call the API from a browser (repeatable; `*` for any)
+    #[arg(long = "cors-origin", value_name = "ORIGIN")]
+    pub cors_origins: Vec<String>,
+
+    #[arg(long, value_name = "SECS")]
+    pub connect_timeout: Option<u64>,
+
+    #[arg(long, value_name = "SECS")]
+    pub request_timeout: Option<u64>,
+
+    /// Directory to cache compact blocks in
+    #[arg(long, value_name = "PATH")]
+    pub cache_dir: Option<PathBuf>,
+
+    #[arg(long, value_name = "COUNT")]
+    pub cache_max_blocks: Option<usize>,
+
+    /// Background scan jobs that may run at once
+    #[arg(long, value_name = "COUNT")]
+    pub max_scan_jobs: Option<usize>,
+
+    /// Directory to save background scan jobs in, to resume after a restart
+    #[arg(long, value_name = "PATH")]
+    pub jobs_state_dir: Option<PathBuf>,
+
+    /// Confirmations a note needs to count toward the confirmed balance
+    #[arg(long, value_name = "COUNT")]
+    pub min_confirmations: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address and port to listen on
+    pub bind: String,
+    /// Only serve keys for this network; every network when unset
+    pub network: Option<String>,
+    pub log_format: LogFormat,
+    pub backend: BackendSection,
+    pub upstreams: UpstreamSection,
+    pub cors: CorsSection,
+    pub timeouts: TimeoutSection,
+    pub cache: CacheSection,
+    pub jobs: JobSection,
+    pub balance: BalanceSection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Lightwalletd,
+    Jsonrpc,
+    Blockchair,
+    Fixtures,
+}
+
+/// The chain data backend and the settings of each kind
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendSection {
+    pub kind: BackendKind,
+    /// lightwalletd endpoint; the public server for the key's network when
+    /// unset
+    pub lightwalletd_url: Option<String>,
+    pub rpc_url: Option<String>,
+    /// Node cookie file, or `rpc_user` and `rpc_password`
+    pub rpc_cookie_file: Option<PathBuf>,
+    pub rpc_user: Option<String>,
+    pub rpc_password: Option<String>,
+    pub blockchair_url: Option<String>,
+    pub blockchair_api_key: Option<String>,
+    pub fixtures_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamSection {
+    /// lightwalletd servers requests may name; `*` admits any
+    pub allowlist: Vec<String>,
+    /// Admit servers on private, loopback and link-local addresses
+    pub allow_private: bool,
+}
+
+impl Default for UpstreamSection {
+    fn default() -> Self {
+        UpstreamSection {
+            allowlist: UpstreamPolicy::default_allowlist(),
+            allow_private: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsSection {
+    /// Origins browsers may call the API from, e.g. the web app's domain;
+    /// `*` allows any
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for CorsSection {
+    fn default() -> Self {
+        CorsSection {
+            allowed_origins: vec!["*".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutSection {
+    /// Seconds to wait for a connection to an upstream server
+    pub connect_secs: u64,
+    /// Seconds to wait for a whole upstream request
+    pub request_secs: u64,
+}
+
+impl Default for TimeoutSection {
+    fn default() -> Self {
+        let timeouts = Timeouts::default();
+        TimeoutSection {
+            connect_secs: timeouts.connect.as_secs(),
+            request_secs: timeouts.request.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSection {
+    /// Directory compact blocks are cached in; no cache when unset
+    pub dir: Option<PathBuf>,
+    /// Compact blocks the block cache may hold
+    pub max_blocks: usize,
+    /// Confirmations a block needs before it is cached, so blocks that may
+    /// still be reorganized away always come from upstream
+    pub final_depth: u64,
+}
+
+impl Default for CacheSection {
+    fn default() -> Self {
+        CacheSection {
+            dir: None,
+            max_blocks: 100_000,
+            final_depth: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobSection {
+    /// Background scan jobs that may run at once; more wait in a queue
+    pub max_concurrent: usize,
+    /// Jobs that may wait in the queue; more are refused until some start
+    pub max_queued: usize,
+    /// Finished jobs kept for clients to read, newest first
+    pub max_finished: usize,
+    /// How long a finished job is kept
+    pub retention_secs: u64,
+    /// Directory jobs are saved in; they're lost on restart when unset
+    pub state_dir: Option<PathBuf>,
+}
+
+impl Default for JobSection {
+    fn default() -> Self {
+        let options = JobOptions::default();
+        JobSection {
+            max_concurrent: options.max_concurrent,
+            max_queued: options.max_queued,
+            max_finished: options.max_finished,
+            retention_secs: options.retention.as_secs(),
+            state_dir: options.state_dir,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BalanceSection {
+    /// Confirmations a note needs to count toward the confirmed balance;
+    /// scan requests may ask for another threshold
+    pub min_confirmations: u32,
+}
+
+impl Default for BalanceSection {
+    fn default() -> Self {
+        BalanceSection {
+            min_confirmations: DEFAULT_MIN_CONFIRMATIONS,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "0.0.0.0:3001".to_string(),
+            network: None,
+            log_format: LogFormat::default(),
+            backend: BackendSection::default(),
+            upstreams: UpstreamSection::default(),
+            cors: CorsSection::default(),
+            timeouts: TimeoutSection::default(),
+            cache: CacheSection::default(),
+            jobs: JobSection::default(),
+            balance: BalanceSection::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration from the file named by `--config` or
+    /// `ZINGO_CONFIG`, the process environment and `cli`, and validate it
+    pub fn load(cli: &Cli) -> Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+
+        let mut config = match cli
+            .config
+            .clone()
+            .or_else(|| var("ZINGO_CONFIG").map(Into::into))
+        {
+            Some(path) => Config::from_file(&path)?,
+            None => Config::default(),
+        };
+        config.apply_env(var)?;
+        config.apply_cli(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    /// Override settings from environment variables, read through `var`
+    ///
+    /// `PORT` replaces just the port of `bind`, for hosts that assign one;
+    /// `ZINGO_BIND` replaces the whole address.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        if let Some(port) = var("PORT") {
+            let host = self
+                .bind
+                .rsplit_once(':')
+                .map_or("0.0.0.0", |(host, _)| host);
+            self.bind = format!("{}:{}", host, port);
+        }
+        set(&mut self.bind, var("ZINGO_BIND"));
+        set_some(&mut self.network, var("ZINGO_NETWORK"));
+        if let Some(format) = var("ZINGO_LOG_FORMAT") {
+            self.log_format = parse_value("ZINGO_LOG_FORMAT", &format)?;
+        }
+
+        let backend = &mut self.backend;
+        if let Some(kind) = var("ZINGO_BACKEND") {
+            backend.kind = parse_value("ZINGO_BACKEND", &kind)?;
+        }
+        set_some(&mut backend.lightwalletd_url, var("ZINGO_LIGHTWALLETD_URL"));
+        set_some(&mut backend.rpc_url, var("ZINGO_RPC_URL"));
+        set_some(
+            &mut backend.rpc_cookie_file,
+            var("ZINGO_RPC_COOKIE_FILE").map(Into::into),
+        );
+        set_some(&mut backend.rpc_user, var("ZINGO_RPC_USER"));
+        set_some(&mut backend.rpc_password, var("ZINGO_RPC_PASSWORD"));
+        set_some(&mut backend.blockchair_url, var("ZINGO_BLOCKCHAIR_URL"));
+        set_some(
+            &mut backend.blockchair_api_key,
+            var("ZINGO_BLOCKCHAIR_API_KEY"),
+        );
+        set_some(
+            &mut backend.fixtures_path,
+            var("ZINGO_FIXTURES_PATH").map(Into::into),
+        );
+
+        set(
+            &mut self.upstreams.allowlist,
+            var("ZINGO_UPSTREAM_ALLOWLIST").map(|list| split_list(&list)),
+        );
+        if let Some(allow) = var("ZINGO_ALLOW_PRIVATE_UPSTREAMS") {
+            self.upstreams.allow_private = parse_bool("ZINGO_ALLOW_PRIVATE_UPSTREAMS", &allow)?;
+        }
+        set(
+            &mut self.cors.allowed_origins,
+            var("ZINGO_CORS_ORIGINS").map(|list| split_list(&list)),
+        );
+        if let Some(secs) = var("ZINGO_CONNECT_TIMEOUT_SECS") {
+            self.timeouts.connect_secs = parse_number("ZINGO_CONNECT_TIMEOUT_SECS", &secs)?;
+        }
+        if let Some(secs) = var("ZINGO_REQUEST_TIMEOUT_SECS") {
+            self.timeouts.request_secs = parse_number("ZINGO_REQUEST_TIMEOUT_SECS", &secs)?;
+        }
+        set_some(&mut self.cache.dir, var("ZINGO_CACHE_DIR").map(Into::into));
+        if let Some(blocks) = var("ZINGO_CACHE_MAX_BLOCKS") {
+            self.cache.max_blocks = parse_number("ZINGO_CACHE_MAX_BLOCKS", &blocks)?;
+        }
+        if let Some(jobs) = var("ZINGO_MAX_SCAN_JOBS") {
+            self.jobs.max_concurrent = parse_number("ZINGO_MAX_SCAN_JOBS", &jobs)?;
+        }
+        set_some(
+            &mut self.jobs.state_dir,
+            var("ZINGO_JOBS_STATE_DIR").map(Into::into),
+        );
+        if let Some(confirmations) = var("ZINGO_MIN_CONFIRMATIONS") {
+            self.balance.min_confirmations =
+                parse_number("ZINGO_MIN_CONFIRMATIONS", &confirmations)?;
+        }
+        Ok(())
+    }
+
+    pub fn apply_cli(&mut self, cli: &Cli) {
+        set(&mut self.bind, cli.bind.clone());
+        set_some(&mut self.network, cli.network.clone());
+        set(&mut self.log_format, cli.log_format);
+
+        let backend = &mut self.backend;
+        set(&mut backend.kind, cli.backend);
+        set_some(&mut backend.lightwalletd_url, cli.lightwalletd_url.clone());
+        set_some(&mut backend.rpc_url, cli.rpc_url.clone());
+        set_some(&mut backend.rpc_cookie_file, cli.rpc_cookie_file.clone());
+        set_some(&mut backend.blockchair_url, cli.blockchair_url.clone());
+        set_some(&mut backend.fixtures_path, cli.fixtures_path.clone());
+
+        if !cli.upstreams.is_empty() {
+            self.upstreams.allowlist = cli.upstreams.clone();
+        }
+        if cli.allow_private_upstreams {
+            self.upstreams.allow_private = true;
+        }
+        if !cli.cors_origins.is_empty() {
+            self.cors.allowed_origins = cli.cors_origins.clone();
+        }
+        set(&mut self.timeouts.connect_secs, cli.connect_timeout);
+        set(&mut self.timeouts.request_secs, cli.request_timeout);
+        set_some(&mut self.cache.dir, cli.cache_dir.clone());
+        set(&mut self.cache.max_blocks, cli.cache_max_blocks);
+        set(&mut self.jobs.max_concurrent, cli.max_scan_jobs);
+        set_some(&mut self.jobs.state_dir, cli.jobs_state_dir.clone());
+        set(&mut self.balance.min_confirmations, cli.min_confirmations);
+    }
+
+    /// Check every setting, as the service would use it
+    pub fn validate(&self) -> Result<()> {
+        self.bind_addr()?;
+        self.backend_config()?;
+        self.backend_options()?;
+        let _ = self.allowed_origins()?;
+        if self.cache.max_blocks == 0 {
+            return Err(anyhow!("cache.max_blocks must be at least 1"));
+        }
+        if self.jobs.max_concurrent == 0 {
+            return Err(anyhow!("jobs.max_concurrent must be at least 1"));
+        }
+        if self.jobs.max_queued == 0 {
+            return Err(anyhow!("jobs.max_queued must be at least 1"));
+        }
+        Ok(())
+    }
+
+    pub fn bind_addr(&self) -> Result<SocketAddr> {
+        self.bind
+            .parse()
+            .map_err(|e| anyhow!("bind '{}' is not an address and port: {}", self.bind, e))
+    }
+
+    pub fn backend_config(&self) -> Result<BackendConfig> {
+        let backend = &self.backend;
+        match backend.kind {
+            BackendKind::Lightwalletd => {
+                if let Some(url) = &backend.lightwalletd_url {
+                    validate::url(url).context("backend.lightwalletd_url")?;
+                }
+                Ok(BackendConfig::Lightwalletd {
+                    url: backend.lightwalletd_url.clone(),
+                })
+            }
+            BackendKind::Jsonrpc => {
+                let auth = match (
+                    &backend.rpc_cookie_file,
+                    &backend.rpc_user,
+                    &backend.rpc_password,
+                ) {
+                    (Some(path), None, None) => RpcAuth::Cookie(path.clone()),
+                    (None, Some(user), Some(password)) => RpcAuth::UserPassword {
+                        user: user.clone(),
+                        password: password.clone(),
+                    },
+                    (None, None, None) => RpcAuth::None,
+                    _ => {
+                        return Err(anyhow!(
+                            "Set either backend.rpc_cookie_file or both backend.rpc_user and backend.rpc_password"
+                        ))
+                    }
+                };
+                let url = backend
+                    .rpc_url
+                    .clone()
+                    .unwrap_or_else(|| jsonrpc::DEFAULT_URL.to_string());
+                validate::url(&url).context("backend.rpc_url")?;
+                Ok(BackendConfig::JsonRpc { url, auth })
+            }
+            BackendKind::Blockchair => {
+                let url = backend
+                    .blockchair_url
+                    .clone()
+                    .unwrap_or_else(|| blockchair::DEFAULT_URL.to_string());
+                validate::url(&url).context("backend.blockchair_url")?;
+                Ok(BackendConfig::Blockchair {
+                    url,
+                    api_key: backend.blockchair_api_key.clone(),
+                })
+            }
+            BackendKind::Fixtures => Ok(BackendConfig::Fixtures {
+                path: backend
+                    .fixtures_path
+                    .clone()
+                    .ok_or_else(|| anyhow!("backend.fixtures_path is required for fixtures"))?,
+            }),
+        }
+    }
+
+    pub fn backend_options(&self) -> Result<BackendOptions> {
+        let network = self
+            .network
+            .as_deref()
+            .map(parse_network)
+            .transpose()
+            .map_err(|e| anyhow!("network: {}", e))?;
+
+        if self.timeouts.connect_secs == 0 || self.timeouts.request_secs == 0 {
+            return Err(anyhow!("Timeouts must be at least one second"));
+        }
+
+        Ok(BackendOptions {
+            upstreams: UpstreamPolicy::new(
+                &self.upstreams.allowlist,
+                self.upstreams.allow_private,
+            )?,
+            timeouts: Timeouts {
+                connect: Duration::from_secs(self.timeouts.connect_secs),
+                request: Duration::from_secs(self.timeouts.request_secs),
+            },
+            network,
+            cache: None,
+        })
+    }
+
+    pub fn job_options(&self) -> JobOptions {
+        JobOptions {
+            max_concurrent: self.jobs.max_concurrent,
+            max_queued: self.jobs.max_queued,
+            max_finished: self.jobs.max_finished,
+            retention: Duration::from_secs(self.jobs.retention_secs),
+            state_dir: self.jobs.state_dir.clone(),
+        }
+    }
+
+    /// Open the block cache, if one is configured
+    pub fn block_cache(&self) -> Result<Option<Arc<BlockCache>>> {
+        let Some(dir) = &self.cache.dir else {
+            return Ok(None);
+        };
+        let cache = BlockCache::open(dir, self.cache.max_blocks, self.cache.final_depth)
+            .with_context(|| format!("Failed to open block cache {}", dir.display()))?;
+        Ok(Some(Arc::new(cache)))
+    }
+
+    /// Origins for the CORS layer
+    pub fn allowed_origins(&self) -> Result<AllowOrigin> {
+        let origins = &self.cors.allowed_origins;
+        if origins.iter().any(|origin| origin == "*") {
+            return Ok(AllowOrigin::any());
+        }
+
+        let mut values = Vec::new();
+        for origin in origins {
+            let url = validate::url(origin).with_context(|| format!("CORS origin '{}'", origin))?;
+            // An origin is just scheme, host and port
+            if url.url.trim_end_matches('/').matches('/').count() > 2 {
+                return Err(anyhow!("CORS origin '{}' must not have a path", origin));
+            }
+            values.push(url.url.trim_end_matches('/').parse()?);
+        }
+        Ok(AllowOrigin::list(values))
+    }
+
+    /// The configuration as TOML, with passwords and API keys replaced
+    pub fn to_redacted_toml(&self) -> Result<String> {
+        let mut config = self.clone();
+        for secret in [
+            &mut config.backend.rpc_password,
+            &mut config.backend.blockchair_api_key,
+        ] {
+            if secret.is_some() {
+                *secret = Some(REDACTED.to_string());
+            }
+        }
+        Ok(toml::to_string(&config)?)
+    }
+}
+
+fn set<T>(field: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *field = value;
+    }
+}
+
+fn set_some<T>(field: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *field = value;
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_value<T: ValueEnum>(name: &str, value: &str) -> Result<T> {
+    T::from_str(value, true).map_err(|e| anyhow!("{}: {}", name, e))
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    match value {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        other => Err(anyhow!("{} must be true or false, not '{}'", name, other)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| anyhow!("{} '{}' is not a valid number: {}", name, value, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn layers_override_in_order() {
+        let mut config: Config = toml::from_str(
+            r#"
+            bind = "127.0.0.1:8000"
+            network = "test"
+
+            [backend]
+            kind = "jsonrpc"
+            rpc_url = "http://10.0.0.5:18232"
+
+            [timeouts]
+            request_secs = 5
+            "#,
+        )
+        .unwrap();
+        config
+            .apply_env(env(&[
+                ("PORT", "9000"),
+                ("ZINGO_RPC_URL", "http://10.0.0.6:18232"),
+                ("ZINGO_REQUEST_TIMEOUT_SECS", "7"),
+                ("ZINGO_MIN_CONFIRMATIONS", "3"),
+            ]))
+            .unwrap();
+        config.apply_cli(&Cli::parse_from([
+            "zingo-service",
+            "--request-timeout",
+            "9",
+            "--cors-origin",
+            "https://zecscan.example",
+        ]));
+        config.validate().unwrap();
+
+        assert_eq!(config.bind, "127.0.0.1:9000");
+        assert_eq!(config.network.as_deref(), Some("test"));
+        assert_eq!(config.backend.kind, BackendKind::Jsonrpc);
+        assert_eq!(
+            config.backend.rpc_url.as_deref(),
+            Some("http://10.0.0.6:18232")
+        );
+        assert_eq!(config.timeouts.request_secs, 9);
+        assert_eq!(config.timeouts.connect_secs, 10);
+        assert_eq!(config.cors.allowed_origins, ["https://zecscan.example"]);
+        assert_eq!(config.balance.min_confirmations, 3);
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let invalid = |toml: &str| {
+            let config: Result<Config> = toml::from_str(toml).map_err(Into::into);
+            config.and_then(|config| config.validate())
+        };
+
+        assert!(invalid("bind = \"localhost\"").is_err());
+        assert!(invalid("network = \"canopy\"").is_err());
+        assert!(invalid("unknown = 1").is_err());
+        assert!(invalid("[backend]\nkind = \"fixtures\"").is_err());
+        assert!(invalid("[backend]\nkind = \"jsonrpc\"\nrpc_user = \"zingo\"").is_err());
+        assert!(invalid("[upstreams]\nallowlist = [\"zec.rocks\"]").is_err());
+        assert!(invalid("[cors]\nallowed_origins = [\"https://a.example/app\"]").is_err());
+        assert!(invalid("[timeouts]\nconnect_secs = 0").is_err());
+        assert!(invalid("[jobs]\nmax_concurrent = 0").is_err());
+        assert!(invalid("[jobs]\nmax_queued = 0").is_err());
+        assert!(invalid("").is_ok());
+
+        let mut config = Config::default();
+        assert!(config
+            .apply_env(env(&[("ZINGO_BACKEND", "zebra")]))
+            .is_err());
+        assert!(config
+            .apply_env(env(&[("ZINGO_ALLOW_PRIVATE_UPSTREAMS", "yes")]))
+            .is_err());
+    }
+
+    #[test]
+    fn printed_config_hides_secrets_and_reloads() {
+        let mut config = Config::default();
+        config.backend.kind = BackendKind::Jsonrpc;
+        config.backend.rpc_user = Some("zingo".to_string());
+        config.backend.rpc_password = Some("hunter2".to_string());
+
+        let printed = config.to_redacted_toml().unwrap();
+        assert!(!printed.contains("hunter2"), "{}", printed);
+        assert!(printed.contains(REDACTED), "{}", printed);
+
+        let reloaded: Config = toml::from_str(&printed).unwrap();
+        reloaded.validate().unwrap();
+        assert_eq!(reloaded.backend.rpc_user.as_deref(), Some("zingo"));
+    }
+}
@@ -1,75 +1,77 @@
-use anyhow::{anyhow, Result};
+use zcash_wasm::decrypt::{decrypt_transaction, DecryptedNote};
+use zcash_wasm::keys::parse_ufvk;
+use zcash_wasm::memo::TypedMemo;
+use zcash_wasm::txid::verify_txid;
+use zcash_wasm::validate;
 
-/// Verify transaction and provide comprehensive decryption guidance
-/// This is a pragmatic, honest approach that actually works
+use crate::backend::Backends;
+use crate::error::{Result, ServiceError};
+use crate::network::resolve_network;
+
+/// Fetch a transaction and trial-decrypt its shielded outputs
+///
+/// Each Sapling output and Orchard action is tried with the UFVK's incoming
+/// viewing keys, then with its outgoing viewing keys, so a transaction the
+/// key holder sent yields the recipient, amount and memo as well. A
+/// transaction with nothing for the key fails with `NotForKey`.
 pub async fn decrypt_memo(
+    backends: &Backends,
     ufvk: &str,
     txid: &str,
-    _lightwalletd_url: &str,
-) -> Result<(String, i64)> {
-    // Fetch transaction from Blockchair to verify it exists
-    let client = reqwest::Client::new();
-    let url = format!("https://api.blockchair.com/zcash/raw/transaction/{}", txid);
-    
-    let response = client.get(&url).send().await
-        .map_err(|e| anyhow!("Failed to fetch transaction: {}", e))?;
-    
-    if !response.status().is_success() {
-        return Err(anyhow!("Transaction not found on Zcash blockchain"));
+    network: Option<&str>,
+    lightwalletd_url: Option<&str>,
+) -> Result<Vec<DecryptedNote>> {
+    let ufvk = validate::viewing_key(ufvk)?;
+    let txid = validate::txid(txid)?;
+    let lightwalletd_url = lightwalletd_url.map(validate::url).transpose()?;
+    let (key_network, ufvk) = parse_ufvk(ufvk).map_err(ServiceError::InvalidViewingKey)?;
+    let network = resolve_network(key_network, network)?;
+
+    // Fetch the raw transaction from the chain backend
+    let backend = backends
+        .for_request(network, lightwalletd_url.as_ref())
+        .await?;
+    let tx = backend.raw_transaction(&txid).await?;
+
+    // Don't trust the upstream to hand back the transaction we asked for
+    verify_txid(&tx.data, &txid).map_err(ServiceError::UpstreamRejected)?;
+
+    // The data hashes to the txid, so a parse failure is the transaction's
+    let notes = decrypt_transaction(&ufvk, network, &tx.data, tx.height)
+        .map_err(ServiceError::Unprocessable)?;
+
+    if notes.is_empty() {
+        return Err(ServiceError::NotForKey);
     }
-    
-    let data: serde_json::Value = response.json().await
-        .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
-    
-    // Get transaction details
-    let tx_size = data["data"][txid]["size"].as_i64().unwrap_or(0);
-    let tx_time = data["data"][txid]["time"].as_str().unwrap_or("unknown");
-    let tx_version = data["data"][txid]["version"].as_i64().unwrap_or(0);
-    
-    // Provide comprehensive, helpful guidance
-    let memo = format!(
-        "✅ TRANSACTION VERIFIED ON BLOCKCHAIN\n\n\
-         📊 Transaction Details:\n\
-         • TX ID: {}\n\
-         • Size: {} bytes\n\
-         • Version: {}\n\
-         • Time: {}\n\
-         • Status: Confirmed\n\n\
-         🔐 TO DECRYPT THE MEMO:\n\n\
-         This transaction contains encrypted data. To decrypt it, import your \
-         viewing key into one of these production-ready Zcash wallets:\n\n\
-         📱 RECOMMENDED WALLETS (in order):\n\n\
-         1. **Ywallet** (Fastest, most advanced)\n\
-            • Download: https://ywallet.app\n\
-            • Platforms: iOS, Android, Desktop\n\
-            • Features: Advanced syncing, multi-account\n\n\
-         2. **Nighthawk Wallet** (Mobile-friendly)\n\
-            • Download: https://nighthawkwallet.com\n\
-            • Platforms: iOS, Android\n\
-            • Features: Simple, secure, well-supported\n\n\
-         3. **Zecwallet Lite** (Desktop power user)\n\
-            • Download: https://www.zecwallet.co\n\
-            • Platforms: Windows, Mac, Linux\n\
-            • Features: Full-featured desktop wallet\n\n\
-         🔑 Your Viewing Key: {}...\n\n\
-         ⚡ HOW IT WORKS:\n\
-         1. Install wallet from link above\n\
-         2. Import your viewing key (UFVK)\n\
-         3. Wallet syncs with blockchain\n\
-         4. Memos decrypt automatically\n\n\
-         💡 WHY USE A WALLET?\n\
-         Zcash uses advanced cryptography (Sapling/Orchard protocols) that \
-         requires full wallet state to decrypt. Your viewing key never needs to \
-         leave your device when using these wallets - they connect directly to \
-         public Zcash light wallet servers.\n\n\
-         🔒 PRIVACY: These wallets are open-source and privacy-preserving. \
-         Your keys stay on your device.",
-        txid,
-        tx_size,
-        tx_version,
-        tx_time,
-        &ufvk[..40]
-    );
-    
-    Ok((memo, tx_size))
+
+    Ok(notes)
+}
+
+/// The payment a transaction makes to or from the key holder, as a value in
+/// zatoshis and the first non-empty memo on it
+///
+/// Notes received on external addresses are the payment when there are any.
+/// Otherwise the key holder sent the transaction, and the payment is the
+/// notes recovered with the outgoing viewing key. Change, which comes back
+/// on internal addresses, never counts.
+pub fn payment_summary(notes: &[DecryptedNote]) -> (u64, Option<TypedMemo>) {
+    let received: Vec<_> = notes
+        .iter()
+        .filter(|note| note.direction == "incoming" && note.scope == "external")
+        .collect();
+    let payment = if received.is_empty() {
+        notes
+            .iter()
+            .filter(|note| note.direction == "outgoing")
+            .collect()
+    } else {
+        received
+    };
+
+    let amount = payment.iter().map(|note| note.value).sum();
+    let memo = payment
+        .iter()
+        .find(|note| note.memo.variant != "empty")
+        .map(|note| note.memo.clone());
+    (amount, memo)
 }
@@ -0,0 +1,203 @@
+//! Errors the API reports, each with an HTTP status and a stable `code`
+//!
+//! Every failure is answered with the same JSON body:
+//!
+//! ```json
+//! { "success": false, "code": "not_for_key", "error": "No outputs in ..." }
+//! ```
+//!
+//! `code` is meant for programs and never changes once published; `error`
+//! is a human-readable explanation whose wording may.
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, Request};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{async_trait, Json};
+use serde_json::json;
+use zcash_wasm::validate::ValidationError;
+
+pub type Result<T, E = ServiceError> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ServiceError {
+    /// The request body isn't JSON
+    #[error("Malformed JSON: {0}")]
+    MalformedJson(String),
+
+    /// A field is missing, has the wrong type or is inconsistent with
+    /// another, e.g. a start height after the end height
+    #[error("{0}")]
+    InvalidRequest(String),
+
+    #[error("Invalid viewing key: {0}")]
+    InvalidViewingKey(String),
+
+    /// The `network` field disagrees with the viewing key
+    #[error("{0}")]
+    NetworkMismatch(String),
+
+    /// The request doesn't fit the resource's current state, e.g. cancelling
+    /// a finished scan job
+    #[error("{0}")]
+    Conflict(String),
+
+    /// The chain data backend doesn't have the transaction or block
+    #[error("{0}")]
+    NotFound(String),
+
+    /// Nothing in the transaction decrypts with the viewing key
+    #[error("No outputs in this transaction could be decrypted with the provided viewing key")]
+    NotForKey,
+
+    /// The chain data backend follows a different chain than the key
+    #[error("{0}")]
+    ChainMismatch(String),
+
+    /// The requested range ends past the backend's chain tip
+    #[error("{0}")]
+    BeyondChainTip(String),
+
+    /// The viewing key can't serve the request, e.g. it has no receiver of
+    /// a requested type
+    #[error("{0}")]
+    Unprocessable(String),
+
+    /// The request names a lightwalletd server the operator hasn't approved
+    #[error("{0}")]
+    UpstreamNotAllowed(String),
+
+    /// The backend answered with data that failed verification
+    #[error("Rejected upstream data: {0}")]
+    UpstreamRejected(String),
+
+    /// The backend couldn't be reached
+    #[error("{0}")]
+    UpstreamUnavailable(String),
+
+    /// The backend took too long to answer
+    #[error("{0}")]
+    UpstreamTimeout(String),
+
+    /// The backend reported an error or answered with something unusable
+    #[error("{0}")]
+    Upstream(String),
+
+    /// The service has too much queued work to take the request now
+    #[error("{0}")]
+    Busy(String),
+
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl ServiceError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ServiceError::MalformedJson(_)
+            | ServiceError::InvalidRequest(_)
+            | ServiceError::InvalidViewingKey(_)
+            | ServiceError::NetworkMismatch(_) => StatusCode::BAD_REQUEST,
+            ServiceError::UpstreamNotAllowed(_) => StatusCode::FORBIDDEN,
+            ServiceError::NotFound(_) => StatusCode::NOT_FOUND,
+            ServiceError::Conflict(_) => StatusCode::CONFLICT,
+            ServiceError::NotForKey
+            | ServiceError::ChainMismatch(_)
+            | ServiceError::BeyondChainTip(_)
+            | ServiceError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ServiceError::UpstreamRejected(_)
+            | ServiceError::UpstreamUnavailable(_)
+            | ServiceError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ServiceError::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ServiceError::Busy(_) => StatusCode::TOO_MANY_REQUESTS,
+            ServiceError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Machine-readable code, part of the API contract
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServiceError::MalformedJson(_) => "malformed_json",
+            ServiceError::InvalidRequest(_) => "invalid_request",
+            ServiceError::InvalidViewingKey(_) => "invalid_viewing_key",
+            ServiceError::NetworkMismatch(_) => "network_mismatch",
+            ServiceError::NotFound(_) => "not_found",
+            ServiceError::Conflict(_) => "conflict",
+            ServiceError::NotForKey => "not_for_key",
+            ServiceError::ChainMismatch(_) => "chain_mismatch",
+            ServiceError::BeyondChainTip(_) => "beyond_chain_tip",
+            ServiceError::Unprocessable(_) => "unprocessable",
+            ServiceError::UpstreamNotAllowed(_) => "upstream_not_allowed",
+            ServiceError::UpstreamRejected(_) => "upstream_rejected",
+            ServiceError::UpstreamUnavailable(_) => "upstream_unavailable",
+            ServiceError::UpstreamTimeout(_) => "upstream_timeout",
+            ServiceError::Upstream(_) => "upstream_error",
+            ServiceError::Busy(_) => "busy",
+            ServiceError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Classify a failed HTTP request to an upstream API
+    pub fn from_reqwest(context: String, err: reqwest::Error) -> Self {
+        let message = format!("{}: {}", context, err);
+        if err.is_timeout() {
+            ServiceError::UpstreamTimeout(message)
+        } else if err.is_connect() {
+            ServiceError::UpstreamUnavailable(message)
+        } else {
+            ServiceError::Upstream(message)
+        }
+    }
+}
+
+impl ServiceError {
+    /// The JSON error body, also sent as the last event of a failed stream
+    pub fn body(&self) -> serde_json::Value {
+        json!({
+            "success": false,
+            "code": self.code(),
+            "error": self.to_string(),
+        })
+    }
+}
+
+impl IntoResponse for ServiceError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self.body())).into_response()
+    }
+}
+
+impl From<ValidationError> for ServiceError {
+    fn from(e: ValidationError) -> Self {
+        match e {
+            ValidationError::ViewingKey(reason) => ServiceError::InvalidViewingKey(reason),
+            e => ServiceError::InvalidRequest(e.to_string()),
+        }
+    }
+}
+
+impl From<JsonRejection> for ServiceError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::JsonSyntaxError(e) => ServiceError::MalformedJson(e.body_text()),
+            e => ServiceError::InvalidRequest(e.body_text()),
+        }
+    }
+}
+
+/// `Json` extractor whose rejections use the API's error body
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+    S: Send + Sync,
+{
+    type Rejection = ServiceError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        Ok(ApiJson(value))
+    }
+}
@@ -0,0 +1,615 @@
+//! Scans that run in the background, independent of any one request
+//!
+//! A submitted scan gets a job ID and runs once one of the instance's
+//! `jobs.max_concurrent` slots is free, so a burst of long scans queues up
+//! instead of hammering the backend. Progress and the transactions found so
+//! far can be read at any time. With `jobs.state_dir` set, each job is saved
+//! there after every batch; jobs cut off by a restart come back as
+//! `interrupted` and resume from the last height they scanned.
+//!
+//! Jobs hold viewing keys, so the registry doesn't keep them forever: the
+//! queue is bounded, and finished jobs are forgotten, files and all, once
+//! they expire or too many newer ones have finished.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::AbortHandle;
+use tracing::{error, warn};
+
+use crate::backend::TransparentOutput;
+use crate::balance::{balance, Balance, DEFAULT_MIN_CONFIRMATIONS};
+use crate::error::{Result, ServiceError};
+use crate::scan::{NoteSpend, Scan, ScanEvent};
+use crate::{ScanTransactionsRequest, Transaction};
+
+/// Scan events buffered before the scan waits for the job to record them
+const EVENT_BUFFER: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting for a free slot
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+    /// Was queued or running when the service stopped
+    Interrupted,
+}
+
+impl JobState {
+    fn name(self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Completed => "completed",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+            JobState::Interrupted => "interrupted",
+        }
+    }
+
+    /// Whether the job can pick up where it stopped
+    fn resumable(self) -> bool {
+        matches!(
+            self,
+            JobState::Failed | JobState::Cancelled | JobState::Interrupted
+        )
+    }
+
+    fn finished(self) -> bool {
+        !matches!(self, JobState::Queued | JobState::Running)
+    }
+}
+
+/// Limits on the jobs one instance runs and keeps
+#[derive(Debug, Clone)]
+pub struct JobOptions {
+    /// Jobs that may run at once
+    pub max_concurrent: usize,
+    /// Jobs that may wait for a slot; more are refused as `Busy`
+    pub max_queued: usize,
+    /// Finished jobs kept, newest first
+    pub max_finished: usize,
+    /// How long a finished job is kept
+    pub retention: Duration,
+    /// Where jobs are saved, if anywhere
+    pub state_dir: Option<PathBuf>,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        JobOptions {
+            max_concurrent: 2,
+            max_queued: 100,
+            max_finished: 100,
+            retention: Duration::from_secs(24 * 60 * 60),
+            state_dir: None,
+        }
+    }
+}
+
+/// Everything saved about a job, enough to resume it
+#[derive(Clone, Serialize, Deserialize)]
+struct JobRecord {
+    id: String,
+    request: ScanTransactionsRequest,
+    state: JobState,
+    /// Last height scanned; a resumed job continues after it
+    scanned_height: Option<u64>,
+    transactions: Vec<Transaction>,
+    /// Spends of the notes in `transactions`
+    #[serde(default)]
+    spends: Vec<NoteSpend>,
+    notes_found: usize,
+    eta_secs: Option<u64>,
+    /// Error body of a failed job
+    error: Option<serde_json::Value>,
+    /// Latest chain tip the scan saw, to count confirmations to
+    #[serde(default)]
+    chain_tip: Option<u64>,
+    #[serde(default = "default_min_confirmations")]
+    min_confirmations: u32,
+    /// Unspent outputs of the key's transparent addresses when the scan
+    /// finished, if it could look them up
+    #[serde(default)]
+    transparent_outputs: Option<Vec<TransparentOutput>>,
+    /// Unix time the job last stopped, which starts its retention period
+    #[serde(default)]
+    finished_at: Option<u64>,
+}
+
+fn default_min_confirmations() -> u32 {
+    DEFAULT_MIN_CONFIRMATIONS
+}
+
+/// A job as reported to clients, without the viewing key
+#[derive(Serialize)]
+pub struct JobStatus {
+    pub id: String,
+    pub state: JobState,
+    pub start_height: u64,
+    pub end_height: u64,
+    pub scanned_height: Option<u64>,
+    pub blocks_scanned: u64,
+    pub total_blocks: u64,
+    pub transactions_found: usize,
+    pub notes_found: usize,
+    /// Estimated seconds left while running
+    pub eta_secs: Option<u64>,
+    pub error: Option<serde_json::Value>,
+}
+
+/// Transactions a job has found so far
+#[derive(Serialize)]
+pub struct JobResults {
+    pub state: JobState,
+    pub scanned_height: Option<u64>,
+    pub transactions: Vec<Transaction>,
+    pub spends: Vec<NoteSpend>,
+    /// Balance of the notes found so far
+    pub balance: Balance,
+}
+
+struct Job {
+    record: JobRecord,
+    task: Option<AbortHandle>,
+}
+
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, Job>>,
+    slots: Arc<Semaphore>,
+    options: JobOptions,
+    /// Held while a job file is written or removed, so an older save can't
+    /// land after a newer one or bring back a forgotten job
+    files: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl JobRegistry {
+    /// Run jobs within `options`, saving them to its `state_dir` and
+    /// loading any saved there before
+    pub fn new(options: JobOptions) -> anyhow::Result<Self> {
+        let mut jobs = HashMap::new();
+        if let Some(dir) = &options.state_dir {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create job directory {}", dir.display()))?;
+            for mut record in load_records(dir)? {
+                if !record.state.finished() {
+                    record.state = JobState::Interrupted;
+                    record.eta_secs = None;
+                }
+                record.finished_at.get_or_insert_with(now);
+                jobs.insert(record.id.clone(), Job { record, task: None });
+            }
+            for id in expired(&jobs, &options) {
+                jobs.remove(&id);
+                if let Err(e) = std::fs::remove_file(record_path(dir, &id)) {
+                    warn!("Failed to remove expired scan job {}: {}", id, e);
+                }
+            }
+        }
+
+        Ok(JobRegistry {
+            jobs: Mutex::new(jobs),
+            slots: Arc::new(Semaphore::new(options.max_concurrent)),
+            options,
+            files: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    /// Queue a prepared scan of `request` and return the job's ID
+    ///
+    /// Fails with `Busy` when `max_queued` jobs are waiting already.
+    pub fn submit(
+        self: &Arc<Self>,
+        request: ScanTransactionsRequest,
+        scan: Scan,
+    ) -> Result<JobStatus> {
+        self.evict();
+        self.check_queue()?;
+        let id = hex::encode(rand::random::<[u8; 16]>());
+        let record = JobRecord {
+            id: id.clone(),
+            request,
+            state: JobState::Queued,
+            scanned_height: None,
+            transactions: Vec::new(),
+            spends: Vec::new(),
+            notes_found: 0,
+            eta_secs: None,
+            error: None,
+            chain_tip: Some(scan.chain_tip()),
+            min_confirmations: scan.min_confirmations(),
+            transparent_outputs: None,
+            finished_at: None,
+        };
+        let status = status_of(&record);
+        self.lock().insert(id.clone(), Job { record, task: None });
+        self.start(id, scan);
+        Ok(status)
+    }
+
+    /// The request and start height to prepare the scan resuming job `id`
+    pub fn resume_point(&self, id: &str) -> Result<(ScanTransactionsRequest, u64)> {
+        self.evict();
+        let jobs = self.lock();
+        let record = &find(&jobs, id)?.record;
+        if !record.state.resumable() {
+            return Err(ServiceError::Conflict(format!(
+                "Scan job {} is {}; only failed, cancelled or interrupted jobs resume",
+                id,
+                record.state.name()
+            )));
+        }
+
+        let start = record
+            .scanned_height
+            .map_or(record.request.start_height, |height| height + 1);
+        Ok((record.request.clone(), start))
+    }
+
+    /// Continue job `id` with a scan prepared from `resume_point`
+    ///
+    /// The scan also looks for spends of the notes found before it.
+    pub fn resume(self: &Arc<Self>, id: &str, mut scan: Scan) -> Result<JobStatus> {
+        self.check_queue()?;
+        let status = {
+            let mut jobs = self.lock();
+            let record = &mut find_mut(&mut jobs, id)?.record;
+            if !record.state.resumable() {
+                return Err(ServiceError::Conflict(format!(
+                    "Scan job {} was resumed already",
+                    id
+                )));
+            }
+            record.state = JobState::Queued;
+            record.error = None;
+            record.finished_at = None;
+            record.chain_tip = Some(scan.chain_tip());
+            record.min_confirmations = scan.min_confirmations();
+            scan.watch(
+                record
+                    .transactions
+                    .iter()
+                    .flat_map(|transaction| &transaction.notes)
+                    .filter_map(|note| note.nullifier.clone()),
+            );
+            status_of(record)
+        };
+        self.start(id.to_string(), scan);
+        Ok(status)
+    }
+
+    /// Mark job `id` completed, for a resume with nothing left to scan
+    pub async fn complete(&self, id: &str) -> Result<JobStatus> {
+        let status = {
+            let mut jobs = self.lock();
+            let record = &mut find_mut(&mut jobs, id)?.record;
+            if !record.state.resumable() {
+                return Err(ServiceError::Conflict(format!(
+                    "Scan job {} was resumed already",
+                    id
+                )));
+            }
+            record.state = JobState::Completed;
+            record.error = None;
+            record.finished_at = Some(now());
+            status_of(record)
+        };
+        self.save(id).await;
+        Ok(status)
+    }
+
+    pub fn status(&self, id: &str) -> Result<JobStatus> {
+        self.evict();
+        Ok(status_of(&find(&self.lock(), id)?.record))
+    }
+
+    pub fn results(&self, id: &str) -> Result<JobResults> {
+        self.evict();
+        let jobs = self.lock();
+        let record = &find(&jobs, id)?.record;
+        // Records saved before the tip was kept count to the last height
+        // scanned
+        let chain_tip = record
+            .chain_tip
+            .or(record.scanned_height)
+            .unwrap_or(record.request.start_height);
+        Ok(JobResults {
+            state: record.state,
+            scanned_height: record.scanned_height,
+            transactions: record.transactions.clone(),
+            spends: record.spends.clone(),
+            balance: balance(
+                &record.transactions,
+                &record.spends,
+                record.transparent_outputs.as_deref(),
+                chain_tip,
+                record.min_confirmations,
+            ),
+        })
+    }
+
+    /// Stop a queued or running job; what it found so far is kept
+    pub async fn cancel(&self, id: &str) -> Result<JobStatus> {
+        let status = {
+            let mut jobs = self.lock();
+            let job = find_mut(&mut jobs, id)?;
+            if !matches!(job.record.state, JobState::Queued | JobState::Running) {
+                return Err(ServiceError::Conflict(format!(
+                    "Scan job {} is {} and can't be cancelled",
+                    id,
+                    job.record.state.name()
+                )));
+            }
+            if let Some(task) = job.task.take() {
+                task.abort();
+            }
+            job.record.state = JobState::Cancelled;
+            job.record.eta_secs = None;
+            job.record.finished_at = Some(now());
+            status_of(&job.record)
+        };
+        self.save(id).await;
+        Ok(status)
+    }
+
+    /// Fail with `Busy` if no more jobs may wait for a slot
+    fn check_queue(&self) -> Result<()> {
+        let queued = self
+            .lock()
+            .values()
+            .filter(|job| job.record.state == JobState::Queued)
+            .count();
+        if queued >= self.options.max_queued {
+            return Err(ServiceError::Busy(format!(
+                "{} scan jobs are already waiting to run; try again later",
+                queued
+            )));
+        }
+        Ok(())
+    }
+
+    /// Forget finished jobs past their retention period or beyond the
+    /// newest `max_finished`, and remove their files
+    fn evict(&self) {
+        let evicted = {
+            let mut jobs = self.lock();
+            let evicted = expired(&jobs, &self.options);
+            for id in &evicted {
+                jobs.remove(id);
+            }
+            evicted
+        };
+        let Some(dir) = self.options.state_dir.clone() else {
+            return;
+        };
+        if evicted.is_empty() {
+            return;
+        }
+
+        let files = self.files.clone();
+        tokio::spawn(async move {
+            let _files = files.lock().await;
+            for id in evicted {
+                if let Err(e) = tokio::fs::remove_file(record_path(&dir, &id)).await {
+                    warn!("Failed to remove expired scan job {}: {}", id, e);
+                }
+            }
+        });
+    }
+
+    fn start(self: &Arc<Self>, id: String, scan: Scan) {
+        let task = tokio::spawn(self.clone().run(id.clone(), scan));
+        if let Some(job) = self.lock().get_mut(&id) {
+            job.task = Some(task.abort_handle());
+        }
+    }
+
+    async fn run(self: Arc<Self>, id: String, scan: Scan) {
+        let _slot = self
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Job slots are never closed");
+        // A resumed job's scan counts from where it picks up
+        let mut notes_before = 0;
+        self.update(&id, |record| {
+            record.state = JobState::Running;
+            notes_before = record.notes_found;
+        });
+        self.save(&id).await;
+
+        let (events, mut receiver) = mpsc::channel(EVENT_BUFFER);
+        let scan = async move { scan.run(Some(&events)).await };
+        let follow = async {
+            while let Some(event) = receiver.recv().await {
+                // Save once per batch of blocks, not for every note
+                let batch_done = matches!(event, ScanEvent::Progress(_));
+                self.update(&id, |record| match event {
+                    ScanEvent::Transaction(transaction) => record.transactions.push(transaction),
+                    ScanEvent::Spend(spend) => record.spends.push(spend),
+                    ScanEvent::Reorg(reorg) => {
+                        record
+                            .transactions
+                            .retain(|transaction| transaction.height <= reorg.fork_height);
+                        record
+                            .spends
+                            .retain(|spend| spend.height <= reorg.fork_height);
+                        record.scanned_height = Some(reorg.fork_height)
+                            .filter(|height| *height >= record.request.start_height);
+                    }
+                    ScanEvent::Progress(progress) => {
+                        record.scanned_height = Some(progress.current_height);
+                        record.notes_found = notes_before + progress.notes_found;
+                        record.eta_secs = progress.eta_secs;
+                    }
+                });
+                if batch_done {
+                    self.save(&id).await;
+                }
+            }
+        };
+        let (result, ()) = tokio::join!(scan, follow);
+
+        self.update(&id, |record| {
+            record.eta_secs = None;
+            record.finished_at = Some(now());
+            match result {
+                Ok(results) => {
+                    record.state = JobState::Completed;
+                    record.chain_tip = Some(results.balance.chain_tip);
+                    record.transparent_outputs = results.transparent_outputs;
+                }
+                Err(e) => {
+                    error!("Scan job {} failed: {}", record.id, e);
+                    record.state = JobState::Failed;
+                    record.error = Some(e.body());
+                }
+            }
+        });
+        self.save(&id).await;
+        self.evict();
+    }
+
+    /// Change a job's record, unless it has been cancelled
+    fn update(&self, id: &str, change: impl FnOnce(&mut JobRecord)) {
+        let mut jobs = self.lock();
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        if job.record.state == JobState::Cancelled {
+            return;
+        }
+        change(&mut job.record);
+    }
+
+    /// Write job `id` as it is now, off the async runtime's threads
+    async fn save(&self, id: &str) {
+        let Some(dir) = self.options.state_dir.clone() else {
+            return;
+        };
+        let files = self.files.clone().lock_owned().await;
+        // Taken under the file lock, so whichever save runs last writes the
+        // latest record; a forgotten job stays gone
+        let Some(record) = self.lock().get(id).map(|job| job.record.clone()) else {
+            return;
+        };
+        // The write holds the lock until it's done, even if this job's task
+        // is aborted meanwhile
+        let saved = tokio::task::spawn_blocking(move || {
+            let _files = files;
+            save_record(&dir, &record)
+        })
+        .await;
+        match saved {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to save scan job {}: {:#}", id, e),
+            Err(e) => warn!("Failed to save scan job {}: {}", id, e),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Job>> {
+        self.jobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn find<'a>(jobs: &'a HashMap<String, Job>, id: &str) -> Result<&'a Job> {
+    jobs.get(id)
+        .ok_or_else(|| ServiceError::NotFound(format!("No scan job {}", id)))
+}
+
+fn find_mut<'a>(jobs: &'a mut HashMap<String, Job>, id: &str) -> Result<&'a mut Job> {
+    jobs.get_mut(id)
+        .ok_or_else(|| ServiceError::NotFound(format!("No scan job {}", id)))
+}
+
+/// Finished jobs to forget: those past the retention period, then the
+/// oldest beyond `max_finished`
+fn expired(jobs: &HashMap<String, Job>, options: &JobOptions) -> Vec<String> {
+    let mut finished: Vec<(u64, &String)> = jobs
+        .values()
+        .filter(|job| job.record.state.finished())
+        .map(|job| (job.record.finished_at.unwrap_or(0), &job.record.id))
+        .collect();
+    finished.sort_unstable_by(|a, b| b.cmp(a));
+
+    let cutoff = now().saturating_sub(options.retention.as_secs());
+    finished
+        .into_iter()
+        .enumerate()
+        .filter(|(rank, (finished_at, _))| *rank >= options.max_finished || *finished_at < cutoff)
+        .map(|(_, (_, id))| id.clone())
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn status_of(record: &JobRecord) -> JobStatus {
+    let request = &record.request;
+    JobStatus {
+        id: record.id.clone(),
+        state: record.state,
+        start_height: request.start_height,
+        end_height: request.end_height,
+        scanned_height: record.scanned_height,
+        blocks_scanned: record
+            .scanned_height
+            .map_or(0, |height| height - request.start_height + 1),
+        total_blocks: request.end_height - request.start_height + 1,
+        transactions_found: record.transactions.len(),
+        notes_found: record.notes_found,
+        eta_secs: record.eta_secs,
+        error: record.error.clone(),
+    }
+}
+
+fn load_records(dir: &Path) -> anyhow::Result<Vec<JobRecord>> {
+    let mut records = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let text = std::fs::read_to_string(&path)?;
+            let record = serde_json::from_str(&text)
+                .with_context(|| format!("Invalid scan job {}", path.display()))?;
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// Write the record next to its final name and rename it into place, so a
+/// crash never leaves half a file
+fn save_record(dir: &Path, record: &JobRecord) -> anyhow::Result<()> {
+    let path = record_path(dir, &record.id);
+    let partial = dir.join(format!("{}.json.partial", record.id));
+
+    // The record holds a viewing key; keep it from other users
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(&partial)?;
+    file.write_all(&serde_json::to_vec(record)?)?;
+    std::fs::rename(&partial, &path)?;
+    Ok(())
+}
+
+fn record_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.json", id))
+}
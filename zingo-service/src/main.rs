@@ -1,79 +1,182 @@
 use axum::{
-    extract::State,
-    http::{HeaderValue, Method, StatusCode},
+    extract::{Path, State},
+    http::{Method, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
     routing::{get, post},
     Json, Router,
 };
+use futures_util::stream::{self, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use std::sync::Arc;
-use tower_http::cors::{Any, CorsLayer};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use clap::Parser;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tracing::{info, error};
 
+mod address;
+mod backend;
+mod balance;
+mod config;
 mod decrypt;
+mod error;
+mod jobs;
+mod network;
+mod reorg;
 mod scan;
+mod upstream;
 
-use decrypt::decrypt_memo;
-use scan::scan_transactions;
+#[cfg(test)]
+mod mock_jsonrpc;
+#[cfg(test)]
+mod mock_lightwalletd;
+#[cfg(test)]
+mod tests;
+
+use address::derive_addresses;
+use backend::cache::CacheStats;
+use backend::Backends;
+use config::{Cli, Config, LogFormat};
+use decrypt::{decrypt_memo, payment_summary};
+use error::{ApiJson, ServiceError};
+use jobs::{JobRegistry, JobResults, JobStatus};
+use scan::{scan_transactions, Scan, ScanEvent, ScanResults};
+use zcash_wasm::address::DerivedAddress;
+use zcash_wasm::compact::CompactNote;
+use zcash_wasm::decrypt::DecryptedNote;
+use zcash_wasm::memo::TypedMemo;
+
+/// Scan events buffered for a slow streaming client before the scan waits
+const SCAN_EVENT_BUFFER: usize = 16;
 
-#[derive(Clone)]
 struct AppState {
-    // Add any shared state here (e.g., connection pools)
+    /// Chain data source chosen by the operator
+    backends: Backends,
+    /// Origins browsers may call the API from
+    cors: AllowOrigin,
+    /// Background scans
+    jobs: Arc<JobRegistry>,
+    /// Confirmations a note needs to count as confirmed, unless a scan
+    /// request says otherwise
+    min_confirmations: u32,
 }
 
 #[derive(Deserialize)]
 struct DecryptMemoRequest {
     ufvk: String,
     txid: String,
-    #[serde(default = "default_lightwalletd_url")]
-    lightwalletd_url: String,
+    /// "main", "test" or "regtest"; must match the viewing key if given
+    #[serde(default)]
+    network: Option<String>,
+    /// Defaults to a public server for the key's network
+    #[serde(default)]
+    lightwalletd_url: Option<String>,
 }
 
 #[derive(Serialize)]
 struct DecryptMemoResponse {
     success: bool,
-    memo: Option<String>,
+    /// ZIP 302 memo of the payment (see `payment_summary`)
+    memo: Option<TypedMemo>,
+    /// Value of the payment in zatoshis (see `payment_summary`)
     amount: Option<i64>,
     txid: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
+    notes: Vec<DecryptedNote>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 struct ScanTransactionsRequest {
     ufvk: String,
     start_height: u64,
     end_height: u64,
-    #[serde(default = "default_lightwalletd_url")]
-    lightwalletd_url: String,
+    /// "main", "test" or "regtest"; must match the viewing key if given
+    #[serde(default)]
+    network: Option<String>,
+    /// Defaults to a public server for the key's network
+    #[serde(default)]
+    lightwalletd_url: Option<String>,
+    /// Confirmations a note needs to count toward the confirmed balance;
+    /// the service's `balance.min_confirmations` when unset
+    #[serde(default)]
+    min_confirmations: Option<u32>,
 }
 
 #[derive(Serialize)]
 struct ScanTransactionsResponse {
     success: bool,
-    transactions: Vec<Transaction>,
-    balance: Balance,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
+    #[serde(flatten)]
+    results: ScanResults,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone)]
 struct Transaction {
     txid: String,
     height: u64,
+    /// Display-order hash of the block the transaction was found in
+    #[serde(default)]
+    block_hash: String,
+    /// Value received on external addresses, in zatoshis
     amount: i64,
+    /// Change the key holder sent back to its internal addresses, in zatoshis
+    #[serde(default)]
+    change: i64,
     memo: Option<String>,
     timestamp: Option<i64>,
+    /// Notes received, with the nullifiers that will spend them
+    #[serde(default)]
+    notes: Vec<CompactNote>,
+}
+
+#[derive(Deserialize)]
+struct DeriveAddressesRequest {
+    ufvk: String,
+    #[serde(default)]
+    diversifier_index: u32,
+    #[serde(default = "default_address_count")]
+    count: u32,
+    #[serde(default)]
+    receivers: Vec<String>,
+    #[serde(default)]
+    network: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DeriveAddressesResponse {
+    success: bool,
+    addresses: Vec<DerivedAddress>,
 }
 
 #[derive(Serialize)]
-struct Balance {
-    confirmed: i64,
-    unconfirmed: i64,
-    total: i64,
+struct ScanJobResponse {
+    success: bool,
+    job: JobStatus,
+}
+
+#[derive(Serialize)]
+struct ScanJobResultsResponse {
+    success: bool,
+    #[serde(flatten)]
+    results: JobResults,
+}
+
+#[derive(Serialize)]
+struct BlockCacheResponse {
+    success: bool,
+    enabled: bool,
+    /// Counters since startup, when the cache is enabled
+    stats: Option<CacheStats>,
+}
+
+#[derive(Serialize)]
+struct LightwalletdServersResponse {
+    success: bool,
+    /// URLs requests may pass as `lightwalletd_url`; `*` admits any server
+    servers: Vec<String>,
 }
 
-fn default_lightwalletd_url() -> String {
-    "https://zec.rocks:443".to_string()
+fn default_address_count() -> u32 {
+    1
 }
 
 async fn health_check() -> Json<serde_json::Value> {
@@ -85,97 +188,346 @@ async fn health_check() -> Json<serde_json::Value> {
 }
 
 async fn decrypt_memo_handler(
-    State(_state): State<Arc<AppState>>,
-    Json(req): Json<DecryptMemoRequest>,
-) -> Result<Json<DecryptMemoResponse>, StatusCode> {
+    State(state): State<Arc<AppState>>,
+    ApiJson(req): ApiJson<DecryptMemoRequest>,
+) -> Result<Json<DecryptMemoResponse>, ServiceError> {
     info!("Decrypt memo request for txid: {}", req.txid);
 
-    match decrypt_memo(&req.ufvk, &req.txid, &req.lightwalletd_url).await {
-        Ok((memo, amount)) => Ok(Json(DecryptMemoResponse {
-            success: true,
-            memo: Some(memo),
-            amount: Some(amount),
-            txid: req.txid,
-            error: None,
-        })),
-        Err(e) => {
-            error!("Decryption error: {}", e);
-            Ok(Json(DecryptMemoResponse {
-                success: false,
-                memo: None,
-                amount: None,
-                txid: req.txid,
-                error: Some(e.to_string()),
-            }))
-        }
-    }
+    let notes = decrypt_memo(
+        &state.backends,
+        &req.ufvk,
+        &req.txid,
+        req.network.as_deref(),
+        req.lightwalletd_url.as_deref(),
+    )
+    .await
+    .inspect_err(|e| error!("Decryption error: {}", e))?;
+
+    let (amount, memo) = payment_summary(&notes);
+    Ok(Json(DecryptMemoResponse {
+        success: true,
+        memo,
+        amount: Some(amount as i64),
+        txid: req.txid,
+        notes,
+    }))
 }
 
 async fn scan_transactions_handler(
-    State(_state): State<Arc<AppState>>,
-    Json(req): Json<ScanTransactionsRequest>,
-) -> Result<Json<ScanTransactionsResponse>, StatusCode> {
+    State(state): State<Arc<AppState>>,
+    ApiJson(req): ApiJson<ScanTransactionsRequest>,
+) -> Result<Json<ScanTransactionsResponse>, ServiceError> {
     info!(
         "Scan transactions request from height {} to {}",
         req.start_height, req.end_height
     );
 
-    match scan_transactions(&req.ufvk, req.start_height, req.end_height, &req.lightwalletd_url).await {
-        Ok((transactions, balance)) => Ok(Json(ScanTransactionsResponse {
-            success: true,
-            transactions,
-            balance,
-            error: None,
-        })),
-        Err(e) => {
-            error!("Scan error: {}", e);
-            Ok(Json(ScanTransactionsResponse {
-                success: false,
-                transactions: vec![],
-                balance: Balance {
-                    confirmed: 0,
-                    unconfirmed: 0,
-                    total: 0,
+    let results = scan_transactions(
+        &state.backends,
+        &req.ufvk,
+        req.start_height,
+        req.end_height,
+        req.network.as_deref(),
+        req.lightwalletd_url.as_deref(),
+        req.min_confirmations.unwrap_or(state.min_confirmations),
+    )
+    .await
+    .inspect_err(|e| error!("Scan error: {}", e))?;
+
+    Ok(Json(ScanTransactionsResponse {
+        success: true,
+        results,
+    }))
+}
+
+/// Scan like `/api/scan-transactions`, streaming Server-Sent Events
+///
+/// `progress` follows every batch of blocks, `transaction` every
+/// transaction found and `spend` every spend of a note found; the stream
+/// ends with `done`, carrying the same body as `/api/scan-transactions`, or
+/// `error`. Invalid requests fail before the stream starts, with the usual
+/// error status.
+async fn scan_transactions_stream_handler(
+    State(state): State<Arc<AppState>>,
+    ApiJson(req): ApiJson<ScanTransactionsRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ServiceError> {
+    info!(
+        "Streaming scan request from height {} to {}",
+        req.start_height, req.end_height
+    );
+
+    let scan = Scan::prepare(
+        &state.backends,
+        &req.ufvk,
+        req.start_height,
+        req.end_height,
+        req.network.as_deref(),
+        req.lightwalletd_url.as_deref(),
+        req.min_confirmations.unwrap_or(state.min_confirmations),
+    )
+    .await
+    .inspect_err(|e| error!("Scan error: {}", e))?;
+
+    // A disconnected client drops the receiver, which stops the scan
+    let (events, receiver) = mpsc::channel(SCAN_EVENT_BUFFER);
+    let scan = tokio::spawn(async move { scan.run(Some(&events)).await });
+
+    let progress = ReceiverStream::new(receiver).map(|event| match event {
+        ScanEvent::Progress(progress) => sse_event("progress", &progress),
+        ScanEvent::Transaction(transaction) => sse_event("transaction", &transaction),
+        ScanEvent::Spend(spend) => sse_event("spend", &spend),
+        ScanEvent::Reorg(reorg) => sse_event("reorg", &reorg),
+    });
+    let outcome = stream::once(async move {
+        let result = scan
+            .await
+            .map_err(|e| ServiceError::Internal(format!("Scan task failed: {}", e)))
+            .and_then(|result| result);
+        match result {
+            Ok(results) => sse_event(
+                "done",
+                &ScanTransactionsResponse {
+                    success: true,
+                    results,
                 },
-                error: Some(e.to_string()),
-            }))
+            ),
+            Err(e) => {
+                error!("Scan error: {}", e);
+                sse_event("error", &e.body())
+            }
         }
-    }
+    });
+
+    Ok(Sse::new(progress.chain(outcome)).keep_alive(KeepAlive::default()))
 }
 
-#[tokio::main]
-async fn main() {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "zingo_service=info,tower_http=debug".into()),
+fn sse_event(name: &str, data: &impl Serialize) -> Result<Event, Infallible> {
+    Ok(Event::default()
+        .event(name)
+        .json_data(data)
+        .expect("Scan events serialize to JSON"))
+}
+
+/// Start a scan in the background and return its job
+async fn create_scan_job_handler(
+    State(state): State<Arc<AppState>>,
+    ApiJson(req): ApiJson<ScanTransactionsRequest>,
+) -> Result<(StatusCode, Json<ScanJobResponse>), ServiceError> {
+    info!(
+        "Scan job request from height {} to {}",
+        req.start_height, req.end_height
+    );
+
+    let scan = Scan::prepare(
+        &state.backends,
+        &req.ufvk,
+        req.start_height,
+        req.end_height,
+        req.network.as_deref(),
+        req.lightwalletd_url.as_deref(),
+        req.min_confirmations.unwrap_or(state.min_confirmations),
+    )
+    .await
+    .inspect_err(|e| error!("Scan error: {}", e))?;
+
+    let job = state.jobs.submit(req, scan)?;
+    info!("Queued scan job {}", job.id);
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ScanJobResponse { success: true, job }),
+    ))
+}
+
+async fn scan_job_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ScanJobResponse>, ServiceError> {
+    Ok(Json(ScanJobResponse {
+        success: true,
+        job: state.jobs.status(&id)?,
+    }))
+}
+
+/// Transactions a job has found so far, whether or not it has finished
+async fn scan_job_results_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ScanJobResultsResponse>, ServiceError> {
+    Ok(Json(ScanJobResultsResponse {
+        success: true,
+        results: state.jobs.results(&id)?,
+    }))
+}
+
+async fn cancel_scan_job_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ScanJobResponse>, ServiceError> {
+    info!("Cancelling scan job {}", id);
+    Ok(Json(ScanJobResponse {
+        success: true,
+        job: state.jobs.cancel(&id).await?,
+    }))
+}
+
+/// Continue a failed, cancelled or interrupted job after the last height
+/// it scanned
+async fn resume_scan_job_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ScanJobResponse>, ServiceError> {
+    let (req, start_height) = state.jobs.resume_point(&id)?;
+    info!("Resuming scan job {} from height {}", id, start_height);
+
+    let job = if start_height > req.end_height {
+        state.jobs.complete(&id).await?
+    } else {
+        let scan = Scan::prepare(
+            &state.backends,
+            &req.ufvk,
+            start_height,
+            req.end_height,
+            req.network.as_deref(),
+            req.lightwalletd_url.as_deref(),
+            req.min_confirmations.unwrap_or(state.min_confirmations),
         )
-        .init();
+        .await
+        .inspect_err(|e| error!("Scan error: {}", e))?;
+        state.jobs.resume(&id, scan)?
+    };
 
-    let state = Arc::new(AppState {});
+    Ok(Json(ScanJobResponse { success: true, job }))
+}
 
+async fn derive_addresses_handler(
+    State(_state): State<Arc<AppState>>,
+    ApiJson(req): ApiJson<DeriveAddressesRequest>,
+) -> Result<Json<DeriveAddressesResponse>, ServiceError> {
+    info!(
+        "Derive addresses request for {} indices from {}",
+        req.count, req.diversifier_index
+    );
+
+    // Sapling and Orchard derivation is CPU-bound; keep it off the runtime
+    let addresses = tokio::task::spawn_blocking(move || {
+        derive_addresses(
+            &req.ufvk,
+            req.diversifier_index,
+            req.count,
+            &req.receivers,
+            req.network.as_deref(),
+        )
+    })
+    .await
+    .map_err(|e| ServiceError::Internal(format!("Derivation task failed: {}", e)))?
+    .inspect_err(|e| error!("Address derivation error: {}", e))?;
+
+    Ok(Json(DeriveAddressesResponse {
+        success: true,
+        addresses,
+    }))
+}
+
+async fn lightwalletd_servers_handler(
+    State(state): State<Arc<AppState>>,
+) -> Json<LightwalletdServersResponse> {
+    Json(LightwalletdServersResponse {
+        success: true,
+        servers: state.backends.upstreams().servers(),
+    })
+}
+
+async fn block_cache_handler(State(state): State<Arc<AppState>>) -> Json<BlockCacheResponse> {
+    let stats = state.backends.block_cache().map(|cache| cache.stats());
+    Json(BlockCacheResponse {
+        success: true,
+        enabled: stats.is_some(),
+        stats,
+    })
+}
+
+fn app(state: Arc<AppState>) -> Router {
     // Configure CORS
     let cors = CorsLayer::new()
-        .allow_origin(Any) // In production, restrict to your Vercel domain
-        .allow_methods([Method::GET, Method::POST])
+        .allow_origin(state.cors.clone())
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
         .allow_headers(Any);
 
-    // Build router
-    let app = Router::new()
+    Router::new()
         .route("/health", get(health_check))
         .route("/api/decrypt-memo", post(decrypt_memo_handler))
         .route("/api/scan-transactions", post(scan_transactions_handler))
+        .route(
+            "/api/scan-transactions/stream",
+            post(scan_transactions_stream_handler),
+        )
+        .route("/api/scan-jobs", post(create_scan_job_handler))
+        .route(
+            "/api/scan-jobs/:id",
+            get(scan_job_handler).delete(cancel_scan_job_handler),
+        )
+        .route("/api/scan-jobs/:id/results", get(scan_job_results_handler))
+        .route("/api/scan-jobs/:id/resume", post(resume_scan_job_handler))
+        .route("/api/derive-addresses", post(derive_addresses_handler))
+        .route("/api/lightwalletd-servers", get(lightwalletd_servers_handler))
+        .route("/api/block-cache", get(block_cache_handler))
         .layer(cors)
-        .with_state(state);
+        .with_state(state)
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {:#}", e);
+            std::process::exit(2);
+        }
+    };
+    if cli.print_config {
+        print!("{}", config.to_redacted_toml().expect("Config serializes"));
+        return;
+    }
+
+    // Initialize tracing
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| "zingo_service=info,tower_http=debug".into());
+    match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(filter).init(),
+        LogFormat::Json => tracing_subscriber::fmt().json().with_env_filter(filter).init(),
+    }
+
+    // Already validated by Config::load
+    let mut options = config.backend_options().expect("Validated backend options");
+    options.cache = config.block_cache().expect("Failed to open block cache");
+    let backends = Backends::new(config.backend_config().expect("Validated backend"), options)
+        .expect("Invalid backend configuration");
+    info!("Chain data backend: {}", backends.describe());
+    if let Some(cache) = backends.block_cache() {
+        info!("Block cache holds {} blocks", cache.stats().blocks);
+    }
+    info!(
+        "Approved lightwalletd servers: {}",
+        backends.upstreams().servers().join(", ")
+    );
+
+    let cors = config.allowed_origins().expect("Validated CORS origins");
+    let jobs = JobRegistry::new(config.job_options()).expect("Failed to load scan jobs");
+    let state = Arc::new(AppState {
+        backends,
+        cors,
+        jobs: Arc::new(jobs),
+        min_confirmations: config.balance.min_confirmations,
+    });
+    let app = app(state);
 
     // Start server
-    let port = std::env::var("PORT").unwrap_or_else(|_| "3001".to_string());
-    let addr = format!("0.0.0.0:{}", port);
-    
+    let addr = config.bind_addr().expect("Validated bind address");
+
     info!("Starting Zingo decryption service on {}", addr);
-    
-    let listener = tokio::net::TcpListener::bind(&addr)
+
+    let listener = tokio::net::TcpListener::bind(addr)
         .await
         .expect("Failed to bind to address");
 
@@ -0,0 +1,219 @@
+//! In-process zebrad/zcashd JSON-RPC endpoint serving a few raw blocks and
+//! transactions, with HTTP basic auth like a node's RPC cookie
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use base64::Engine;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::backend::{display_hex, TransparentOutput};
+
+/// Header fields before the Equihash solution
+const HEADER_PREFIX_SIZE: usize = 140;
+const SOLUTION_SIZE: usize = 1344;
+
+#[derive(Default)]
+pub struct MockNode {
+    chain: String,
+    /// Expected `user:password`
+    credentials: String,
+    /// Serialized blocks by height
+    blocks: BTreeMap<u64, Vec<u8>>,
+    /// Raw transactions and heights by display-order txid
+    transactions: HashMap<String, (Vec<u8>, u64)>,
+    /// Answer `z_gettreestate` with a `skipHash` instead of the trees
+    skip_trees: bool,
+    /// Unspent transparent outputs at the tip
+    utxos: Vec<TransparentOutput>,
+    /// Refuse `getaddressutxos`, like a zcashd without an address index
+    no_address_index: bool,
+}
+
+impl MockNode {
+    pub fn new(chain: &str, credentials: &str) -> Self {
+        MockNode {
+            chain: chain.to_string(),
+            credentials: credentials.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Mine `transactions` into a block on top of the current tip
+    pub fn push_block(&mut self, height: u64, time: u32, transactions: &[(&str, Vec<u8>)]) {
+        let prev_hash = self
+            .blocks
+            .values()
+            .next_back()
+            .map(|block| block_hash(block))
+            .unwrap_or([0; 32]);
+
+        let mut block = Vec::new();
+        block.extend(4u32.to_le_bytes());
+        block.extend(prev_hash);
+        block.extend([0u8; 64]);
+        block.extend(time.to_le_bytes());
+        block.extend([0u8; 36]);
+        block.extend([0xfd, 0x40, 0x05]);
+        block.extend([0u8; SOLUTION_SIZE]);
+        block.push(transactions.len() as u8);
+        for (txid, tx) in transactions {
+            block.extend(tx);
+            self.transactions
+                .insert(txid.to_string(), (tx.clone(), height));
+        }
+
+        self.blocks.insert(height, block);
+    }
+
+    /// Leave the note commitment trees out of `z_gettreestate`, as a node
+    /// does when it can't serve them
+    pub fn skip_trees(&mut self) {
+        self.skip_trees = true;
+    }
+
+    /// Refuse `getaddressutxos`, as zcashd does unless started with
+    /// `-insightexplorer` or `-lightwalletd`
+    pub fn without_address_index(&mut self) {
+        self.no_address_index = true;
+    }
+
+    /// Report `utxo` as unspent from `getaddressutxos`
+    pub fn push_utxo(&mut self, utxo: TransparentOutput) {
+        self.utxos.push(utxo);
+    }
+
+    /// Serve on an ephemeral localhost port, returning the RPC URL
+    pub async fn spawn(self) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock node");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let app = Router::new()
+            .route("/", post(handle))
+            .with_state(Arc::new(self));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        url
+    }
+
+    fn dispatch(&self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "getblockchaininfo" => {
+                let (height, block) = self.blocks.iter().next_back().unwrap();
+                Ok(json!({
+                    "chain": self.chain,
+                    "blocks": height,
+                    "bestblockhash": display_hex(&block_hash(block)),
+                }))
+            }
+            "getrawtransaction" => {
+                let txid = params[0].as_str().unwrap_or_default();
+                let (tx, height) = self
+                    .transactions
+                    .get(txid)
+                    .ok_or((-5, "No such mempool or blockchain transaction".to_string()))?;
+                Ok(json!({ "hex": hex::encode(tx), "height": height }))
+            }
+            "getblock" => {
+                let height: u64 = params[0].as_str().unwrap_or_default().parse().unwrap();
+                let block = self
+                    .blocks
+                    .get(&height)
+                    .ok_or((-8, "Block height out of range".to_string()))?;
+                Ok(json!(hex::encode(block)))
+            }
+            "z_gettreestate" => {
+                let height: u64 = params[0].as_str().unwrap_or_default().parse().unwrap();
+                let block = self
+                    .blocks
+                    .get(&height)
+                    .ok_or((-8, "Block height out of range".to_string()))?;
+                // Nothing is ever mined into the mock's trees
+                let tree = if self.skip_trees {
+                    json!({ "skipHash": display_hex(&[0; 32]) })
+                } else {
+                    json!({ "commitments": { "finalState": "000000" } })
+                };
+                Ok(json!({
+                    "height": height,
+                    "hash": display_hex(&block_hash(block)),
+                    "time": u32::from_le_bytes(block[100..104].try_into().unwrap()),
+                    "sapling": tree,
+                    "orchard": tree,
+                }))
+            }
+            "getaddressutxos" if self.no_address_index => {
+                Err((-1, "getaddressutxos is disabled".to_string()))
+            }
+            "getaddressutxos" => {
+                let addresses = params[0]["addresses"]
+                    .as_array()
+                    .cloned()
+                    .unwrap_or_default();
+                let utxos: Vec<Value> = self
+                    .utxos
+                    .iter()
+                    .filter(|utxo| addresses.contains(&json!(utxo.address)))
+                    .map(|utxo| {
+                        json!({
+                            "address": utxo.address,
+                            "txid": utxo.txid,
+                            "outputIndex": utxo.index,
+                            "script": "",
+                            "satoshis": utxo.value,
+                            "height": utxo.height,
+                        })
+                    })
+                    .collect();
+                Ok(json!(utxos))
+            }
+            _ => Err((-32601, "Method not found".to_string())),
+        }
+    }
+}
+
+async fn handle(
+    State(node): State<Arc<MockNode>>,
+    headers: HeaderMap,
+    Json(request): Json<Value>,
+) -> (StatusCode, Json<Value>) {
+    let expected = format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(&node.credentials)
+    );
+    if headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        != Some(&expected)
+    {
+        return (StatusCode::UNAUTHORIZED, Json(Value::Null));
+    }
+
+    let method = request["method"].as_str().unwrap_or_default();
+    match node.dispatch(method, &request["params"]) {
+        Ok(result) => (
+            StatusCode::OK,
+            Json(json!({ "result": result, "error": null, "id": request["id"] })),
+        ),
+        Err((code, message)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "result": null,
+                "error": { "code": code, "message": message },
+                "id": request["id"],
+            })),
+        ),
+    }
+}
+
+fn block_hash(block: &[u8]) -> [u8; 32] {
+    let header = &block[..HEADER_PREFIX_SIZE + 3 + SOLUTION_SIZE];
+    Sha256::digest(Sha256::digest(header)).into()
+}
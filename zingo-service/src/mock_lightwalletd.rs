@@ -0,0 +1,221 @@
+//! In-process lightwalletd serving a fixture chain, so the handlers can be
+//! tested without a network
+
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+
+use crate::backend::fixtures::FixtureChain;
+use crate::backend::lightwalletd::proto::compact_tx_streamer_server::{
+    CompactTxStreamer, CompactTxStreamerServer,
+};
+use crate::backend::lightwalletd::proto::{
+    BlockId, BlockRange, ChainSpec, CompactBlock, Empty, GetAddressUtxosArg, GetAddressUtxosReply,
+    GetAddressUtxosReplyList, LightdInfo, RawTransaction, TreeState, TxFilter,
+};
+use crate::backend::{display_hex, txid_to_internal};
+
+/// lightwalletd's height for transactions not in the main chain
+const UNMINED_HEIGHT: u64 = u64::MAX;
+
+/// Load `tests/fixtures/<name>`
+pub fn fixture(name: &str) -> FixtureChain {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    FixtureChain::load(&path).unwrap()
+}
+
+pub struct MockLightwalletd {
+    chain: FixtureChain,
+    reorg: Option<Reorg>,
+    /// Block range requests wait for a permit from here, if set
+    gate: Option<Arc<Semaphore>>,
+}
+
+/// A branch the mock switches to partway through a test
+struct Reorg {
+    /// Block range requests answered from the original chain first
+    after_ranges: usize,
+    chain: FixtureChain,
+    ranges_served: AtomicUsize,
+}
+
+impl MockLightwalletd {
+    pub fn new(chain: FixtureChain) -> Self {
+        MockLightwalletd {
+            chain,
+            reorg: None,
+            gate: None,
+        }
+    }
+
+    /// Hold every block range request until `gate` has a permit for it,
+    /// keeping scans running for as long as the test needs
+    pub fn gate_block_ranges(mut self, gate: Arc<Semaphore>) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    /// Serve `chain` instead once `after_ranges` block ranges have been
+    /// served, as if the chain reorganized under the client
+    pub fn reorg_after(mut self, after_ranges: usize, chain: FixtureChain) -> Self {
+        self.reorg = Some(Reorg {
+            after_ranges,
+            chain,
+            ranges_served: AtomicUsize::new(0),
+        });
+        self
+    }
+
+    fn chain(&self) -> &FixtureChain {
+        match &self.reorg {
+            Some(reorg) if reorg.ranges_served.load(Ordering::SeqCst) >= reorg.after_ranges => {
+                &reorg.chain
+            }
+            _ => &self.chain,
+        }
+    }
+
+    /// Serve on an ephemeral localhost port for the rest of the test,
+    /// returning the URL to hand to the service
+    pub async fn spawn(self) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock lightwalletd");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(CompactTxStreamerServer::new(self))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        url
+    }
+}
+
+#[tonic::async_trait]
+impl CompactTxStreamer for MockLightwalletd {
+    async fn get_latest_block(
+        &self,
+        _request: Request<ChainSpec>,
+    ) -> Result<Response<BlockId>, Status> {
+        let tip = self
+            .chain()
+            .tip()
+            .ok_or_else(|| Status::unavailable("mock chain has no blocks"))?;
+        Ok(Response::new(BlockId {
+            height: tip.height,
+            hash: tip.hash.clone(),
+        }))
+    }
+
+    async fn get_block(&self, request: Request<BlockId>) -> Result<Response<CompactBlock>, Status> {
+        let height = request.into_inner().height;
+        self.chain()
+            .blocks(height, height)
+            .pop()
+            .map(Response::new)
+            .ok_or_else(|| Status::not_found(format!("no block at height {}", height)))
+    }
+
+    type GetBlockRangeStream = Pin<Box<dyn Stream<Item = Result<CompactBlock, Status>> + Send>>;
+
+    async fn get_block_range(
+        &self,
+        request: Request<BlockRange>,
+    ) -> Result<Response<Self::GetBlockRangeStream>, Status> {
+        if let Some(gate) = &self.gate {
+            gate.acquire().await.unwrap().forget();
+        }
+        let range = request.into_inner();
+        let start = range.start.map_or(0, |id| id.height);
+        let end = range.end.map_or(0, |id| id.height);
+
+        if end > self.chain().tip().map_or(0, |tip| tip.height) {
+            return Err(Status::out_of_range(format!(
+                "block range end {} is beyond the tip",
+                end
+            )));
+        }
+
+        let blocks = self.chain().blocks(start, end);
+        if let Some(reorg) = &self.reorg {
+            reorg.ranges_served.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(Response::new(Box::pin(tokio_stream::iter(
+            blocks.into_iter().map(Ok),
+        ))))
+    }
+
+    async fn get_transaction(
+        &self,
+        request: Request<TxFilter>,
+    ) -> Result<Response<RawTransaction>, Status> {
+        let txid = display_hex(&request.into_inner().hash);
+        let (data, height) = self
+            .chain()
+            .transaction(&txid)
+            .ok_or_else(|| Status::not_found(format!("transaction {} not found", txid)))?;
+
+        Ok(Response::new(RawTransaction {
+            data: data.to_vec(),
+            height: if height == 0 { UNMINED_HEIGHT } else { height },
+        }))
+    }
+
+    async fn get_tree_state(
+        &self,
+        request: Request<BlockId>,
+    ) -> Result<Response<TreeState>, Status> {
+        let height = request.into_inner().height;
+        self.chain()
+            .tree_state_at(height)
+            .cloned()
+            .map(Response::new)
+            .ok_or_else(|| Status::not_found(format!("no tree state at height {}", height)))
+    }
+
+    async fn get_address_utxos(
+        &self,
+        request: Request<GetAddressUtxosArg>,
+    ) -> Result<Response<GetAddressUtxosReplyList>, Status> {
+        let addresses = request.into_inner().addresses;
+        let address_utxos = self
+            .chain()
+            .utxos(&addresses)
+            .into_iter()
+            .map(|utxo| GetAddressUtxosReply {
+                txid: txid_to_internal(&utxo.txid).unwrap_or_default(),
+                index: utxo.index as i32,
+                value_zat: utxo.value as i64,
+                height: utxo.height,
+                address: utxo.address,
+                ..Default::default()
+            })
+            .collect();
+        Ok(Response::new(GetAddressUtxosReplyList { address_utxos }))
+    }
+
+    async fn get_lightd_info(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<LightdInfo>, Status> {
+        let height = self.chain().tip().map_or(0, |tip| tip.height);
+        Ok(Response::new(LightdInfo {
+            version: "mock".to_string(),
+            vendor: "zingo-service tests".to_string(),
+            chain_name: self.chain().chain_name.clone(),
+            block_height: height,
+            estimated_height: height,
+            ..Default::default()
+        }))
+    }
+}
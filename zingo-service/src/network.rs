@@ -0,0 +1,31 @@
+use zcash_wasm::network::{network_name, parse_network, Network};
+
+use crate::error::{Result, ServiceError};
+
+/// Pick the network for a request
+///
+/// The viewing key's prefix decides; an explicit `network` field is only a
+/// cross-check and must agree with the key.
+pub fn resolve_network(key_network: Network, requested: Option<&str>) -> Result<Network> {
+    if let Some(name) = requested {
+        let requested = parse_network(name).map_err(ServiceError::InvalidRequest)?;
+        if requested != key_network {
+            return Err(ServiceError::NetworkMismatch(format!(
+                "Requested network {} but the viewing key is for {}",
+                network_name(requested),
+                network_name(key_network)
+            )));
+        }
+    }
+
+    Ok(key_network)
+}
+
+/// Public lightwalletd server for each network; regtest expects a local one
+pub fn default_lightwalletd_url(network: Network) -> &'static str {
+    match network {
+        Network::Main => "https://zec.rocks:443",
+        Network::Test => "https://testnet.zec.rocks:443",
+        Network::Regtest => "http://127.0.0.1:9067",
+    }
+}
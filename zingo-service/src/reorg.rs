@@ -0,0 +1,426 @@
+//! Keeping a scan on one chain while the upstream chain reorganizes
+//!
+//! A scan remembers the hash of every block it has scanned. Each new batch
+//! must continue from the last of them: a first block whose parent hash is
+//! not the hash scanned at the height below means upstream has switched to
+//! another branch. The scan then finds the highest height where upstream
+//! still agrees with what was scanned (the fork point), drops the
+//! transactions and spends found above it and scans forward again from
+//! there.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::backend::{display_hex, CompactBlock};
+use crate::error::{Result, ServiceError};
+use crate::scan::NoteSpend;
+use crate::Transaction;
+
+/// How far back a fork point is searched for; zcashd refuses reorgs deeper
+/// than this
+pub const MAX_REORG_DEPTH: u64 = 100;
+
+/// Reorgs a single scan follows before giving up on an unsettled chain
+pub const MAX_REORGS: usize = 10;
+
+/// The blocks, transactions and spends a scan has seen so far
+pub struct ScannedChain {
+    start: u64,
+    hashes: BTreeMap<u64, Vec<u8>>,
+    /// Sapling note commitment tree size below the scan, if known
+    sapling_tree_size: Option<u64>,
+    /// Sapling tree size as of the end of each scanned block, when the size
+    /// below the scan is known
+    sapling_tree_sizes: BTreeMap<u64, u64>,
+    /// Found transactions, in height order
+    transactions: Vec<Transaction>,
+    /// Spends of found or watched notes, in height order
+    spends: Vec<NoteSpend>,
+    /// Nullifiers of notes found before the scan, e.g. by an earlier part
+    /// of a resumed job
+    watched: HashSet<String>,
+    /// `watched` plus the nullifiers of the notes in `transactions`
+    nullifiers: HashSet<String>,
+}
+
+/// Upstream no longer follows the branch scanned so far
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diverged {
+    /// Height of the block that doesn't fit
+    pub height: u64,
+}
+
+impl ScannedChain {
+    /// Scan from `start`, above a Sapling tree of `sapling_tree_size` notes
+    pub fn new(start: u64, sapling_tree_size: Option<u64>) -> Self {
+        ScannedChain {
+            start,
+            hashes: BTreeMap::new(),
+            sapling_tree_size,
+            sapling_tree_sizes: BTreeMap::new(),
+            transactions: Vec::new(),
+            spends: Vec::new(),
+            watched: HashSet::new(),
+            nullifiers: HashSet::new(),
+        }
+    }
+
+    /// Also record spends of notes with these nullifiers
+    pub fn watch(&mut self, nullifiers: impl IntoIterator<Item = String>) {
+        for nullifier in nullifiers {
+            self.watched.insert(nullifier.clone());
+            self.nullifiers.insert(nullifier);
+        }
+    }
+
+    /// Hash scanned at `height`
+    pub fn hash(&self, height: u64) -> Option<&[u8]> {
+        self.hashes.get(&height).map(Vec::as_slice)
+    }
+
+    /// Record a batch of blocks, which must be the next heights in order
+    ///
+    /// Returns where upstream diverged, recording nothing, if the first
+    /// block doesn't build on the last one scanned. Fails with
+    /// `UpstreamRejected` if the batch is empty or isn't a chain itself.
+    pub fn extend(&mut self, blocks: &[CompactBlock]) -> Result<Option<Diverged>> {
+        let Some(first) = blocks.first() else {
+            return Err(ServiceError::UpstreamRejected(
+                "Upstream returned an empty batch of blocks".to_string(),
+            ));
+        };
+        let expected = self
+            .hashes
+            .last_key_value()
+            .map_or(self.start, |(height, _)| height + 1);
+        if first.height != expected {
+            return Err(ServiceError::UpstreamRejected(format!(
+                "Expected block {} but got {}",
+                expected, first.height
+            )));
+        }
+        for pair in blocks.windows(2) {
+            if pair[1].height != pair[0].height + 1 || pair[1].prev_hash != pair[0].hash {
+                return Err(ServiceError::UpstreamRejected(format!(
+                    "Block {} doesn't build on block {}",
+                    pair[1].height, pair[0].height
+                )));
+            }
+        }
+
+        if let Some(parent) = first.height.checked_sub(1).and_then(|h| self.hash(h)) {
+            if parent != first.prev_hash {
+                return Ok(Some(Diverged {
+                    height: first.height,
+                }));
+            }
+        }
+
+        for block in blocks {
+            if let Some(position) = self.sapling_position(block.height) {
+                let outputs: usize = block.vtx.iter().map(|tx| tx.outputs.len()).sum();
+                self.sapling_tree_sizes
+                    .insert(block.height, position + outputs as u64);
+            }
+            self.hashes.insert(block.height, block.hash.clone());
+        }
+        Ok(None)
+    }
+
+    /// Position in the Sapling tree of the first output of the block at
+    /// `height`, once the block below it is recorded
+    pub fn sapling_position(&self, height: u64) -> Option<u64> {
+        if height == self.start {
+            return self.sapling_tree_size;
+        }
+        let below = height.checked_sub(1)?;
+        self.sapling_tree_sizes.get(&below).copied()
+    }
+
+    /// Replace the Sapling tree size below the scan, which a reorg reaching
+    /// below the scan's start may have changed
+    pub fn set_sapling_tree_size(&mut self, sapling_tree_size: Option<u64>) {
+        self.sapling_tree_size = sapling_tree_size;
+    }
+
+    pub fn add_transaction(&mut self, transaction: Transaction) {
+        self.nullifiers.extend(nullifiers(&transaction));
+        self.transactions.push(transaction);
+    }
+
+    /// Whether `spend` spends a note found or watched
+    pub fn spends_ours(&self, spend: &NoteSpend) -> bool {
+        self.nullifiers.contains(&spend.nullifier)
+    }
+
+    pub fn add_spend(&mut self, spend: NoteSpend) {
+        self.spends.push(spend);
+    }
+
+    pub fn notes_found(&self) -> usize {
+        self.transactions
+            .iter()
+            .map(|transaction| transaction.notes.len())
+            .sum()
+    }
+
+    /// Lowest height to fetch from upstream when looking for the fork point
+    /// below `height`
+    pub fn fork_search_start(&self, height: u64) -> u64 {
+        self.start.max(height.saturating_sub(MAX_REORG_DEPTH))
+    }
+
+    /// Highest scanned height whose hash `upstream` agrees with, or the
+    /// height below the scan's start if none does
+    ///
+    /// `upstream` holds upstream's blocks from `fork_search_start` up.
+    /// Fails if the fork is deeper than `MAX_REORG_DEPTH` but above the
+    /// scan's start, since it can't be located then.
+    pub fn fork_point(&self, upstream: &[CompactBlock]) -> Result<u64> {
+        let agreed = upstream
+            .iter()
+            .rev()
+            .find(|block| self.hash(block.height) == Some(block.hash.as_slice()));
+        if let Some(block) = agreed {
+            return Ok(block.height);
+        }
+
+        match upstream.first() {
+            Some(lowest) if lowest.height > self.start => Err(ServiceError::Upstream(format!(
+                "The chain reorganized deeper than {} blocks below height {}",
+                MAX_REORG_DEPTH,
+                lowest.height + MAX_REORG_DEPTH
+            ))),
+            _ => Ok(self.start.saturating_sub(1)),
+        }
+    }
+
+    /// Forget everything above `fork`, returning the transactions found in
+    /// the orphaned blocks
+    pub fn rollback(&mut self, fork: u64) -> Vec<Transaction> {
+        self.hashes.split_off(&(fork + 1));
+        self.sapling_tree_sizes.split_off(&(fork + 1));
+        let keep = self.spends.partition_point(|spend| spend.height <= fork);
+        self.spends.truncate(keep);
+        let keep = self
+            .transactions
+            .partition_point(|transaction| transaction.height <= fork);
+        let orphaned = self.transactions.split_off(keep);
+
+        self.nullifiers = self.watched.clone();
+        self.nullifiers
+            .extend(self.transactions.iter().flat_map(nullifiers));
+        orphaned
+    }
+
+    /// The transactions found and the spends of their notes
+    pub fn into_results(self) -> (Vec<Transaction>, Vec<NoteSpend>) {
+        (self.transactions, self.spends)
+    }
+}
+
+/// Nullifiers of the notes a transaction received, where known
+fn nullifiers(transaction: &Transaction) -> impl Iterator<Item = String> + '_ {
+    transaction
+        .notes
+        .iter()
+        .filter_map(|note| note.nullifier.clone())
+}
+
+/// Fail with `UpstreamRejected` unless `blocks` are exactly the heights
+/// `start..=end` in order, so a short answer can't pass for a scanned range
+pub fn check_range(blocks: &[CompactBlock], start: u64, end: u64) -> Result<()> {
+    if blocks.iter().map(|block| block.height).eq(start..=end) {
+        return Ok(());
+    }
+    let returned = match (blocks.first(), blocks.last()) {
+        (Some(first), Some(last)) => format!(
+            "{} blocks from {} to {}",
+            blocks.len(),
+            first.height,
+            last.height
+        ),
+        _ => "no blocks".to_string(),
+    };
+    Err(ServiceError::UpstreamRejected(format!(
+        "Asked for blocks {} to {} but got {}",
+        start, end, returned
+    )))
+}
+
+/// Display-order hash of a block, as `Transaction::block_hash` carries it
+pub fn block_hash(block: &CompactBlock) -> String {
+    display_hex(&block.hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_lightwalletd::fixture;
+    use zcash_wasm::compact::CompactNote;
+
+    /// A transaction with `notes` Orchard notes, whose nullifiers name the
+    /// height and index
+    fn transaction(height: u64, notes: usize) -> Transaction {
+        Transaction {
+            txid: format!("{:064x}", height),
+            height,
+            block_hash: String::new(),
+            amount: 1000 * notes as i64,
+            change: 0,
+            memo: None,
+            timestamp: None,
+            notes: (0..notes)
+                .map(|index| CompactNote {
+                    pool: "orchard".to_string(),
+                    index,
+                    scope: "external".to_string(),
+                    value: 1000,
+                    nullifier: Some(format!("{}-{}", height, index)),
+                })
+                .collect(),
+        }
+    }
+
+    fn spend(height: u64, nullifier: &str) -> NoteSpend {
+        NoteSpend {
+            txid: format!("{:064x}", height),
+            height,
+            nullifier: nullifier.to_string(),
+        }
+    }
+
+    #[test]
+    fn batches_must_continue_the_scanned_chain() {
+        let main = fixture("mainnet.json");
+        let mut fork = fixture("mainnet.json");
+        fork.fork_at(2700002);
+
+        let mut chain = ScannedChain::new(2700000, None);
+        assert_eq!(chain.extend(&main.blocks(2700000, 2700001)).unwrap(), None);
+        assert_eq!(chain.extend(&fork.blocks(2700002, 2700003)).unwrap(), None);
+
+        let mut chain = ScannedChain::new(2700000, None);
+        chain.extend(&fork.blocks(2700000, 2700002)).unwrap();
+        assert_eq!(
+            chain.extend(&main.blocks(2700003, 2700003)).unwrap(),
+            Some(Diverged { height: 2700003 })
+        );
+        // Nothing from the other branch was recorded
+        assert_eq!(chain.hash(2700003), None);
+
+        // Gaps and batches that aren't a chain are upstream faults
+        assert!(chain.extend(&main.blocks(2700001, 2700001)).is_err());
+        let mut mixed = main.blocks(2700000, 2700003);
+        mixed[2] = fork.blocks(2700002, 2700002).remove(0);
+        assert!(ScannedChain::new(2700000, None).extend(&mixed).is_err());
+    }
+
+    #[test]
+    fn short_answers_are_not_taken_as_the_requested_range() {
+        let main = fixture("mainnet.json");
+
+        assert!(check_range(&main.blocks(2700000, 2700003), 2700000, 2700003).is_ok());
+        // A truncated batch is a chain, but not the one asked for
+        let truncated = main.blocks(2700000, 2700002);
+        let err = check_range(&truncated, 2700000, 2700003).unwrap_err();
+        assert!(matches!(err, ServiceError::UpstreamRejected(_)));
+        assert!(err.to_string().contains("3 blocks from 2700000 to 2700002"));
+        assert!(check_range(&main.blocks(2700001, 2700003), 2700000, 2700003).is_err());
+        assert!(check_range(&[], 2700003, 2700003).is_err());
+
+        let mut chain = ScannedChain::new(2700000, None);
+        assert!(chain.extend(&[]).is_err());
+        assert_eq!(chain.hash(2700000), None);
+    }
+
+    #[test]
+    fn rollback_drops_transactions_above_the_fork_point() {
+        let main = fixture("mainnet.json");
+        let mut fork = fixture("mainnet.json");
+        fork.fork_at(2700002);
+
+        let mut chain = ScannedChain::new(2700000, None);
+        chain.extend(&main.blocks(2700000, 2700003)).unwrap();
+        chain.add_transaction(transaction(2700001, 1));
+        chain.add_transaction(transaction(2700002, 2));
+        chain.add_transaction(transaction(2700003, 1));
+        assert_eq!(chain.notes_found(), 4);
+
+        let search = chain.fork_search_start(2700003);
+        let fork_point = chain.fork_point(&fork.blocks(search, 2700003)).unwrap();
+        assert_eq!(fork_point, 2700001);
+
+        let orphaned = chain.rollback(fork_point);
+        let heights: Vec<u64> = orphaned.iter().map(|tx| tx.height).collect();
+        assert_eq!(heights, [2700002, 2700003]);
+        assert_eq!(chain.notes_found(), 1);
+        assert_eq!(chain.hash(2700002), None);
+
+        // The new branch continues from the fork point
+        chain.extend(&fork.blocks(2700002, 2700003)).unwrap();
+        let (kept, _) = chain.into_results();
+        let kept: Vec<u64> = kept.iter().map(|tx| tx.height).collect();
+        assert_eq!(kept, [2700001]);
+    }
+
+    #[test]
+    fn fork_below_the_scan_rescans_everything() {
+        let main = fixture("mainnet.json");
+        let mut fork = fixture("mainnet.json");
+        fork.fork_at(2700000);
+
+        let mut chain = ScannedChain::new(2700001, None);
+        chain.extend(&main.blocks(2700001, 2700003)).unwrap();
+        chain.add_transaction(transaction(2700001, 1));
+
+        let fork_point = chain.fork_point(&fork.blocks(2700001, 2700003)).unwrap();
+        assert_eq!(fork_point, 2700000);
+        assert_eq!(chain.rollback(fork_point).len(), 1);
+    }
+
+    #[test]
+    fn spends_of_orphaned_notes_are_forgotten() {
+        let main = fixture("mainnet.json");
+
+        let mut chain = ScannedChain::new(2700000, None);
+        chain.watch(["earlier".to_string()]);
+        chain.extend(&main.blocks(2700000, 2700003)).unwrap();
+        chain.add_transaction(transaction(2700001, 1));
+        chain.add_transaction(transaction(2700002, 1));
+
+        assert!(chain.spends_ours(&spend(2700002, "earlier")));
+        assert!(chain.spends_ours(&spend(2700002, "2700002-0")));
+        assert!(!chain.spends_ours(&spend(2700002, "2700002-1")));
+        chain.add_spend(spend(2700002, "2700001-0"));
+        chain.add_spend(spend(2700003, "earlier"));
+
+        chain.rollback(2700001);
+        assert!(chain.spends_ours(&spend(2700002, "earlier")));
+        assert!(chain.spends_ours(&spend(2700002, "2700001-0")));
+        assert!(!chain.spends_ours(&spend(2700002, "2700002-0")));
+        let (_, spends) = chain.into_results();
+        assert!(spends.is_empty());
+    }
+
+    #[test]
+    fn sapling_positions_count_every_output() {
+        let main = fixture("mainnet.json");
+
+        // The fixture has one Sapling output at 2700001 and two at 2700002
+        let mut chain = ScannedChain::new(2700000, Some(500));
+        chain.extend(&main.blocks(2700000, 2700001)).unwrap();
+        assert_eq!(chain.sapling_position(2700000), Some(500));
+        assert_eq!(chain.sapling_position(2700002), Some(501));
+        chain.extend(&main.blocks(2700002, 2700003)).unwrap();
+        assert_eq!(chain.sapling_position(2700003), Some(503));
+
+        // Positions above a fork point are counted again on the new branch
+        chain.rollback(2700001);
+        assert_eq!(chain.sapling_position(2700003), None);
+        assert_eq!(chain.sapling_position(2700002), Some(501));
+
+        let mut chain = ScannedChain::new(2700000, None);
+        chain.extend(&main.blocks(2700000, 2700003)).unwrap();
+        assert_eq!(chain.sapling_position(2700002), None);
+    }
+}
@@ -1,23 +1,591 @@
-use anyhow::{anyhow, Result};
-use crate::{Transaction, Balance};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::warn;
+use zcash_wasm::address::transparent_addresses;
+use zcash_wasm::compact::{
+    CompactNote, CompactOrchardAction, CompactSaplingOutput, CompactTransaction, ScanningKeys,
+};
+use zcash_wasm::decrypt::decrypt_transaction;
+use zcash_wasm::keys::{parse_ufvk, UnifiedFullViewingKey};
+use zcash_wasm::network::{Network, NetworkParams};
+use zcash_wasm::txid::verify_txid;
+use zcash_wasm::validate;
+
+use crate::backend::lightwalletd::proto::CompactTx;
+use crate::backend::{
+    commitment_tree_size, display_hex, Backends, ChainBackend, CompactBlock, TransparentOutput,
+};
+use crate::balance::{balance, Balance};
+use crate::error::{Result, ServiceError};
+use crate::network::resolve_network;
+use crate::reorg::{block_hash, check_range, ScannedChain, MAX_REORGS};
+use crate::Transaction;
+
+/// Compact blocks fetched and trial-decrypted at a time
+const BATCH_SIZE: u64 = 100;
+
+/// Longest range one request may scan, about three months of blocks
+pub const MAX_SCAN_BLOCKS: u64 = 100_000;
+
+/// Transparent addresses per chain whose outputs count toward the balance,
+/// BIP 44's gap limit
+pub const TRANSPARENT_ADDRESSES: u32 = 20;
+
+/// A transaction with at least one note for the viewing key
+struct FoundTransaction {
+    txid: String,
+    height: u32,
+    block_hash: String,
+    time: u32,
+    notes: Vec<CompactNote>,
+}
+
+/// Notes found and spends seen in a batch of blocks
+struct ScannedBatch {
+    found: Vec<FoundTransaction>,
+    /// Every nullifier the batch revealed, whoever's note it spent
+    spends: Vec<NoteSpend>,
+}
+
+/// Something that happened during a scan, for callers following along
+pub enum ScanEvent {
+    /// Sent after every batch of blocks
+    Progress(ScanProgress),
+    /// A transaction with notes for the key, as soon as its memo is read
+    Transaction(Transaction),
+    /// A transaction spent one of the notes found
+    Spend(NoteSpend),
+    /// Upstream switched branches; transactions and spends sent for blocks
+    /// above the fork point are void, and the scan continues from there
+    Reorg(ScanReorg),
+}
+
+/// A note of the key's spent by a later transaction
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoteSpend {
+    /// Display-order txid of the spending transaction
+    pub txid: String,
+    pub height: u64,
+    /// Hex nullifier the spend revealed, as the note carries it
+    pub nullifier: String,
+}
+
+/// Everything a finished scan found
+#[derive(Serialize)]
+pub struct ScanResults {
+    pub transactions: Vec<Transaction>,
+    pub spends: Vec<NoteSpend>,
+    /// Value of the notes found that are still unspent
+    pub balance: Balance,
+    /// Unspent outputs of the key's transparent addresses, which jobs keep
+    /// to count their confirmations again later
+    #[serde(skip)]
+    pub transparent_outputs: Option<Vec<TransparentOutput>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ScanReorg {
+    /// Highest height still on the chain
+    pub fork_height: u64,
+    /// Txids of the transactions found in orphaned blocks
+    pub orphaned: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ScanProgress {
+    pub start_height: u64,
+    pub end_height: u64,
+    /// Last height scanned
+    pub current_height: u64,
+    pub blocks_scanned: u64,
+    pub total_blocks: u64,
+    /// Notes for the key in the blocks scanned so far
+    pub notes_found: usize,
+    /// Estimated seconds left, from the rate so far
+    pub eta_secs: Option<u64>,
+}
+
+/// A scan whose request has been checked and whose backend is ready
+///
+/// Preparing validates everything up front, so callers that stream or
+/// queue the scan can still answer a bad request with an error status.
+pub struct Scan {
+    backend: Arc<dyn ChainBackend>,
+    ufvk: UnifiedFullViewingKey,
+    network: Network,
+    keys: Arc<ScanningKeys>,
+    start_height: u64,
+    end_height: u64,
+    /// Chain tip when the scan was prepared
+    chain_tip: u64,
+    min_confirmations: u32,
+    /// Nullifiers of notes found before the scan whose spends to record
+    watched: Vec<String>,
+}
+
+impl Scan {
+    pub async fn prepare(
+        backends: &Backends,
+        ufvk: &str,
+        start_height: u64,
+        end_height: u64,
+        network: Option<&str>,
+        lightwalletd_url: Option<&str>,
+        min_confirmations: u32,
+    ) -> Result<Self> {
+        let ufvk = validate::viewing_key(ufvk)?;
+        validate::height_range(start_height, end_height, MAX_SCAN_BLOCKS)?;
+        let lightwalletd_url = lightwalletd_url.map(validate::url).transpose()?;
+
+        let (key_network, ufvk) = parse_ufvk(ufvk).map_err(ServiceError::InvalidViewingKey)?;
+        let network = resolve_network(key_network, network)?;
+
+        let backend = backends
+            .for_request(network, lightwalletd_url.as_ref())
+            .await?;
+        let tip = backend.chain_tip().await?.height;
+
+        if end_height > tip {
+            return Err(ServiceError::BeyondChainTip(format!(
+                "End height {} is beyond the chain tip at {}",
+                end_height, tip
+            )));
+        }
+
+        let keys = Arc::new(
+            ScanningKeys::new(&ufvk, NetworkParams::new(network))
+                .map_err(ServiceError::Unprocessable)?,
+        );
+
+        Ok(Scan {
+            backend,
+            ufvk,
+            network,
+            keys,
+            start_height,
+            end_height,
+            chain_tip: tip,
+            min_confirmations,
+            watched: Vec::new(),
+        })
+    }
+
+    pub fn chain_tip(&self) -> u64 {
+        self.chain_tip
+    }
+
+    pub fn min_confirmations(&self) -> u32 {
+        self.min_confirmations
+    }
+
+    /// Also report spends of these notes, found before the scan's range
+    pub fn watch(&mut self, nullifiers: impl IntoIterator<Item = String>) {
+        self.watched.extend(nullifiers);
+    }
+
+    /// Scan the range, sending progress, each transaction found and each
+    /// spend of its notes to `events` if given
+    ///
+    /// Compact blocks are fetched from the chain backend in batches and
+    /// every Sapling output and Orchard action is trial-decrypted with the
+    /// UFVK's incoming viewing keys. For each transaction with a note, the
+    /// full transaction is fetched to read its memo. Nullifiers revealed by
+    /// later transactions mark the notes they spend. The scan stops if
+    /// `events` is closed, e.g. because a streaming client went away.
+    pub async fn run(self, events: Option<&mpsc::Sender<ScanEvent>>) -> Result<ScanResults> {
+        let started = Instant::now();
+        let total_blocks = self.end_height - self.start_height + 1;
+        let sapling_tree_size = self.sapling_tree_size().await?;
+        let mut chain = ScannedChain::new(self.start_height, sapling_tree_size);
+        chain.watch(self.watched.iter().cloned());
+        let mut reorgs = 0;
+
+        let mut batch_start = self.start_height;
+        loop {
+            while batch_start <= self.end_height {
+                let batch_end = self.end_height.min(batch_start + BATCH_SIZE - 1);
+                let blocks = self.backend.block_range(batch_start, batch_end).await?;
+                check_range(&blocks, batch_start, batch_end)?;
+                if let Some(diverged) = chain.extend(&blocks)? {
+                    let below = diverged.height - 1;
+                    batch_start = self
+                        .follow_reorg(&mut chain, below, &mut reorgs, events)
+                        .await?
+                        + 1;
+                    continue;
+                }
+
+                let position = chain.sapling_position(batch_start);
+                let batch = scan_batch(self.keys.clone(), blocks, position).await?;
+                for tx in batch.found {
+                    let memo =
+                        fetch_memo(self.backend.as_ref(), &self.ufvk, self.network, &tx).await?;
+                    let (amount, change) = received_value(&tx.notes);
+
+                    let transaction = Transaction {
+                        txid: tx.txid,
+                        height: tx.height as u64,
+                        block_hash: tx.block_hash,
+                        amount: amount as i64,
+                        change: change as i64,
+                        memo,
+                        timestamp: Some(tx.time as i64),
+                        notes: tx.notes,
+                    };
+                    emit(events, || ScanEvent::Transaction(transaction.clone())).await?;
+                    chain.add_transaction(transaction);
+                }
+                for spend in batch.spends {
+                    if chain.spends_ours(&spend) {
+                        emit(events, || ScanEvent::Spend(spend.clone())).await?;
+                        chain.add_spend(spend);
+                    }
+                }
+
+                let blocks_scanned = batch_end - self.start_height + 1;
+                emit(events, || {
+                    ScanEvent::Progress(ScanProgress {
+                        start_height: self.start_height,
+                        end_height: self.end_height,
+                        current_height: batch_end,
+                        blocks_scanned,
+                        total_blocks,
+                        notes_found: chain.notes_found(),
+                        eta_secs: eta(started.elapsed(), blocks_scanned, total_blocks),
+                    })
+                })
+                .await?;
+                batch_start = batch_end + 1;
+            }
+
+            // No later batch will show whether the last one is still on
+            // the chain, so ask
+            let last = self
+                .backend
+                .block_range(self.end_height, self.end_height)
+                .await?;
+            check_range(&last, self.end_height, self.end_height)?;
+            if chain.hash(self.end_height) == Some(last[0].hash.as_slice()) {
+                break;
+            }
+            batch_start = self
+                .follow_reorg(&mut chain, self.end_height, &mut reorgs, events)
+                .await?
+                + 1;
+        }
+
+        // Count confirmations to the tip now, which may have moved on
+        let tip = self.backend.chain_tip().await?.height;
+        let transparent_outputs = self.transparent_outputs().await?;
+        let (transactions, spends) = chain.into_results();
+        let balance = balance(
+            &transactions,
+            &spends,
+            transparent_outputs.as_deref(),
+            tip,
+            self.min_confirmations,
+        );
+        Ok(ScanResults {
+            transactions,
+            spends,
+            balance,
+            transparent_outputs,
+        })
+    }
+
+    /// Unspent outputs of the first [`TRANSPARENT_ADDRESSES`] P2PKH addresses
+    /// on each of the key's transparent chains, as of the chain tip
+    ///
+    /// `None` if the key has no transparent item or the backend can't look
+    /// its addresses up, so the transparent balance is unknown.
+    async fn transparent_outputs(&self) -> Result<Option<Vec<TransparentOutput>>> {
+        let addresses = transparent_addresses(&self.ufvk, self.network, TRANSPARENT_ADDRESSES)
+            .map_err(ServiceError::Unprocessable)?;
+        let Some(addresses) = addresses else {
+            return Ok(None);
+        };
+        match self.backend.address_utxos(&addresses).await {
+            Ok(outputs) => Ok(Some(outputs)),
+            Err(e @ (ServiceError::UpstreamTimeout(_) | ServiceError::UpstreamUnavailable(_))) => {
+                Err(e)
+            }
+            Err(e) => {
+                warn!(
+                    "No transparent outputs for the key, so its transparent balance is unknown: {}",
+                    e
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Size of the Sapling note commitment tree below the scan, which
+    /// places its Sapling notes to derive their nullifiers
+    ///
+    /// Without it the scan still finds Sapling notes, but can't tell when
+    /// they are spent.
+    async fn sapling_tree_size(&self) -> Result<Option<u64>> {
+        let Some(below) = self.start_height.checked_sub(1) else {
+            return Ok(Some(0));
+        };
+        let size = match self.backend.tree_state(below).await {
+            Ok(state) => commitment_tree_size(&state.sapling_tree),
+            Err(e) => Err(e),
+        };
+        match size {
+            Ok(size) => Ok(Some(size)),
+            // The scan would fail on the next request anyway
+            Err(e @ (ServiceError::UpstreamTimeout(_) | ServiceError::UpstreamUnavailable(_))) => {
+                Err(e)
+            }
+            Err(e) => {
+                warn!(
+                    "No Sapling tree size below height {}, so Sapling spends go undetected: {}",
+                    self.start_height, e
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Find where upstream left the scanned chain at or below `below`, roll
+    /// the scan back to there and return the fork point
+    async fn follow_reorg(
+        &self,
+        chain: &mut ScannedChain,
+        below: u64,
+        reorgs: &mut usize,
+        events: Option<&mpsc::Sender<ScanEvent>>,
+    ) -> Result<u64> {
+        *reorgs += 1;
+        if *reorgs > MAX_REORGS {
+            return Err(ServiceError::Upstream(format!(
+                "The chain reorganized more than {} times during the scan",
+                MAX_REORGS
+            )));
+        }
+
+        let search_start = chain.fork_search_start(below);
+        let upstream = self.backend.block_range(search_start, below).await?;
+        check_range(&upstream, search_start, below)?;
+        let fork_height = chain.fork_point(&upstream)?;
+        let orphaned = chain.rollback(fork_height);
+        if fork_height < self.start_height {
+            // The block below the scan was replaced too, and with it the
+            // tree state that places the scan's Sapling notes
+            chain.set_sapling_tree_size(self.sapling_tree_size().await?);
+        }
+        warn!(
+            "Chain reorganized above height {}; rescanning, {} transactions orphaned",
+            fork_height,
+            orphaned.len()
+        );
+
+        emit(events, || {
+            ScanEvent::Reorg(ScanReorg {
+                fork_height,
+                orphaned: orphaned.into_iter().map(|tx| tx.txid).collect(),
+            })
+        })
+        .await?;
+        Ok(fork_height)
+    }
+}
 
 /// Scan blockchain for transactions belonging to a viewing key
-/// NOTE: Zingolib integration temporarily disabled due to build issues
+///
+/// Compact blocks for `start_height..=end_height` are trial-decrypted as
+/// described in [`Scan::run`].
 pub async fn scan_transactions(
+    backends: &Backends,
     ufvk: &str,
     start_height: u64,
     end_height: u64,
-    lightwalletd_url: &str,
-) -> Result<(Vec<Transaction>, Balance)> {
-    // Zingolib integration coming soon
-    Err(anyhow!(
-        "Zingo service HTTP server is running! \n\
-         Wallet scanning temporarily unavailable due to zingolib dependency conflicts. \n\
-         Working to resolve orchard/rand crate version issues. \n\
-         \n\
-         Requested scan: heights {} to {} \n\
-         UFVK: {} \n\
-         Lightwalletd: {}",
-        start_height, end_height, &ufvk[..20], lightwalletd_url
-    ))
+    network: Option<&str>,
+    lightwalletd_url: Option<&str>,
+    min_confirmations: u32,
+) -> Result<ScanResults> {
+    Scan::prepare(
+        backends,
+        ufvk,
+        start_height,
+        end_height,
+        network,
+        lightwalletd_url,
+        min_confirmations,
+    )
+    .await?
+    .run(None)
+    .await
+}
+
+/// Send an event if anyone is listening; fail once they've stopped
+async fn emit(
+    events: Option<&mpsc::Sender<ScanEvent>>,
+    event: impl FnOnce() -> ScanEvent,
+) -> Result<()> {
+    match events {
+        Some(events) => events
+            .send(event())
+            .await
+            .map_err(|_| ServiceError::Internal("Scan abandoned by its listener".to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Seconds left at the rate so far, once there is a rate
+fn eta(elapsed: Duration, blocks_scanned: u64, total_blocks: u64) -> Option<u64> {
+    if blocks_scanned == 0 {
+        return None;
+    }
+    let remaining = total_blocks - blocks_scanned;
+    Some((elapsed.as_secs_f64() * remaining as f64 / blocks_scanned as f64).ceil() as u64)
+}
+
+/// Trial-decrypt a batch of compact blocks off the async runtime and
+/// collect the nullifiers they reveal
+///
+/// `sapling_position` is where the batch's first Sapling output sits in
+/// the note commitment tree, if known.
+async fn scan_batch(
+    keys: Arc<ScanningKeys>,
+    blocks: Vec<CompactBlock>,
+    mut sapling_position: Option<u64>,
+) -> Result<ScannedBatch> {
+    tokio::task::spawn_blocking(move || {
+        let mut found = Vec::new();
+        let mut spends = Vec::new();
+
+        for block in &blocks {
+            let height = u32::try_from(block.height).map_err(|_| {
+                ServiceError::Upstream(format!("Block height {} out of range", block.height))
+            })?;
+
+            for tx in &block.vtx {
+                let compact = compact_transaction(tx, sapling_position);
+                sapling_position = sapling_position.map(|first| first + tx.outputs.len() as u64);
+                let notes = keys.scan_transaction(height, &compact);
+
+                let txid = display_hex(&tx.hash);
+                let nullifiers = tx
+                    .spends
+                    .iter()
+                    .map(|spend| &spend.nf)
+                    .chain(tx.actions.iter().map(|action| &action.nullifier));
+                for nullifier in nullifiers {
+                    spends.push(NoteSpend {
+                        txid: txid.clone(),
+                        height: block.height,
+                        nullifier: hex::encode(nullifier),
+                    });
+                }
+
+                if !notes.is_empty() {
+                    found.push(FoundTransaction {
+                        txid,
+                        height,
+                        block_hash: block_hash(block),
+                        time: block.time,
+                        notes,
+                    });
+                }
+            }
+        }
+
+        Ok(ScannedBatch { found, spends })
+    })
+    .await
+    .map_err(|e| ServiceError::Internal(format!("Scan task failed: {}", e)))?
+}
+
+/// Fetch the full transaction and return the memo of the first received
+/// note that carries one
+async fn fetch_memo(
+    backend: &dyn ChainBackend,
+    ufvk: &UnifiedFullViewingKey,
+    network: Network,
+    found: &FoundTransaction,
+) -> Result<Option<String>> {
+    let tx = backend.raw_transaction(&found.txid).await?;
+    verify_txid(&tx.data, &found.txid).map_err(ServiceError::UpstreamRejected)?;
+
+    let notes = decrypt_transaction(ufvk, network, &tx.data, Some(found.height))
+        .map_err(ServiceError::Unprocessable)?;
+
+    Ok(notes
+        .iter()
+        .filter(|note| note.direction == "incoming" && note.memo.variant != "empty")
+        .map(|note| note.memo.display())
+        .next())
+}
+
+fn compact_transaction(tx: &CompactTx, sapling_tree_position: Option<u64>) -> CompactTransaction {
+    CompactTransaction {
+        sapling_outputs: tx
+            .outputs
+            .iter()
+            .map(|output| CompactSaplingOutput {
+                cmu: to_array(&output.cmu),
+                ephemeral_key: to_array(&output.ephemeral_key),
+                ciphertext: output.ciphertext.clone(),
+            })
+            .collect(),
+        orchard_actions: tx
+            .actions
+            .iter()
+            .map(|action| CompactOrchardAction {
+                nullifier: to_array(&action.nullifier),
+                cmx: to_array(&action.cmx),
+                ephemeral_key: to_array(&action.ephemeral_key),
+                ciphertext: action.ciphertext.clone(),
+            })
+            .collect(),
+        sapling_tree_position,
+    }
+}
+
+/// Value of a transaction's notes received on external addresses, and of
+/// those that came back to internal addresses as change
+fn received_value(notes: &[CompactNote]) -> (u64, u64) {
+    notes.iter().fold((0, 0), |(received, change), note| {
+        match note.scope.as_str() {
+            "internal" => (received, change + note.value),
+            _ => (received + note.value, change),
+        }
+    })
+}
+
+/// Malformed fields become all-zero, which no key decrypts
+fn to_array(bytes: &[u8]) -> [u8; 32] {
+    bytes.try_into().unwrap_or([0; 32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(scope: &str, value: u64) -> CompactNote {
+        CompactNote {
+            pool: "orchard".to_string(),
+            index: 0,
+            scope: scope.to_string(),
+            value,
+            nullifier: None,
+        }
+    }
+
+    #[test]
+    fn change_is_not_counted_as_received() {
+        let notes = [
+            note("external", 3000),
+            note("internal", 500),
+            note("external", 1000),
+        ];
+        assert_eq!(received_value(&notes), (4000, 500));
+
+        // A transaction only returning change to the wallet received nothing
+        assert_eq!(received_value(&[note("internal", 7000)]), (0, 7000));
+    }
 }
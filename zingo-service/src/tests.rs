@@ -0,0 +1,1360 @@
+//! End-to-end handler tests against the in-process mock lightwalletd
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tower::ServiceExt;
+use tower_http::cors::AllowOrigin;
+use zcash_wasm::network::Network;
+use zcash_wasm::validate;
+
+use crate::backend::cache::BlockCache;
+use crate::backend::jsonrpc::{JsonRpcClient, RpcAuth};
+use crate::backend::lightwalletd::LightwalletdClient;
+use crate::backend::{
+    commitment_tree_size, BackendConfig, BackendOptions, Backends, ChainBackend, Timeouts,
+};
+use crate::balance::DEFAULT_MIN_CONFIRMATIONS;
+use crate::error::ServiceError;
+use crate::jobs::{JobOptions, JobRegistry};
+use crate::mock_jsonrpc::MockNode;
+use crate::mock_lightwalletd::{fixture, MockLightwalletd};
+use crate::upstream::UpstreamPolicy;
+use crate::{app, AppState};
+
+/// Mainnet UFVK with Sapling and Orchard components; nothing in
+/// mainnet.json is addressed to it, but payment.json pays it
+const UFVK: &str = "uview19av5rvg3syp6x6vkklu5r7lag67plc388pjr34wwcnrlgkhae9p0v9nczgev90akzavs2k3tmn9mvj24vvu9kl3lafjdqjj9w0dpjl8a39p2kv2hd53z0q9cy0vc29zlhk5k27rxx8057gla7jzp9nplxpta62lnc94wneqtwdjl2kmm4ly0kgh9gw323d49hxtv9a8ylyke8tr22jygxnjzmgps08uyay52slx2fyhplkhl2mpae98gacsse0jfffc4s6k4zu05qqsxkxr4mwcnaquspdqw4vj5m0ae53ctu2ka0qw3ksspwe3ahhu2x26rjchvcv76erc6gmxwyge3qn3y3js6xdtaxtgjcspf8sy6qtvh757p0r63qh5yjxegpgcjgpanf";
+
+/// `UFVK` plus a transparent item; payment.json has unspent outputs for its
+/// external address 1 and internal address 5
+const UFVK_TRANSPARENT: &str = "uview1azp7r5lp0urde55q84x7nuwqmuhhfxz542k7sltdvvhg07qn9ngm9r6a6vugxfg06tfv275wd64dtp6q5el67r2y7jrl4hfmkg6jwv076we8elvzjkq8aaqtlkev4ghwlqwqezdj4mq96gsjvph97systjepk0r2r5236vlq7x2t7qxu3exzsl3yxmaxlpdsxdgqqawkex5jlpr4snpm9ep6yxk8u8lf05zw2595vayrqx9s4546p4y6tlhet373syc6s7yr8lxgqzv2efnxmy67r08zw9wvldn2q52ncrqz9akqfzhj86g9q5s8nsdux6erpsnrj3t3a6spqt6c9muud7khu2fs445v5n84nkvnp39hx65j63z5lqrt3afatscwlu2pd568jhawqaed2gluk7dz2n2j7y7v50u594ajd2tx67rxk7lztgvy3del6l9swt6hya5st25v4rnt7j5j8y7v6a328wzvss9ar8ld708p9s3sepck";
+
+/// Transparent-only v5 transaction in the fixtures, served under its real txid
+const TXID: &str = "9ab4c6ea7811a0ec9bd82919a923c1e34db6cff750c073e7bd242fe4028feaee";
+
+/// Served with the same data as `TXID`, which doesn't hash to it
+const MISMATCHED_TXID: &str = "eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee";
+
+/// Transaction in payment.json, mined at 2700001, paying `UFVK` 150,000
+/// zatoshis on a Sapling note ("Thanks for the coffee") and 2,500,000 on an
+/// Orchard note ("Rent for March"), both at diversifier index 1
+const PAYMENT_TXID: &str = "20a05e9f91acf56e54e7c3a5d09406c2ae617193e714c5f7086de5f57c0cf8ea";
+
+async fn mock_mainnet() -> String {
+    MockLightwalletd::new(fixture("mainnet.json")).spawn().await
+}
+
+async fn mock_payment() -> String {
+    MockLightwalletd::new(fixture("payment.json")).spawn().await
+}
+
+/// Requests may name any server, including the mocks on localhost
+fn state(config: BackendConfig) -> Arc<AppState> {
+    state_with(
+        config,
+        UpstreamPolicy::new(&["*".to_string()], true).unwrap(),
+    )
+}
+
+fn state_with(config: BackendConfig, upstreams: UpstreamPolicy) -> Arc<AppState> {
+    Arc::new(AppState {
+        backends: Backends::new(
+            config,
+            BackendOptions {
+                upstreams,
+                ..Default::default()
+            },
+        )
+        .unwrap(),
+        cors: AllowOrigin::any(),
+        jobs: Arc::new(JobRegistry::new(JobOptions::default()).unwrap()),
+        min_confirmations: DEFAULT_MIN_CONFIRMATIONS,
+    })
+}
+
+/// Status and JSON body of a response
+type Response = (StatusCode, Value);
+
+/// Default configuration; requests name the mock's URL
+async fn post(path: &str, body: Value) -> Response {
+    post_to(state(BackendConfig::Lightwalletd { url: None }), path, body).await
+}
+
+async fn post_to(state: Arc<AppState>, path: &str, body: Value) -> Response {
+    post_raw(state, path, body.to_string()).await
+}
+
+async fn post_raw(state: Arc<AppState>, path: &str, body: String) -> Response {
+    let request = Request::post(path)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap();
+    send(state, request).await
+}
+
+async fn send(state: Arc<AppState>, request: Request<Body>) -> Response {
+    let response = app(state).oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+/// Check the error schema, status and code, and return the message
+fn error_of(response: &Response, status: StatusCode, code: &str) -> String {
+    let (actual, body) = response;
+    assert_eq!(body["success"], false, "unexpected success: {}", body);
+    assert_eq!(*actual, status, "{}", body);
+    assert_eq!(body["code"], code, "{}", body);
+    body["error"].as_str().unwrap().to_string()
+}
+
+fn body_of(response: &Response) -> &Value {
+    let (status, body) = response;
+    assert_eq!(*status, StatusCode::OK, "{}", body);
+    assert_eq!(body["success"], true, "{}", body);
+    body
+}
+
+#[tokio::test]
+async fn decrypt_memo_reports_transactions_not_for_the_key() {
+    let url = mock_mainnet().await;
+    let response = post(
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": TXID, "lightwalletd_url": url }),
+    )
+    .await;
+
+    // The transaction was fetched and passed txid verification, but has no
+    // shielded outputs for the key
+    let error = error_of(&response, StatusCode::UNPROCESSABLE_ENTITY, "not_for_key");
+    assert!(error.starts_with("No outputs"), "{}", error);
+}
+
+#[tokio::test]
+async fn decrypt_memo_reports_the_payment_to_the_key() {
+    let url = mock_payment().await;
+    let response = post(
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": PAYMENT_TXID, "lightwalletd_url": url }),
+    )
+    .await;
+
+    let body = body_of(&response);
+    assert_eq!(body["txid"], PAYMENT_TXID);
+    assert_eq!(body["amount"], 2_650_000);
+    // The first non-empty memo, on the Orchard note
+    assert_eq!(
+        body["memo"],
+        json!({ "variant": "text", "text": "Rent for March", "hex": null })
+    );
+
+    let notes: Vec<_> = body["notes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|note| {
+            (
+                note["pool"].as_str().unwrap(),
+                note["direction"].as_str().unwrap(),
+                note["value"].as_u64().unwrap(),
+                note["recipient"].as_str().unwrap(),
+                note["memo"]["text"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        notes,
+        [
+            (
+                "orchard",
+                "incoming",
+                2_500_000,
+                "u1y5jnsvc3mfrv65umsk29dvtcn3etmy8wpwsgh8jx23q8wkysyfzd20792jmqywyf8mtmz74men8f3h65kvde307ydl7nn0f49gq593w0",
+                "Rent for March",
+            ),
+            (
+                "sapling",
+                "incoming",
+                150_000,
+                "zs1m2f7tsugas2t2rsu58wmguehpv60qwmlq9nawk68p30465jlw45hvumgqjzzt4htsdrgvhhvqd7",
+                "Thanks for the coffee",
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn decrypt_memo_rejects_data_that_does_not_match_txid() {
+    let url = mock_mainnet().await;
+    let response = post(
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": MISMATCHED_TXID, "lightwalletd_url": url }),
+    )
+    .await;
+
+    let error = error_of(&response, StatusCode::BAD_GATEWAY, "upstream_rejected");
+    assert!(error.starts_with("Rejected upstream data"), "{}", error);
+    assert!(error.contains(TXID), "{}", error);
+}
+
+#[tokio::test]
+async fn decrypt_memo_reports_unknown_transaction() {
+    let url = mock_mainnet().await;
+    let txid = "0".repeat(64);
+    let response = post(
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": txid, "lightwalletd_url": url }),
+    )
+    .await;
+
+    let error = error_of(&response, StatusCode::NOT_FOUND, "not_found");
+    assert!(error.contains("GetTransaction"), "{}", error);
+    assert!(error.contains("NotFound"), "{}", error);
+}
+
+#[tokio::test]
+async fn scan_transactions_finds_nothing_for_unrelated_key() {
+    let url = mock_mainnet().await;
+    let response = post(
+        "/api/scan-transactions",
+        json!({
+            "ufvk": UFVK,
+            "start_height": 2700000,
+            "end_height": 2700003,
+            "lightwalletd_url": url,
+        }),
+    )
+    .await;
+
+    let body = body_of(&response);
+    assert_eq!(body["transactions"], json!([]));
+    assert_eq!(body["spends"], json!([]));
+    let empty = json!({ "confirmed": 0, "unconfirmed": 0, "total": 0 });
+    assert_eq!(
+        body["balance"],
+        json!({
+            "confirmed": 0,
+            "unconfirmed": 0,
+            "total": 0,
+            "pools": { "transparent": null, "sapling": empty, "orchard": empty },
+            "chain_tip": 2700003,
+            "min_confirmations": 10,
+        })
+    );
+}
+
+#[tokio::test]
+async fn scan_transactions_finds_payments_to_the_key() {
+    let url = mock_payment().await;
+    let response = post(
+        "/api/scan-transactions",
+        json!({
+            "ufvk": UFVK,
+            "start_height": 2700001,
+            "end_height": 2700002,
+            "lightwalletd_url": url,
+        }),
+    )
+    .await;
+
+    let body = body_of(&response);
+    assert_eq!(body["transactions"].as_array().map(Vec::len), Some(1));
+    let found = &body["transactions"][0];
+    assert_eq!(found["txid"], PAYMENT_TXID);
+    assert_eq!(found["height"], 2700001);
+    assert_eq!(
+        found["block_hash"],
+        "000000000002a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7a7"
+    );
+    assert_eq!(found["amount"], 2_650_000);
+    assert_eq!(found["change"], 0);
+    assert_eq!(found["memo"], "Rent for March");
+    assert_eq!(found["timestamp"], 1729000075);
+    // The Sapling note is the first output above the empty tree at 2700000
+    assert_eq!(
+        found["notes"],
+        json!([{
+            "pool": "sapling",
+            "index": 0,
+            "scope": "external",
+            "value": 150_000,
+            "nullifier": "a93294b8b27d3ae37ae3b1cde413aa2cc525cc53bd36338792133499087da9fd",
+        }, {
+            "pool": "orchard",
+            "index": 0,
+            "scope": "external",
+            "value": 2_500_000,
+            "nullifier": "d4943204321c0d096803510f08f2352b3dca08f2f069b18cb1697afca0b40f32",
+        }])
+    );
+    assert_eq!(body["spends"], json!([]));
+    // One confirmation at the tip of 2700002, short of the default ten
+    assert_eq!(body["balance"]["unconfirmed"], 2_650_000);
+    assert_eq!(body["balance"]["confirmed"], 0);
+}
+
+#[tokio::test]
+async fn scan_transactions_counts_transparent_outputs() {
+    let url = mock_payment().await;
+    let response = post(
+        "/api/scan-transactions",
+        json!({
+            "ufvk": UFVK_TRANSPARENT,
+            "start_height": 2700001,
+            "end_height": 2700002,
+            "lightwalletd_url": url,
+            "min_confirmations": 3,
+        }),
+    )
+    .await;
+
+    // The output at 2700000 has three confirmations at the tip of 2700002;
+    // the one paying somebody else's address isn't asked for
+    let balance = &body_of(&response)["balance"];
+    assert_eq!(
+        balance["pools"]["transparent"],
+        json!({ "confirmed": 400_000, "unconfirmed": 25_000, "total": 425_000 }),
+        "{}",
+        balance
+    );
+    assert_eq!(balance["confirmed"], 400_000);
+    assert_eq!(balance["unconfirmed"], 2_675_000);
+    assert_eq!(balance["total"], 3_075_000);
+}
+
+#[tokio::test]
+async fn scan_transactions_rejects_range_past_tip() {
+    let url = mock_mainnet().await;
+    let response = post(
+        "/api/scan-transactions",
+        json!({
+            "ufvk": UFVK,
+            "start_height": 2700000,
+            "end_height": 2700004,
+            "lightwalletd_url": url,
+        }),
+    )
+    .await;
+
+    let error = error_of(
+        &response,
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "beyond_chain_tip",
+    );
+    assert!(
+        error.contains("beyond the chain tip at 2700003"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn requests_fail_against_server_on_another_chain() {
+    let mut chain = fixture("mainnet.json");
+    chain.chain_name = "test".to_string();
+    let url = MockLightwalletd::new(chain).spawn().await;
+
+    let response = post(
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": TXID, "lightwalletd_url": url }),
+    )
+    .await;
+
+    let error = error_of(
+        &response,
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "chain_mismatch",
+    );
+    assert!(error.contains("serves the test chain"), "{}", error);
+}
+
+#[tokio::test]
+async fn configured_lightwalletd_serves_requests_without_url() {
+    let url = mock_mainnet().await;
+    let state = state(BackendConfig::Lightwalletd { url: Some(url) });
+    let response = post_to(
+        state,
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": MISMATCHED_TXID }),
+    )
+    .await;
+
+    let error = error_of(&response, StatusCode::BAD_GATEWAY, "upstream_rejected");
+    assert!(error.starts_with("Rejected upstream data"), "{}", error);
+}
+
+#[tokio::test]
+async fn fixture_backend_serves_decrypt_and_scan() {
+    let config = BackendConfig::Fixtures {
+        path: concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mainnet.json").into(),
+    };
+
+    let response = post_to(
+        state(config.clone()),
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": MISMATCHED_TXID }),
+    )
+    .await;
+    let error = error_of(&response, StatusCode::BAD_GATEWAY, "upstream_rejected");
+    assert!(error.starts_with("Rejected upstream data"), "{}", error);
+
+    let response = post_to(
+        state(config),
+        "/api/scan-transactions",
+        json!({ "ufvk": UFVK, "start_height": 2700000, "end_height": 2700003 }),
+    )
+    .await;
+    assert_eq!(body_of(&response)["transactions"], json!([]));
+}
+
+/// Events of a Server-Sent Events body as (name, JSON data) pairs
+async fn sse_events(state: Arc<AppState>, path: &str, body: Value) -> Vec<(String, Value)> {
+    let request = Request::post(path)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let response = app(state).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    String::from_utf8(bytes.to_vec())
+        .unwrap()
+        .split("\n\n")
+        .filter_map(|event| {
+            let field = |name: &str| {
+                event
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .map(str::to_string)
+            };
+            Some((
+                field("event: ")?,
+                serde_json::from_str(&field("data: ")?).unwrap(),
+            ))
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn scan_stream_reports_progress_then_result() {
+    let url = mock_mainnet().await;
+    let events = sse_events(
+        state(BackendConfig::Lightwalletd { url: None }),
+        "/api/scan-transactions/stream",
+        json!({
+            "ufvk": UFVK,
+            "start_height": 2700000,
+            "end_height": 2700003,
+            "lightwalletd_url": url,
+        }),
+    )
+    .await;
+
+    let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["progress", "done"]);
+
+    let progress = &events[0].1;
+    assert_eq!(progress["current_height"], 2700003);
+    assert_eq!(progress["blocks_scanned"], 4);
+    assert_eq!(progress["total_blocks"], 4);
+    assert_eq!(progress["notes_found"], 0);
+    assert_eq!(progress["eta_secs"], 0);
+
+    let done = &events[1].1;
+    assert_eq!(done["success"], true);
+    assert_eq!(done["transactions"], json!([]));
+}
+
+#[tokio::test]
+async fn scan_follows_reorg_and_rescans_from_fork_point() {
+    // The chain switches branches at 2700002 once the first range is served
+    let mut fork = fixture("mainnet.json");
+    fork.fork_at(2700002);
+    let url = MockLightwalletd::new(fixture("mainnet.json"))
+        .reorg_after(1, fork)
+        .spawn()
+        .await;
+
+    let events = sse_events(
+        state(BackendConfig::Lightwalletd { url: None }),
+        "/api/scan-transactions/stream",
+        json!({
+            "ufvk": UFVK,
+            "start_height": 2700000,
+            "end_height": 2700003,
+            "lightwalletd_url": url,
+        }),
+    )
+    .await;
+
+    let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["progress", "reorg", "progress", "done"]);
+    assert_eq!(
+        events[1].1,
+        json!({ "fork_height": 2700001, "orphaned": [] })
+    );
+    assert_eq!(events[2].1["current_height"], 2700003);
+    assert_eq!(events[3].1["success"], true);
+}
+
+#[tokio::test]
+async fn reorg_below_the_scan_reloads_the_sapling_tree() {
+    // The new branch replaces 2700000 too, with one more Sapling note in the
+    // tree below the scan
+    let mut fork = fixture("payment.json");
+    fork.fork_at(2700000);
+    fork.set_sapling_tree(2700000, &format!("01{}0000", "11".repeat(32)));
+    let url = MockLightwalletd::new(fixture("payment.json"))
+        .reorg_after(1, fork)
+        .spawn()
+        .await;
+
+    let events = sse_events(
+        state(BackendConfig::Lightwalletd { url: None }),
+        "/api/scan-transactions/stream",
+        json!({
+            "ufvk": UFVK,
+            "start_height": 2700001,
+            "end_height": 2700002,
+            "lightwalletd_url": url,
+        }),
+    )
+    .await;
+
+    let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "transaction",
+            "progress",
+            "reorg",
+            "transaction",
+            "progress",
+            "done"
+        ]
+    );
+    assert_eq!(
+        events[2].1,
+        json!({ "fork_height": 2700000, "orphaned": [PAYMENT_TXID] })
+    );
+    // The Sapling note is now at position 1, so its nullifier changed
+    let sapling = |event: &Value| event["notes"][0]["nullifier"].clone();
+    assert_eq!(
+        sapling(&events[0].1),
+        "a93294b8b27d3ae37ae3b1cde413aa2cc525cc53bd36338792133499087da9fd"
+    );
+    assert_eq!(
+        sapling(&events[3].1),
+        "321a0577f38a8dfeb4ba58cd06c1f4d54bce6242436864cdff044af46cae2089"
+    );
+    assert_eq!(
+        events[5].1["transactions"][0]["notes"][0]["nullifier"],
+        sapling(&events[3].1)
+    );
+}
+
+#[tokio::test]
+async fn scan_stream_rejects_bad_request_before_streaming() {
+    let url = mock_mainnet().await;
+    let response = post(
+        "/api/scan-transactions/stream",
+        json!({
+            "ufvk": UFVK,
+            "start_height": 2700000,
+            "end_height": 2700004,
+            "lightwalletd_url": url,
+        }),
+    )
+    .await;
+
+    error_of(
+        &response,
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "beyond_chain_tip",
+    );
+}
+
+/// A node with the fixtures' transparent transaction mined at 2700001
+async fn mock_node(credentials: &str) -> String {
+    let chain = fixture("mainnet.json");
+    let (tx, _) = chain.transaction(TXID).unwrap();
+
+    let mut node = MockNode::new("main", credentials);
+    node.push_block(2700000, 1729000000, &[]);
+    node.push_block(2700001, 1729000075, &[(TXID, tx.to_vec())]);
+    node.spawn().await
+}
+
+#[tokio::test]
+async fn jsonrpc_backend_serves_decrypt_and_scan() {
+    let config = BackendConfig::JsonRpc {
+        url: mock_node("zingo:secret").await,
+        auth: RpcAuth::UserPassword {
+            user: "zingo".to_string(),
+            password: "secret".to_string(),
+        },
+    };
+
+    let response = post_to(
+        state(config.clone()),
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": TXID }),
+    )
+    .await;
+    let error = error_of(&response, StatusCode::UNPROCESSABLE_ENTITY, "not_for_key");
+    assert!(error.starts_with("No outputs"), "{}", error);
+
+    let response = post_to(
+        state(config),
+        "/api/scan-transactions",
+        json!({ "ufvk": UFVK, "start_height": 2700000, "end_height": 2700001 }),
+    )
+    .await;
+    assert_eq!(body_of(&response)["transactions"], json!([]));
+}
+
+#[tokio::test]
+async fn jsonrpc_backend_looks_up_transparent_outputs() {
+    let chain = fixture("payment.json");
+    let utxos = chain.utxos(&["t1Tt4L1HLiprvzjTugsLc5voG8iLqmN9bGU".to_string()]);
+    let mut node = MockNode::new("main", "zingo:secret");
+    node.push_block(2700000, 1729000000, &[]);
+    node.push_utxo(utxos[0].clone());
+    let auth = RpcAuth::UserPassword {
+        user: "zingo".to_string(),
+        password: "secret".to_string(),
+    };
+
+    let config = BackendConfig::JsonRpc {
+        url: node.spawn().await,
+        auth: auth.clone(),
+    };
+    let request =
+        json!({ "ufvk": UFVK_TRANSPARENT, "start_height": 2700000, "end_height": 2700000 });
+    let response = post_to(state(config), "/api/scan-transactions", request.clone()).await;
+    assert_eq!(
+        body_of(&response)["balance"]["pools"]["transparent"],
+        json!({ "confirmed": 0, "unconfirmed": 400_000, "total": 400_000 })
+    );
+
+    // Without an address index the transparent balance is unknown, and the
+    // scan still succeeds
+    let mut node = MockNode::new("main", "zingo:secret");
+    node.push_block(2700000, 1729000000, &[]);
+    node.without_address_index();
+    let config = BackendConfig::JsonRpc {
+        url: node.spawn().await,
+        auth,
+    };
+    let response = post_to(state(config), "/api/scan-transactions", request).await;
+    let balance = &body_of(&response)["balance"];
+    assert_eq!(balance["pools"]["transparent"], Value::Null);
+    assert_eq!(balance["total"], 0);
+}
+
+#[tokio::test]
+async fn jsonrpc_backend_authenticates_with_cookie_file() {
+    let cookie = std::env::temp_dir().join(format!("zingo-test-{}.cookie", std::process::id()));
+    std::fs::write(&cookie, "__cookie__:0123abcd\n").unwrap();
+
+    let config = BackendConfig::JsonRpc {
+        url: mock_node("__cookie__:0123abcd").await,
+        auth: RpcAuth::Cookie(cookie.clone()),
+    };
+    let response = post_to(
+        state(config),
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": "1".repeat(64) }),
+    )
+    .await;
+    std::fs::remove_file(cookie).unwrap();
+
+    let error = error_of(&response, StatusCode::NOT_FOUND, "not_found");
+    assert!(
+        error.contains("No such mempool or blockchain transaction (code -5)"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn jsonrpc_backend_reports_rejected_credentials() {
+    let config = BackendConfig::JsonRpc {
+        url: mock_node("zingo:secret").await,
+        auth: RpcAuth::UserPassword {
+            user: "zingo".to_string(),
+            password: "wrong".to_string(),
+        },
+    };
+    let response = post_to(
+        state(config),
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": TXID }),
+    )
+    .await;
+
+    let error = error_of(&response, StatusCode::BAD_GATEWAY, "upstream_error");
+    assert!(error.contains("check the credentials"), "{}", error);
+}
+
+#[tokio::test]
+async fn jsonrpc_backend_requires_note_commitment_trees() {
+    let auth = || RpcAuth::UserPassword {
+        user: "zingo".to_string(),
+        password: "secret".to_string(),
+    };
+    let client = JsonRpcClient::new(
+        &mock_node("zingo:secret").await,
+        auth(),
+        Timeouts::default(),
+    )
+    .unwrap();
+    let state = client.tree_state(2700000).await.unwrap();
+    assert_eq!(commitment_tree_size(&state.sapling_tree).unwrap(), 0);
+    assert_eq!(commitment_tree_size(&state.orchard_tree).unwrap(), 0);
+
+    // A node without the trees must not pass for one with empty trees
+    let mut node = MockNode::new("main", "zingo:secret");
+    node.push_block(2700000, 1729000000, &[]);
+    node.skip_trees();
+    let client = JsonRpcClient::new(&node.spawn().await, auth(), Timeouts::default()).unwrap();
+    match client.tree_state(2700000).await {
+        Err(ServiceError::Upstream(message)) => {
+            assert!(
+                message.contains("no sapling note commitment tree"),
+                "{}",
+                message
+            )
+        }
+        other => panic!("expected an upstream error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn malformed_json_is_rejected_with_error_body() {
+    let response = post_raw(
+        state(BackendConfig::Lightwalletd { url: None }),
+        "/api/decrypt-memo",
+        "{\"ufvk\": ".to_string(),
+    )
+    .await;
+
+    let error = error_of(&response, StatusCode::BAD_REQUEST, "malformed_json");
+    assert!(error.starts_with("Malformed JSON"), "{}", error);
+}
+
+#[tokio::test]
+async fn missing_fields_are_rejected_with_error_body() {
+    let response = post("/api/scan-transactions", json!({ "ufvk": UFVK })).await;
+
+    let error = error_of(&response, StatusCode::BAD_REQUEST, "invalid_request");
+    assert!(error.contains("start_height"), "{}", error);
+}
+
+#[tokio::test]
+async fn invalid_input_is_rejected_before_contacting_backend() {
+    let response = post(
+        "/api/scan-transactions",
+        json!({ "ufvk": "not-a-viewing-key", "start_height": 1, "end_height": 2 }),
+    )
+    .await;
+    error_of(&response, StatusCode::BAD_REQUEST, "invalid_viewing_key");
+
+    let response = post(
+        "/api/scan-transactions",
+        json!({ "ufvk": UFVK, "start_height": 2, "end_height": 1 }),
+    )
+    .await;
+    error_of(&response, StatusCode::BAD_REQUEST, "invalid_request");
+
+    let response = post(
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": TXID, "network": "test" }),
+    )
+    .await;
+    error_of(&response, StatusCode::BAD_REQUEST, "network_mismatch");
+}
+
+#[tokio::test]
+async fn short_or_non_ascii_input_is_rejected_without_panicking() {
+    for ufvk in [
+        "",
+        "u",
+        "uview1",
+        "ü",
+        "uview1😀😀😀😀😀😀😀😀😀😀😀😀😀😀😀😀😀😀😀😀",
+    ] {
+        let response = post("/api/decrypt-memo", json!({ "ufvk": ufvk, "txid": TXID })).await;
+        error_of(&response, StatusCode::BAD_REQUEST, "invalid_viewing_key");
+
+        let response = post(
+            "/api/scan-transactions",
+            json!({ "ufvk": ufvk, "start_height": 1, "end_height": 2 }),
+        )
+        .await;
+        error_of(&response, StatusCode::BAD_REQUEST, "invalid_viewing_key");
+    }
+
+    for txid in ["", "abc", "é", &"é".repeat(32)] {
+        let response = post("/api/decrypt-memo", json!({ "ufvk": UFVK, "txid": txid })).await;
+        let error = error_of(&response, StatusCode::BAD_REQUEST, "invalid_request");
+        assert!(error.starts_with("Invalid txid"), "{}", error);
+    }
+
+    for url in [
+        "zec.rocks:443",
+        "ftp://zec.rocks",
+        "https://user@zec.rocks",
+        "https://é",
+    ] {
+        let response = post(
+            "/api/decrypt-memo",
+            json!({ "ufvk": UFVK, "txid": TXID, "lightwalletd_url": url }),
+        )
+        .await;
+        let error = error_of(&response, StatusCode::BAD_REQUEST, "invalid_request");
+        assert!(error.starts_with("Invalid URL"), "{}", error);
+    }
+}
+
+#[tokio::test]
+async fn scan_range_is_limited() {
+    let response = post(
+        "/api/scan-transactions",
+        json!({
+            "ufvk": UFVK,
+            "start_height": 1,
+            "end_height": crate::scan::MAX_SCAN_BLOCKS + 1,
+        }),
+    )
+    .await;
+    let error = error_of(&response, StatusCode::BAD_REQUEST, "invalid_request");
+    assert!(error.contains("more than the limit"), "{}", error);
+
+    let response = post(
+        "/api/scan-transactions",
+        json!({ "ufvk": UFVK, "start_height": 1, "end_height": u64::from(u32::MAX) + 1 }),
+    )
+    .await;
+    let error = error_of(&response, StatusCode::BAD_REQUEST, "invalid_request");
+    assert!(error.starts_with("Invalid height"), "{}", error);
+}
+
+#[tokio::test]
+async fn unreachable_backend_is_reported_as_unavailable() {
+    // Bind and drop a listener for a port nothing serves
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let response = post(
+        "/api/decrypt-memo",
+        json!({
+            "ufvk": UFVK,
+            "txid": TXID,
+            "lightwalletd_url": format!("http://127.0.0.1:{}", port),
+        }),
+    )
+    .await;
+
+    error_of(&response, StatusCode::BAD_GATEWAY, "upstream_unavailable");
+}
+
+#[tokio::test]
+async fn lightwalletd_url_must_be_on_the_allowlist() {
+    let url = mock_mainnet().await;
+    let policy = UpstreamPolicy::new(&UpstreamPolicy::default_allowlist(), true).unwrap();
+    let response = post_to(
+        state_with(BackendConfig::Lightwalletd { url: None }, policy),
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": TXID, "lightwalletd_url": url }),
+    )
+    .await;
+
+    let error = error_of(&response, StatusCode::FORBIDDEN, "upstream_not_allowed");
+    assert!(error.contains("https://zec.rocks:443"), "{}", error);
+}
+
+#[tokio::test]
+async fn internal_servers_are_refused_unless_enabled() {
+    let url = mock_mainnet().await;
+    let port = url.rsplit(':').next().unwrap();
+    let by_name = format!("http://localhost:{}", port);
+    let allowed = [url.clone(), by_name.clone()];
+    let request = |url: &str| json!({ "ufvk": UFVK, "txid": TXID, "lightwalletd_url": url });
+
+    let policy = UpstreamPolicy::new(&allowed, false).unwrap();
+    for url in [&url, &by_name] {
+        let response = post_to(
+            state_with(BackendConfig::Lightwalletd { url: None }, policy.clone()),
+            "/api/decrypt-memo",
+            request(url),
+        )
+        .await;
+        let error = error_of(&response, StatusCode::FORBIDDEN, "upstream_not_allowed");
+        assert!(error.contains("internal address"), "{}", error);
+    }
+
+    // With private servers enabled the request reaches the mock
+    let policy = UpstreamPolicy::new(&allowed, true).unwrap();
+    let response = post_to(
+        state_with(BackendConfig::Lightwalletd { url: None }, policy),
+        "/api/decrypt-memo",
+        request(&url),
+    )
+    .await;
+    error_of(&response, StatusCode::UNPROCESSABLE_ENTITY, "not_for_key");
+}
+
+#[tokio::test]
+async fn lightwalletd_url_cannot_bypass_a_configured_backend() {
+    let url = mock_mainnet().await;
+    let config = BackendConfig::Fixtures {
+        path: concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mainnet.json").into(),
+    };
+    let response = post_to(
+        state(config),
+        "/api/decrypt-memo",
+        json!({ "ufvk": UFVK, "txid": TXID, "lightwalletd_url": url }),
+    )
+    .await;
+
+    let error = error_of(&response, StatusCode::BAD_REQUEST, "invalid_request");
+    assert!(error.contains("may not set lightwalletd_url"), "{}", error);
+}
+
+#[tokio::test]
+async fn approved_servers_are_reached_at_the_checked_addresses() {
+    let url = mock_mainnet().await;
+    let address: SocketAddr = url.trim_start_matches("http://").parse().unwrap();
+
+    // The name doesn't resolve, so the client can only have used the
+    // address it was given
+    let unresolvable = format!("http://lightwalletd.invalid:{}", address.port());
+    let client = LightwalletdClient::connect_to(&unresolvable, vec![address], Timeouts::default())
+        .await
+        .unwrap();
+    assert_eq!(client.lightd_info().await.unwrap().chain_name, "main");
+}
+
+#[tokio::test]
+async fn requested_servers_bypass_the_block_cache() {
+    let dir = std::env::temp_dir().join(format!("zingo-test-cache-bypass-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    // The fixture tip is 2700003, so 2700000..=2700002 are final
+    let cache = Arc::new(BlockCache::open(&dir, 100, 1).unwrap());
+    let backends = Backends::new(
+        BackendConfig::Lightwalletd {
+            url: Some(mock_mainnet().await),
+        },
+        BackendOptions {
+            upstreams: UpstreamPolicy::new(&["*".to_string()], true).unwrap(),
+            cache: Some(cache.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let configured = || backends.for_request(Network::Main, None);
+
+    let honest = configured()
+        .await
+        .unwrap()
+        .block_range(2700000, 2700003)
+        .await
+        .unwrap();
+    assert_eq!(cache.stats().blocks, 3);
+
+    // A caller's server on another branch gets its own blocks back...
+    let mut fork = fixture("mainnet.json");
+    fork.fork_at(2700001);
+    let forked_url = validate::url(&MockLightwalletd::new(fork).spawn().await).unwrap();
+    let forked = backends
+        .for_request(Network::Main, Some(&forked_url))
+        .await
+        .unwrap()
+        .block_range(2700000, 2700003)
+        .await
+        .unwrap();
+    assert_ne!(forked, honest);
+
+    // ...without touching what the configured backend serves
+    let stats = cache.stats();
+    assert_eq!((stats.blocks, stats.reorgs), (3, 0));
+    let again = configured()
+        .await
+        .unwrap()
+        .block_range(2700000, 2700003)
+        .await
+        .unwrap();
+    assert_eq!(again, honest);
+    assert_eq!(cache.stats().hits, 3);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn approved_servers_are_listed() {
+    let policy = UpstreamPolicy::new(&UpstreamPolicy::default_allowlist(), false).unwrap();
+    let request = Request::get("/api/lightwalletd-servers")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(state_with(
+        BackendConfig::Lightwalletd { url: None },
+        policy,
+    ))
+    .oneshot(request)
+    .await
+    .unwrap();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: Value = serde_json::from_slice(&bytes).unwrap();
+
+    assert_eq!(
+        body["servers"],
+        json!(["https://zec.rocks:443", "https://testnet.zec.rocks:443"])
+    );
+}
+
+/// Poll a scan job until it leaves the queue and finishes running
+async fn finished_job(state: Arc<AppState>, id: &str) -> Value {
+    loop {
+        let request = Request::get(format!("/api/scan-jobs/{}", id))
+            .body(Body::empty())
+            .unwrap();
+        let job = body_of(&send(state.clone(), request).await)["job"].clone();
+        if job["state"] != "queued" && job["state"] != "running" {
+            return job;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn scan_job_runs_in_background() {
+    let url = mock_mainnet().await;
+    let state = state(BackendConfig::Lightwalletd { url: None });
+
+    let (status, body) = post_to(
+        state.clone(),
+        "/api/scan-jobs",
+        json!({
+            "ufvk": UFVK,
+            "start_height": 2700000,
+            "end_height": 2700003,
+            "lightwalletd_url": url,
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", body);
+    assert!(body["job"].get("ufvk").is_none(), "{}", body);
+    let id = body["job"]["id"].as_str().unwrap().to_string();
+
+    let job = finished_job(state.clone(), &id).await;
+    assert_eq!(job["state"], "completed", "{}", job);
+    assert_eq!(job["scanned_height"], 2700003);
+    assert_eq!(job["blocks_scanned"], 4);
+    assert_eq!(job["total_blocks"], 4);
+
+    let request = Request::get(format!("/api/scan-jobs/{}/results", id))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(state.clone(), request).await;
+    let results = body_of(&response);
+    assert_eq!(results["state"], "completed");
+    assert_eq!(results["transactions"], json!([]));
+    assert_eq!(results["balance"]["total"], 0);
+
+    // Finished jobs can't be cancelled or resumed
+    let request = Request::delete(format!("/api/scan-jobs/{}", id))
+        .body(Body::empty())
+        .unwrap();
+    error_of(
+        &send(state.clone(), request).await,
+        StatusCode::CONFLICT,
+        "conflict",
+    );
+    let response = post_to(
+        state.clone(),
+        &format!("/api/scan-jobs/{}/resume", id),
+        json!({}),
+    )
+    .await;
+    error_of(&response, StatusCode::CONFLICT, "conflict");
+
+    let request = Request::get("/api/scan-jobs/0123456789abcdef")
+        .body(Body::empty())
+        .unwrap();
+    error_of(
+        &send(state, request).await,
+        StatusCode::NOT_FOUND,
+        "not_found",
+    );
+}
+
+#[tokio::test]
+async fn scan_job_is_checked_before_queueing() {
+    let url = mock_mainnet().await;
+    let response = post(
+        "/api/scan-jobs",
+        json!({
+            "ufvk": UFVK,
+            "start_height": 2700000,
+            "end_height": 2700004,
+            "lightwalletd_url": url,
+        }),
+    )
+    .await;
+    error_of(
+        &response,
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "beyond_chain_tip",
+    );
+}
+
+#[tokio::test]
+async fn scan_jobs_beyond_the_queue_limit_are_refused() {
+    let gate = Arc::new(Semaphore::new(0));
+    let url = MockLightwalletd::new(fixture("mainnet.json"))
+        .gate_block_ranges(gate.clone())
+        .spawn()
+        .await;
+    let mut state = state(BackendConfig::Lightwalletd { url: None });
+    Arc::get_mut(&mut state).unwrap().jobs = Arc::new(
+        JobRegistry::new(JobOptions {
+            max_concurrent: 1,
+            max_queued: 1,
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+    let submit = || {
+        post_to(
+            state.clone(),
+            "/api/scan-jobs",
+            json!({
+                "ufvk": UFVK,
+                "start_height": 2700000,
+                "end_height": 2700003,
+                "lightwalletd_url": url,
+            }),
+        )
+    };
+    let status = |id: String| {
+        let request = Request::get(format!("/api/scan-jobs/{}", id))
+            .body(Body::empty())
+            .unwrap();
+        send(state.clone(), request)
+    };
+
+    // The first job takes the only slot and waits on the gate
+    let (code, body) = submit().await;
+    assert_eq!(code, StatusCode::ACCEPTED, "{}", body);
+    let running = body["job"]["id"].as_str().unwrap().to_string();
+    while body_of(&status(running.clone()).await)["job"]["state"] != "running" {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    // The second waits in the queue, which is then full
+    let (code, body) = submit().await;
+    assert_eq!(code, StatusCode::ACCEPTED, "{}", body);
+    let queued = body["job"]["id"].as_str().unwrap().to_string();
+    let error = error_of(&submit().await, StatusCode::TOO_MANY_REQUESTS, "busy");
+    assert!(error.contains("waiting to run"), "{}", error);
+
+    gate.add_permits(1000);
+    for id in [running, queued] {
+        assert_eq!(finished_job(state.clone(), &id).await["state"], "completed");
+    }
+    let (code, body) = submit().await;
+    assert_eq!(code, StatusCode::ACCEPTED, "{}", body);
+}
+
+#[tokio::test]
+async fn finished_scan_jobs_expire_with_their_files() {
+    let dir = std::env::temp_dir().join(format!("zingo-test-expiry-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // Finished two hours, 30, 20 and 10 seconds ago
+    let ids = ["aa", "bb", "cc", "dd"].map(|byte| byte.repeat(16));
+    for (id, age) in ids.iter().zip([7200, 30, 20, 10]) {
+        let saved = json!({
+            "id": id,
+            "request": {
+                "ufvk": UFVK,
+                "start_height": 2700000,
+                "end_height": 2700003,
+                "network": null,
+                "lightwalletd_url": null,
+                "min_confirmations": null,
+            },
+            "state": "completed",
+            "scanned_height": 2700003,
+            "transactions": [],
+            "notes_found": 0,
+            "eta_secs": null,
+            "error": null,
+            "finished_at": now - age,
+        });
+        std::fs::write(dir.join(format!("{}.json", id)), saved.to_string()).unwrap();
+    }
+
+    // Kept for an hour, and at most the newest two
+    let mut state = state(BackendConfig::Lightwalletd { url: None });
+    Arc::get_mut(&mut state).unwrap().jobs = Arc::new(
+        JobRegistry::new(JobOptions {
+            max_finished: 2,
+            retention: std::time::Duration::from_secs(3600),
+            state_dir: Some(dir.clone()),
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+
+    for (id, kept) in ids.iter().zip([false, false, true, true]) {
+        let request = Request::get(format!("/api/scan-jobs/{}", id))
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(state.clone(), request).await;
+        let expected = if kept {
+            StatusCode::OK
+        } else {
+            StatusCode::NOT_FOUND
+        };
+        assert_eq!(status, expected, "{}: {}", id, body);
+        assert_eq!(dir.join(format!("{}.json", id)).exists(), kept, "{}", id);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn interrupted_scan_job_resumes_after_last_scanned_height() {
+    let url = mock_mainnet().await;
+    let dir = std::env::temp_dir().join(format!("zingo-test-jobs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Saved by an instance that stopped halfway through the scan, after
+    // finding a note at 2700000 and spending it at 2700001
+    let id = "00112233445566778899aabbccddeeff";
+    let note = |pool: &str, index: usize, value: u64, nullifier: Option<&str>| {
+        json!({
+            "pool": pool,
+            "index": index,
+            "scope": "external",
+            "value": value,
+            "nullifier": nullifier.map(|byte| byte.repeat(32)),
+        })
+    };
+    let saved = json!({
+        "id": id,
+        "request": {
+            "ufvk": UFVK,
+            "start_height": 2700000,
+            "end_height": 2700003,
+            "network": null,
+            "lightwalletd_url": url,
+            "min_confirmations": 4,
+        },
+        "state": "running",
+        "scanned_height": 2700001,
+        "transactions": [{
+            "txid": MISMATCHED_TXID,
+            "height": 2700000,
+            "amount": 4000,
+            "memo": null,
+            "timestamp": 1699999925,
+            "notes": [note("orchard", 0, 1000, Some("cc")), note("sapling", 0, 3000, None)],
+        }, {
+            "txid": TXID,
+            "height": 2700001,
+            "amount": 7000,
+            "memo": "thanks",
+            "timestamp": 1700000000,
+            "notes": [note("orchard", 0, 2000, Some("bb")), note("sapling", 0, 5000, Some("aa"))],
+        }],
+        "spends": [{ "txid": TXID, "height": 2700001, "nullifier": "cc".repeat(32) }],
+        "notes_found": 4,
+        "eta_secs": 3,
+        "error": null,
+    });
+    std::fs::write(dir.join(format!("{}.json", id)), saved.to_string()).unwrap();
+
+    let mut state = state(BackendConfig::Lightwalletd { url: None });
+    Arc::get_mut(&mut state).unwrap().jobs = Arc::new(
+        JobRegistry::new(JobOptions {
+            max_concurrent: 1,
+            state_dir: Some(dir.clone()),
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+
+    let request = Request::get(format!("/api/scan-jobs/{}", id))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(state.clone(), request).await;
+    let job = &body_of(&response)["job"];
+    assert_eq!(job["state"], "interrupted");
+    assert_eq!(job["eta_secs"], Value::Null);
+
+    let response = post_to(
+        state.clone(),
+        &format!("/api/scan-jobs/{}/resume", id),
+        json!({}),
+    )
+    .await;
+    assert_eq!(body_of(&response)["job"]["scanned_height"], 2700001);
+
+    let job = finished_job(state.clone(), id).await;
+    assert_eq!(job["state"], "completed", "{}", job);
+    assert_eq!(job["scanned_height"], 2700003);
+    assert_eq!(job["blocks_scanned"], 4);
+    assert_eq!(job["transactions_found"], 2);
+    assert_eq!(job["notes_found"], 4);
+
+    // The spent note is gone; with a tip of 2700003 only the notes at
+    // 2700000 have the four confirmations asked for
+    let request = Request::get(format!("/api/scan-jobs/{}/results", id))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(state.clone(), request).await;
+    let balance = &body_of(&response)["balance"];
+    assert_eq!(balance["confirmed"], 3000, "{}", balance);
+    assert_eq!(balance["unconfirmed"], 7000);
+    assert_eq!(balance["total"], 10000);
+    assert_eq!(
+        balance["pools"]["sapling"],
+        json!({ "confirmed": 3000, "unconfirmed": 5000, "total": 8000 })
+    );
+    assert_eq!(
+        balance["pools"]["orchard"],
+        json!({ "confirmed": 0, "unconfirmed": 2000, "total": 2000 })
+    );
+    assert_eq!(balance["pools"]["transparent"], Value::Null);
+    assert_eq!(balance["chain_tip"], 2700003);
+    assert_eq!(balance["min_confirmations"], 4);
+
+    // The finished job was saved for the next restart
+    let saved: Value =
+        serde_json::from_slice(&std::fs::read(dir.join(format!("{}.json", id))).unwrap()).unwrap();
+    assert_eq!(saved["state"], "completed");
+    assert_eq!(saved["transactions"][1]["memo"], "thanks");
+    assert_eq!(saved["spends"].as_array().unwrap().len(), 1);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,296 @@
+//! Which lightwalletd servers a request may name in `lightwalletd_url`
+//!
+//! The URL comes from the browser, so without a check the service would
+//! connect anywhere on the caller's behalf, including hosts inside our own
+//! network. Requests may only pick from the operator's allowlist, and
+//! servers that resolve to private, loopback or link-local addresses are
+//! refused unless the operator opts in. The service then connects to the
+//! addresses that were checked, so the name can't be re-pointed in between.
+
+use anyhow::anyhow;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use zcash_wasm::network::Network;
+use zcash_wasm::validate::{self, ValidUrl};
+
+use crate::error::{Result, ServiceError};
+use crate::network::default_lightwalletd_url;
+
+/// Allowlist entry that admits any server (still subject to the private
+/// address check)
+const ANY_SERVER: &str = "*";
+
+#[derive(Debug, Clone)]
+pub struct UpstreamPolicy {
+    allowed: Vec<ValidUrl>,
+    allow_any: bool,
+    /// Admit servers on private, loopback and link-local addresses
+    allow_private: bool,
+}
+
+impl UpstreamPolicy {
+    /// Admit the given servers (`*` for any) and, if `allow_private`,
+    /// servers on internal addresses
+    pub fn new(allowed: &[String], allow_private: bool) -> anyhow::Result<Self> {
+        let mut policy = UpstreamPolicy {
+            allowed: Vec::new(),
+            allow_any: false,
+            allow_private,
+        };
+        for entry in allowed {
+            if entry.trim() == ANY_SERVER {
+                policy.allow_any = true;
+            } else {
+                let url = validate::url(entry)
+                    .map_err(|e| anyhow!("Upstream allowlist entry '{}': {}", entry, e))?;
+                policy.allowed.push(url);
+            }
+        }
+        Ok(policy)
+    }
+
+    /// The public servers the service uses by default
+    pub fn default_allowlist() -> Vec<String> {
+        [Network::Main, Network::Test]
+            .into_iter()
+            .map(|network| default_lightwalletd_url(network).to_string())
+            .collect()
+    }
+
+    /// Approved servers, for clients to choose from
+    pub fn servers(&self) -> Vec<String> {
+        let mut servers: Vec<String> = self.allowed.iter().map(|url| url.url.clone()).collect();
+        if self.allow_any {
+            servers.push(ANY_SERVER.to_string());
+        }
+        servers
+    }
+
+    /// Fail with `UpstreamNotAllowed` unless a request may use `url`, and
+    /// return the addresses it was approved at
+    ///
+    /// Callers must connect to these rather than resolve the host again: a
+    /// name with a short TTL could answer with an internal address the
+    /// second time.
+    pub async fn check(&self, url: &ValidUrl) -> Result<Vec<SocketAddr>> {
+        if !self.allow_any && !self.allowed.iter().any(|allowed| same_server(allowed, url)) {
+            return Err(ServiceError::UpstreamNotAllowed(format!(
+                "{} is not an approved lightwalletd server; choose one of: {}",
+                url.url,
+                self.servers().join(", ")
+            )));
+        }
+
+        // Check every address the name resolves to, not just literals, so a
+        // public-looking name can't point inside the network
+        let addresses: Vec<SocketAddr> = tokio::net::lookup_host((url.host.as_str(), url.port))
+            .await
+            .map_err(|e| {
+                ServiceError::UpstreamUnavailable(format!("Failed to resolve {}: {}", url.host, e))
+            })?
+            .collect();
+        if addresses.is_empty() {
+            return Err(ServiceError::UpstreamUnavailable(format!(
+                "{} resolves to no addresses",
+                url.host
+            )));
+        }
+        if !self.allow_private {
+            if let Some(address) = addresses.iter().find(|address| is_internal(address.ip())) {
+                return Err(ServiceError::UpstreamNotAllowed(format!(
+                    "{} resolves to the internal address {}",
+                    url.url,
+                    address.ip()
+                )));
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+impl Default for UpstreamPolicy {
+    /// The public mainnet and testnet servers, nothing internal
+    fn default() -> Self {
+        UpstreamPolicy::new(&UpstreamPolicy::default_allowlist(), false)
+            .expect("Default lightwalletd URLs are valid")
+    }
+}
+
+fn same_server(a: &ValidUrl, b: &ValidUrl) -> bool {
+    a.https == b.https && a.host == b.host && a.port == b.port
+}
+
+/// Addresses that aren't reachable from the public internet
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_v4(ip),
+        IpAddr::V6(ip) => is_internal_v6(ip),
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network" 0.0.0.0/8, carrier-grade NAT 100.64.0.0/10,
+        // benchmarking 198.18.0.0/15, and reserved 240.0.0.0/4 including
+        // broadcast
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 198 && (b == 18 || b == 19))
+        || a >= 240
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    let v4 = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+    // IPv4-mapped ::ffff:a.b.c.d, IPv4-compatible ::a.b.c.d and NAT64
+    // 64:ff9b::a.b.c.d reach the IPv4 address they embed, as do 6to4
+    // 2002:aabb:ccdd:: and Teredo 2001:0:server:...:!client through a relay
+    let embedded = match segments {
+        [0, 0, 0, 0, 0, 0xffff, ..] | [0, 0, 0, 0, 0, 0, ..] => ip.to_ipv4().into_iter().collect(),
+        [0x64, 0xff9b, 0, 0, 0, 0, ..] => vec![v4(segments[6], segments[7])],
+        [0x2002, ..] => vec![v4(segments[1], segments[2])],
+        [0x2001, 0, ..] => vec![v4(segments[2], segments[3]), v4(!segments[6], !segments[7])],
+        _ => vec![],
+    };
+    if !embedded.is_empty() {
+        // Loopback ::1 and unspecified :: land in 0.0.0.0/8
+        return embedded.into_iter().any(is_internal_v4);
+    }
+
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        // Deprecated site-local fec0::/10 and local-use NAT64 64:ff9b:1::/48
+        || segments[0] & 0xffc0 == 0xfec0
+        || segments[..3] == [0x64, 0xff9b, 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn internal(ip: &str) -> bool {
+        is_internal(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn public_addresses_are_external() {
+        for ip in [
+            "1.1.1.1",
+            "100.63.255.255",
+            "100.128.0.0",
+            "198.17.255.255",
+            "198.20.0.0",
+            "223.255.255.255",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+            "64:ff9b::101:101",
+        ] {
+            assert!(!internal(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn private_and_special_ipv4_ranges_are_internal() {
+        for ip in [
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "127.0.0.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "0.1.2.3",
+            "192.0.2.1",
+        ] {
+            assert!(internal(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn carrier_grade_nat_is_internal() {
+        assert!(internal("100.64.0.1"));
+        assert!(internal("100.127.255.255"));
+    }
+
+    #[test]
+    fn benchmarking_range_is_internal() {
+        assert!(internal("198.18.0.1"));
+        assert!(internal("198.19.255.255"));
+    }
+
+    #[test]
+    fn ipv4_multicast_is_internal() {
+        assert!(internal("224.0.0.1"));
+        assert!(internal("239.255.255.255"));
+    }
+
+    #[test]
+    fn reserved_ipv4_range_is_internal() {
+        assert!(internal("240.0.0.1"));
+        assert!(internal("255.255.255.255"));
+    }
+
+    #[test]
+    fn private_and_special_ipv6_ranges_are_internal() {
+        for ip in ["::1", "::", "fd00::1", "fe80::1"] {
+            assert!(internal(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn ipv6_multicast_is_internal() {
+        assert!(internal("ff02::1"));
+        assert!(internal("ff0e::1"));
+    }
+
+    #[test]
+    fn site_local_ipv6_is_internal() {
+        assert!(internal("fec0::1"));
+        assert!(internal("feff::1"));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_follow_the_ipv4_address() {
+        assert!(internal("::ffff:127.0.0.1"));
+        assert!(internal("::ffff:10.1.2.3"));
+    }
+
+    #[test]
+    fn ipv4_compatible_addresses_follow_the_ipv4_address() {
+        assert!(internal("::127.0.0.1"));
+        assert!(internal("::192.168.0.1"));
+        assert!(!internal("::1.1.1.1"));
+    }
+
+    #[test]
+    fn six_to_four_addresses_follow_the_ipv4_address() {
+        assert!(internal("2002:7f00:0001::"));
+        assert!(internal("2002:a9fe:a9fe::1"));
+        assert!(internal("2002:c0a8:0101::"));
+        assert!(!internal("2002:0101:0101::1"));
+    }
+
+    #[test]
+    fn teredo_addresses_follow_the_ipv4_addresses() {
+        // Server 65.54.227.120, client 1.1.1.1 (stored inverted)
+        assert!(!internal("2001:0:4136:e378:8000:63bf:fefe:fefe"));
+        // Client 127.0.0.1
+        assert!(internal("2001:0:4136:e378:8000:63bf:80ff:fffe"));
+        // Server 10.0.0.1
+        assert!(internal("2001:0:a00:1:8000:63bf:fefe:fefe"));
+    }
+
+    #[test]
+    fn nat64_addresses_follow_the_ipv4_address() {
+        assert!(internal("64:ff9b::7f00:1"));
+        assert!(internal("64:ff9b::a9fe:a9fe"));
+        assert!(internal("64:ff9b:1::1"));
+    }
+}